@@ -0,0 +1,2382 @@
+//! JSON endpoints for managing timers programmatically, e.g. from scripts or home automation
+use crate::{
+    audit::AuditAction,
+    auth::AuthUser,
+    handlers::{clamp_duration, DurationUnit, NewDaily, OutputRef, PageParams, TimerFilter},
+    util::{format_duration, format_time_of_day, parse_time_of_day, run_output, AppState},
+    Error, IntervalSettings, IntervalTimer, Schedule,
+};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListParams {
+    /// Maximum number of timers to return
+    pub limit: Option<usize>,
+    /// Number of timers to skip
+    pub offset: Option<usize>,
+    /// Page to return, starting at 1. Takes precedence over `limit` and `offset`.
+    pub page: Option<usize>,
+    /// Timers per page, at most `MAX_PER_PAGE`
+    pub per_page: Option<usize>,
+}
+
+/// A timer along with when it will next run, as returned by the API. Its durations are whole
+/// numbers of `duration_unit` rather than the seconds and nanoseconds it's stored with.
+#[derive(Debug, Serialize)]
+pub struct TimerView {
+    #[serde(flatten)]
+    pub timer: serde_json::Value,
+    /// Seconds until the timer next turns on, if it has a fixed start time
+    pub next_fire_secs: Option<i64>,
+    /// How long the output stays on, e.g. "1 h 30 min"
+    pub duration_human: String,
+    /// The unit of the timer's durations
+    pub duration_unit: DurationUnit,
+}
+
+impl TimerView {
+    pub fn new(timer: &IntervalTimer, unit: DurationUnit) -> Result<TimerView, Error> {
+        let mut value = serde_json::to_value(timer)?;
+        if let Some(settings) = value.get_mut("settings") {
+            durations_in_unit(settings, unit);
+        }
+        Ok(TimerView {
+            timer: value,
+            next_fire_secs: timer.time_until_next_fire().map(|d| d.num_seconds()),
+            duration_human: format_duration(timer.settings.duration_on),
+            duration_unit: unit,
+        })
+    }
+}
+
+/// Replace each duration in `value`, which serde writes as an object of `secs` and `nanos`,
+/// with a whole number of `unit`
+fn durations_in_unit(value: &mut serde_json::Value, unit: DurationUnit) {
+    use serde_json::Value;
+    let secs = match &*value {
+        Value::Object(fields) if fields.len() == 2 && fields.contains_key("nanos") => {
+            fields.get("secs").and_then(Value::as_u64)
+        }
+        _ => None,
+    };
+    if let Some(secs) = secs {
+        *value = unit.value(std::time::Duration::from_secs(secs)).into();
+        return;
+    }
+    match value {
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|field| durations_in_unit(field, unit)),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| durations_in_unit(item, unit)),
+        _ => {}
+    }
+}
+
+/// Respond with a timer serialized as JSON, with durations in `unit`
+fn timer_json(
+    status: StatusCode,
+    timer: &IntervalTimer,
+    unit: DurationUnit,
+) -> Result<Response, Error> {
+    Ok((
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_vec(&TimerView::new(timer, unit)?)?,
+    )
+        .into_response())
+}
+
+#[axum::debug_handler]
+pub async fn create_timer(
+    State(state): State<AppState>,
+    user: AuthUser,
+    n: Result<Json<NewDaily>, JsonRejection>,
+) -> Result<Response, Error> {
+    let Json(mut n) = n?;
+    state.fill_new_daily(&mut n);
+    let timer = IntervalTimer::from_newdaily(n)?;
+    state.check_pin(timer.output)?;
+    let prev = state.insert_interval_timer(&timer)?;
+    info!(
+        "Inserted timer {:?} into the database. Previous value: {:?}",
+        &timer, &prev
+    );
+    state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+    state.spawn_interval_timer(&timer);
+    created_json(&timer, state.duration_unit)
+}
+
+/// Respond with 201, the new timer as JSON and its page's URL in the `Location` header
+pub(crate) fn created_json(timer: &IntervalTimer, unit: DurationUnit) -> Result<Response, Error> {
+    let mut response = timer_json(StatusCode::CREATED, timer, unit)?;
+    if let Ok(location) = HeaderValue::from_str(&format!("/timer/{}", timer.get_id())) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    Ok(response)
+}
+
+/// Create many timers at once, e.g. when setting up every zone of a new system. Nothing is
+/// stored unless every timer is valid.
+#[axum::debug_handler]
+pub async fn create_timers(
+    State(state): State<AppState>,
+    user: AuthUser,
+    items: Result<Json<Vec<NewDaily>>, JsonRejection>,
+) -> Result<(StatusCode, Json<Vec<Uuid>>), Error> {
+    let Json(items) = items?;
+    let mut timers = Vec::with_capacity(items.len());
+    let mut problems = Vec::new();
+    for (i, mut n) in items.into_iter().enumerate() {
+        state.fill_new_daily(&mut n);
+        let timer = IntervalTimer::from_newdaily(n)
+            .and_then(|timer| state.check_pin(timer.output).map(|_| timer));
+        match timer {
+            Ok(timer) => timers.push(timer),
+            Err(Error::Validation(fields)) => {
+                problems.extend(fields.iter().map(|field| format!("{}: {}", i, field)))
+            }
+            Err(e) => problems.push(format!("{}: {}", i, e)),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(Error::Validation(problems));
+    }
+    state.insert_interval_timers(&timers)?;
+    info!("Inserted {} timers into the database.", timers.len());
+    for timer in &timers {
+        state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+        state.spawn_interval_timer(timer);
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(timers.iter().map(IntervalTimer::get_id).collect()),
+    ))
+}
+
+#[axum::debug_handler]
+pub async fn list_timers(
+    State(state): State<AppState>,
+    Query(params): Query<ListParams>,
+    Query(filter): Query<TimerFilter>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let (offset, limit) = if params.page.is_some() || params.per_page.is_some() {
+        let page = PageParams {
+            page: params.page,
+            per_page: params.per_page,
+        };
+        (page.offset(), page.per_page())
+    } else {
+        (
+            params.offset.unwrap_or_default(),
+            params.limit.unwrap_or(usize::MAX),
+        )
+    };
+    let (timers, total) = state.find_interval_timers(&filter, offset, limit)?;
+    let stored = timers
+        .iter()
+        .map(IntervalTimer::to_json_vec)
+        .collect::<Result<Vec<_>, _>>()?;
+    let etag = etag_of((stored, total, state.duration_unit));
+    not_modified_or(&headers, &etag, || {
+        let views = timers
+            .iter()
+            .map(|timer| TimerView::new(timer, state.duration_unit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let body = serde_json::to_vec(&views)?;
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/json".to_owned()),
+                (
+                    header::HeaderName::from_static("x-total-count"),
+                    total.to_string(),
+                ),
+            ],
+            body,
+        ))
+    })
+}
+
+/// An ETag hashed from what a response is made of. Listings hash the stored timers rather than
+/// the response, which would change every second with `next_fire_secs`.
+pub(crate) fn etag_of(parts: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    parts.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// An ETag for a timer, hashed from its stored JSON. It changes when the timer is changed,
+/// but not when its next run moves on.
+fn timer_etag(timer: &IntervalTimer) -> Result<String, Error> {
+    Ok(etag_of(timer.to_json_vec()?))
+}
+
+/// Whether an `If-None-Match` header lists the given ETag, comparing weakly as RFC 9110 says
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// `304 Not Modified` if `If-None-Match` lists `etag`, or else the response `respond` makes,
+/// either way carrying the ETag
+pub(crate) fn not_modified_or<R: IntoResponse>(
+    headers: &HeaderMap,
+    etag: &str,
+    respond: impl FnOnce() -> Result<R, Error>,
+) -> Result<Response, Error> {
+    let mut response = match etag_matches(headers, etag) {
+        true => StatusCode::NOT_MODIFIED.into_response(),
+        false => respond()?.into_response(),
+    };
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    Ok(response)
+}
+
+/// Get a timer, or `304 Not Modified` if it has the ETag given in `If-None-Match`, so that
+/// dashboards polling it don't download it again when nothing has changed
+#[axum::debug_handler]
+pub async fn get_timer(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let Path(id) = id?;
+    let Some(timer) = state.get_interval_timer(id)? else {
+        return Err(Error::NotFound(format!("Timer with ID {}", &id)));
+    };
+    not_modified_or(&headers, &timer_etag(&timer)?, || {
+        timer_json(StatusCode::OK, &timer, state.duration_unit)
+    })
+}
+
+/// Fields to change on an existing timer. Missing fields are left as they are.
+#[derive(Debug, Default, Deserialize)]
+pub struct TimerPatch {
+    pub name: Option<String>,
+    /// An empty description, e.g. from a cleared textarea, removes it
+    pub description: Option<String>,
+    /// Duration in `duration_unit`
+    pub duration_on: Option<u32>,
+    /// The unit `duration_on` is in, which is the server's configured unit rather than
+    /// anything submitted
+    #[serde(skip)]
+    pub duration_unit: DurationUnit,
+    /// The GPIO output pin to control, by number or by one of the configured aliases
+    pub output: Option<OutputRef>,
+    /// The alias `output` was given as, once it has been resolved to a pin
+    #[serde(skip)]
+    pub output_alias: Option<String>,
+    /// Time of day to run, in %H:%M or %H:%M:%S format
+    pub start_time: Option<String>,
+    /// IANA timezone name, or an empty string for the server's local timezone
+    pub timezone: Option<String>,
+    pub enabled: Option<bool>,
+    pub normally_on: Option<bool>,
+    pub active_low: Option<bool>,
+}
+
+impl TimerPatch {
+    /// Bring a given `duration_on` within the allowed range rather than rejecting it
+    pub fn clamp_duration(&mut self) {
+        if let Some(value) = self.duration_on {
+            let clamped = clamp_duration(value, self.duration_unit);
+            if clamped != value {
+                warn!(
+                    "Clamped duration from {} to {} {}",
+                    value,
+                    clamped,
+                    self.duration_unit.name()
+                );
+                self.duration_on = Some(clamped);
+            }
+        }
+    }
+
+    /// Apply the present fields to `timer`, returning whether anything affecting its schedule
+    /// changed. Nothing is changed if any field is invalid.
+    pub fn apply(self, timer: &mut IntervalTimer) -> Result<bool, Error> {
+        let mut problems = Vec::new();
+        if self
+            .name
+            .as_ref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            problems.push("name: must not be empty".to_owned());
+        }
+        let unit = self.duration_unit;
+        let settings = match self.duration_on {
+            Some(value) if !(1..=unit.max_duration()).contains(&value) => {
+                problems.push(format!(
+                    "duration_on: must be between 1 and {} {}",
+                    unit.max_duration(),
+                    unit.name()
+                ));
+                None
+            }
+            Some(value) => match timer.settings.with_duration_on(unit.duration(value)) {
+                Ok(settings) => Some(settings),
+                Err(e) => {
+                    problems.push(format!("duration_on: {}", e));
+                    None
+                }
+            },
+            None => None,
+        };
+        let output = self.output.as_ref().map(|output| {
+            match (output, output.pin()) {
+                (_, Some(0)) => problems.push("output: must not be 0".to_owned()),
+                (OutputRef::Alias(alias), None) => {
+                    problems.push(format!("output: unknown pin alias {:?}", alias.trim()))
+                }
+                _ => {}
+            }
+            output.pin().unwrap_or_default()
+        });
+        let fixed_start = matches!(
+            timer.settings.schedule,
+            Schedule::Daily | Schedule::Weekly(_)
+        );
+        let start_time = match self.start_time.as_deref() {
+            Some(_) if !fixed_start => {
+                problems.push("start_time: only daily and weekly timers have one".to_owned());
+                None
+            }
+            Some(time) => match parse_time_of_day(time) {
+                Ok(time) => Some(time),
+                Err(_) => {
+                    problems.push("start_time: must be in HH:MM or HH:MM:SS format".to_owned());
+                    None
+                }
+            },
+            None => None,
+        };
+        let tz = match self.timezone.as_deref().map(str::trim) {
+            Some("") => Some(None),
+            Some(name) => match Tz::from_str(name) {
+                Ok(tz) => Some(Some(tz)),
+                Err(_) => {
+                    problems.push(format!("timezone: unknown timezone {}", name));
+                    None
+                }
+            },
+            None => None,
+        };
+        if !problems.is_empty() {
+            return Err(Error::Validation(problems));
+        }
+
+        if let Some(name) = self.name {
+            timer.name = Some(name.trim().to_owned());
+        }
+        if let Some(description) = self.description {
+            timer.description = Some(description).filter(|text| !text.trim().is_empty());
+        }
+        let mut rescheduled = false;
+        if let Some(settings) = settings {
+            timer.settings = settings;
+            rescheduled = true;
+        }
+        if let Some(output) = output {
+            timer.output = output;
+            timer.output_alias = self.output_alias;
+            rescheduled = true;
+        }
+        if let Some(start_time) = start_time {
+            timer.settings.start_time = Some(start_time);
+            rescheduled = true;
+        }
+        if let Some(tz) = tz {
+            timer.set_timezone(tz);
+            rescheduled = true;
+        }
+        if let Some(enabled) = self.enabled {
+            timer.enabled = enabled;
+            rescheduled = true;
+        }
+        if let Some(normally_on) = self.normally_on {
+            timer.normally_on = normally_on;
+            rescheduled = true;
+        }
+        if let Some(active_low) = self.active_low {
+            timer.active_low = active_low;
+            rescheduled = true;
+        }
+        timer.updated_at = Local::now();
+        Ok(rescheduled)
+    }
+}
+
+/// Change only the given fields of a timer, rescheduling it if its timing changed
+#[axum::debug_handler]
+pub async fn patch_timer(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+    user: AuthUser,
+    patch: Result<Json<TimerPatch>, JsonRejection>,
+) -> Result<Response, Error> {
+    let Path(id) = id?;
+    let Json(mut patch) = patch?;
+    patch.duration_unit = state.duration_unit;
+    if let Some(output) = &mut patch.output {
+        patch.output_alias = state.resolve_output(output);
+    }
+    if state.clamp_durations {
+        patch.clamp_duration();
+    }
+    let mut timer = state
+        .get_interval_timer(id)?
+        .ok_or_else(|| Error::NotFound(format!("Timer with ID {}", &id)))?;
+    let rescheduled = patch.apply(&mut timer)?;
+    state.check_pin(timer.output)?;
+    state.insert_interval_timer(&timer)?;
+    info!("Patched timer {:?}", &timer);
+    state.audit(AuditAction::Update, Some(id), &user);
+    if rescheduled {
+        state.spawn_interval_timer(&timer);
+    }
+    timer_json(StatusCode::OK, &timer, state.duration_unit)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RunParams {
+    /// How long to turn the output on for, in the server's `duration_unit`. Defaults to the
+    /// timer's duration.
+    pub duration: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunResponse {
+    pub id: Uuid,
+    pub output: u16,
+    /// When the output will be turned back off
+    pub off_at: DateTime<Local>,
+}
+
+/// Turn a timer's output on right away, after which its schedule carries on as normal
+#[axum::debug_handler]
+pub async fn run_timer_now(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<RunParams>,
+) -> Result<Json<RunResponse>, Error> {
+    let Path(id) = id?;
+    let timer = state
+        .get_interval_timer(id)?
+        .ok_or_else(|| Error::NotFound(format!("Timer with ID {}", &id)))?;
+    let duration = match params.duration {
+        Some(value) => state.duration_unit.duration(value),
+        None => timer.settings.duration_on,
+    };
+    if duration.is_zero() {
+        return Err(Error::InvalidDuration);
+    }
+    state.check_gpio_capacity()?;
+    let off_at =
+        Local::now() + chrono::Duration::from_std(duration).map_err(|e| Error::Anyhow(e.into()))?;
+    info!("Manually running timer {} for {:?}.", id, duration);
+    state.audit(AuditAction::Fire, Some(id), &user);
+    state.run_now(&timer, duration);
+    Ok(Json(RunResponse {
+        id,
+        output: timer.output,
+        off_at,
+    }))
+}
+
+/// Create a copy of a timer with a new id and start running it, e.g. to set up a similar zone
+#[axum::debug_handler]
+pub async fn duplicate_timer(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Response, Error> {
+    let Path(id) = id?;
+    let source = state
+        .get_interval_timer(id)?
+        .ok_or_else(|| Error::NotFound(format!("Timer with ID {}", &id)))?;
+    let timer = source.duplicate();
+    state.insert_interval_timer(&timer)?;
+    info!("Duplicated timer {} as {}.", id, timer.get_id());
+    state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+    state.spawn_interval_timer(&timer);
+    created_json(&timer, state.duration_unit)
+}
+
+/// Whether a timer is part way through a run
+#[derive(Debug, Serialize)]
+pub struct TimerActivity {
+    pub active: bool,
+    /// When the run ends, in the timer's timezone
+    pub until: Option<NaiveTime>,
+}
+
+/// Whether a timer is in one of its runs right now going by its schedule rather than the
+/// output's state, e.g. for clients which can't read pins. Disabled timers are never active.
+#[axum::debug_handler]
+pub async fn timer_active(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<TimerActivity>, Error> {
+    let Path(id) = id?;
+    let timer = state
+        .get_interval_timer(id)?
+        .ok_or_else(|| Error::NotFound(format!("Timer with ID {}", &id)))?;
+    let until = match timer.enabled {
+        true => timer.active_until(state.clock.now(), state.start_stagger),
+        false => None,
+    };
+    let until = until.map(|end| match timer.settings().timezone() {
+        Some(tz) => end.with_timezone(&tz).time(),
+        None => end.with_timezone(&Local).time(),
+    });
+    Ok(Json(TimerActivity {
+        active: until.is_some(),
+        until,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct RescheduleResponse {
+    /// Timers whose tasks were started again
+    pub rescheduled: usize,
+}
+
+/// Restart every timer's task from the database without restarting the process
+#[axum::debug_handler]
+pub async fn reschedule_timers(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<RescheduleResponse>, Error> {
+    let rescheduled = state.reschedule_all()?;
+    info!("Rescheduled {} timers.", rescheduled);
+    state.audit(AuditAction::Reschedule, None, &user);
+    Ok(Json(RescheduleResponse { rescheduled }))
+}
+
+/// Hours ahead `GET /api/schedule` lists runs for by default
+pub const DEFAULT_SCHEDULE_HOURS: u32 = 24;
+
+/// The most hours ahead `GET /api/schedule` will list runs for, so a request can't make it
+/// expand schedules indefinitely
+pub const MAX_SCHEDULE_HOURS: u32 = 7 * 24;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ScheduleParams {
+    /// Hours ahead to list runs for, at most `MAX_SCHEDULE_HOURS`
+    pub hours: Option<u32>,
+}
+
+/// A run of a timer which is coming up
+#[derive(Debug, Serialize)]
+pub struct ScheduledRun {
+    pub timer_id: Uuid,
+    pub name: Option<String>,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+}
+
+/// Every run of an enabled timer starting within the next few hours, soonest first, e.g. for
+/// a view of today's watering. Timers without fixed start times aren't listed.
+#[axum::debug_handler]
+pub async fn schedule(
+    State(state): State<AppState>,
+    Query(params): Query<ScheduleParams>,
+) -> Result<Json<Vec<ScheduledRun>>, Error> {
+    let hours = params.hours.unwrap_or(DEFAULT_SCHEDULE_HOURS);
+    if hours == 0 || hours > MAX_SCHEDULE_HOURS {
+        return Err(Error::Validation(vec![format!(
+            "hours: must be between 1 and {}",
+            MAX_SCHEDULE_HOURS
+        )]));
+    }
+    let from = state.clock.now();
+    let to = from + chrono::Duration::hours(hours.into());
+    let mut runs = Vec::new();
+    for timer in state.get_all_interval_timers()? {
+        if !timer.enabled {
+            continue;
+        }
+        for (start, end) in timer.runs_between(from, to, state.start_stagger) {
+            runs.push(ScheduledRun {
+                timer_id: timer.get_id(),
+                name: timer.name.clone(),
+                start: start.with_timezone(&Local),
+                end: end.with_timezone(&Local),
+            });
+        }
+    }
+    runs.sort_by_key(|run| (run.start, run.timer_id));
+    Ok(Json(runs))
+}
+
+/// The server's view of the time, for working out why a timer ran at an unexpected hour
+#[derive(Debug, Serialize)]
+pub struct ServerTime {
+    pub utc: DateTime<Utc>,
+    /// The same instant in the server's local timezone
+    pub local: DateTime<Local>,
+    /// Seconds east of UTC of the server's local timezone
+    pub local_offset_secs: i32,
+    /// IANA name of the timezone used for timers which don't specify one, or `None` if they use
+    /// the server's local timezone
+    pub timezone: Option<String>,
+    /// The same instant in `timezone`, if set
+    pub timezone_time: Option<NaiveDateTime>,
+    /// Seconds since the controller started, measured with the monotonic clock
+    pub uptime_secs: u64,
+}
+
+/// The current time as the server sees it
+#[axum::debug_handler]
+pub async fn server_time(State(state): State<AppState>) -> Json<ServerTime> {
+    let utc = state.clock.now();
+    let local = utc.with_timezone(&Local);
+    Json(ServerTime {
+        utc,
+        local,
+        local_offset_secs: local.offset().local_minus_utc(),
+        timezone: state.default_tz.map(|tz| tz.name().to_owned()),
+        timezone_time: state
+            .default_tz
+            .map(|tz| utc.with_timezone(&tz).naive_local()),
+        uptime_secs: state.started.elapsed().as_secs(),
+    })
+}
+
+/// The longest a pin may be test-fired for, so a forgotten test doesn't leave a valve open
+pub const MAX_TEST_FIRE_SECS: u32 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct TestFire {
+    pub output: u16,
+    /// How long to turn the output on for, in seconds
+    pub seconds: u32,
+    /// Whether the output's relay turns the load on when driven low. Defaults to the polarity
+    /// of the timers controlling the output.
+    #[serde(default)]
+    pub active_low: Option<bool>,
+}
+
+/// Turn an output on for a few seconds without saving a timer, e.g. to check a relay's wiring.
+/// Responds once the output has been turned back off.
+#[axum::debug_handler]
+pub async fn test_fire(
+    State(state): State<AppState>,
+    user: AuthUser,
+    body: Result<Json<TestFire>, JsonRejection>,
+) -> Result<StatusCode, Error> {
+    let Json(TestFire {
+        output,
+        seconds,
+        active_low,
+    }) = body?;
+    state.check_pin(output)?;
+    if seconds == 0 || seconds > MAX_TEST_FIRE_SECS {
+        return Err(Error::Validation(vec![format!(
+            "seconds: must be between 1 and {}",
+            MAX_TEST_FIRE_SECS
+        )]));
+    }
+    state.check_gpio_capacity()?;
+    info!("Test-firing output {} for {} seconds.", output, seconds);
+    state.audit(AuditAction::Fire, None, &user);
+    let active_low = match active_low {
+        Some(active_low) => active_low,
+        None => state.output_active_low(output)?,
+    };
+    run_output(
+        state.gpio_tx.clone(),
+        output,
+        true,
+        active_low,
+        chrono::Duration::seconds(seconds.into()),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The version of the backup format written by `export_timers`
+pub const BACKUP_VERSION: u32 = 1;
+
+/// A backup of every timer in the database
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub timers: Vec<IntervalTimer>,
+}
+
+/// Download every timer as a single JSON document, e.g. for backups or moving to a new device
+#[axum::debug_handler]
+pub async fn export_timers(State(state): State<AppState>) -> Result<Response, Error> {
+    let backup = Backup {
+        version: BACKUP_VERSION,
+        timers: state.get_all_interval_timers()?,
+    };
+    info!("Exporting {} timers.", backup.timers.len());
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"sploosh-backup.json\"",
+            ),
+        ],
+        serde_json::to_vec_pretty(&backup)?,
+    )
+        .into_response())
+}
+
+/// The columns of the CSV export, which the CSV import reads back
+const CSV_COLUMNS: [&str; 12] = [
+    "id",
+    "name",
+    "description",
+    "output",
+    "start_time",
+    "duration_on",
+    "enabled",
+    "timezone",
+    "normally_on",
+    "active_low",
+    "schedule",
+    "duration_off",
+];
+
+/// Columns a CSV import may leave out, taking the defaults for a new daily timer
+const OPTIONAL_CSV_COLUMNS: [&str; 8] = [
+    "id",
+    "description",
+    "enabled",
+    "timezone",
+    "normally_on",
+    "active_low",
+    "schedule",
+    "duration_off",
+];
+
+/// Quote a CSV field if it contains a comma, quote or line break, doubling any quotes
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Download every timer as CSV, one row each, e.g. for managing zones in a spreadsheet.
+/// Durations are whole numbers of the configured duration unit. Schedules other than daily
+/// are written by name, or as JSON if they have fields, and the off duration only for
+/// cycling timers.
+#[axum::debug_handler]
+pub async fn export_csv(State(state): State<AppState>) -> Result<Response, Error> {
+    let timers = state.get_all_interval_timers()?;
+    info!("Exporting {} timers as CSV.", timers.len());
+    let mut csv = CSV_COLUMNS.join(",") + "\r\n";
+    let unit = state.duration_unit;
+    for timer in &timers {
+        let settings = timer.settings();
+        let row = [
+            timer.get_id().to_string(),
+            csv_field(timer.name.as_deref().unwrap_or_default()),
+            csv_field(timer.description.as_deref().unwrap_or_default()),
+            timer.output.to_string(),
+            settings
+                .start_time()
+                .map(format_time_of_day)
+                .unwrap_or_default(),
+            unit.value(settings.duration_on()).to_string(),
+            timer.enabled.to_string(),
+            settings
+                .timezone()
+                .map(|tz| tz.name().to_owned())
+                .unwrap_or_default(),
+            timer.normally_on.to_string(),
+            timer.active_low.to_string(),
+            match settings.schedule() {
+                Schedule::Daily => String::new(),
+                schedule => match serde_json::to_value(schedule)? {
+                    serde_json::Value::String(name) => name,
+                    fields => csv_field(&fields.to_string()),
+                },
+            },
+            match settings.schedule() {
+                Schedule::Cycling => unit.value(settings.duration_off()).to_string(),
+                _ => String::new(),
+            },
+        ];
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"sploosh-timers.csv\"",
+            ),
+        ],
+        csv,
+    )
+        .into_response())
+}
+
+/// What to do with timers in a backup whose id is already in the database
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep the existing timer and skip the imported one
+    #[default]
+    Skip,
+    /// Replace the existing timer with the imported one
+    Overwrite,
+    /// Import every timer under a new id, leaving existing ones untouched
+    Regenerate,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub skipped: usize,
+    /// Rows of a CSV import which couldn't be imported, with their line numbers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Restore timers from a document produced by `export_timers`
+#[axum::debug_handler]
+pub async fn import_timers(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<ImportParams>,
+    backup: Result<Json<Backup>, JsonRejection>,
+) -> Result<Json<ImportSummary>, Error> {
+    let Json(backup) = backup?;
+    if backup.version != BACKUP_VERSION {
+        return Err(Error::UnsupportedVersion(backup.version));
+    }
+    // Validate everything up front so that a bad backup isn't partially imported
+    for timer in &backup.timers {
+        state.check_pin(timer.output)?;
+    }
+    let mut summary = ImportSummary::default();
+    for mut timer in backup.timers {
+        match params.mode {
+            ImportMode::Skip => {
+                let existing = state.get_interval_timer(timer.id)?;
+                if existing.is_some() {
+                    summary.skipped += 1;
+                    continue;
+                }
+            }
+            ImportMode::Overwrite => {}
+            ImportMode::Regenerate => timer.id = Uuid::new_v4(),
+        }
+        state.replace_interval_timer(&timer)?;
+        state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+        state.spawn_interval_timer(&timer);
+        summary.inserted += 1;
+    }
+    info!(
+        "Imported {} timers, skipped {}.",
+        summary.inserted, summary.skipped
+    );
+    Ok(Json(summary))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CsvImportParams {
+    #[serde(default)]
+    pub mode: ImportMode,
+    /// Import nothing if any row is invalid, rather than importing the valid rows
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// Split CSV text into records of fields, each with the line it starts on. Quoted fields may
+/// contain commas, doubled quotes and line breaks. Blank lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, Error> {
+    let mut records = Vec::new();
+    let (mut record, mut field) = (Vec::new(), String::new());
+    let (mut line, mut start_line) = (1, 1);
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push((start_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(Error::BadRequest(format!(
+            "line {}: unterminated quoted field",
+            start_line
+        )));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start_line, record));
+    }
+    Ok(records)
+}
+
+/// Parse an optional true or false column of a CSV import
+fn csv_bool(column: &str, value: &str) -> Result<Option<bool>, String> {
+    match value {
+        "" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{}: {:?} is not true or false", column, value)),
+    }
+}
+
+/// Build a timer from one row of a CSV import. Rows without an id get a new one, and rows
+/// without a schedule are daily.
+fn timer_from_csv_row(
+    state: &AppState,
+    columns: &[Option<usize>],
+    row: &[String],
+) -> Result<IntervalTimer, String> {
+    let field = |column: &str| {
+        CSV_COLUMNS
+            .iter()
+            .position(|c| *c == column)
+            .and_then(|i| columns[i])
+            .and_then(|i| row.get(i))
+            .map(|value| value.trim())
+            .unwrap_or_default()
+    };
+    let unit = state.duration_unit;
+    let duration = |column: &str| {
+        field(column).parse().map_err(|_| {
+            format!(
+                "{}: {:?} is not a whole number of {}",
+                column,
+                field(column),
+                unit.name()
+            )
+        })
+    };
+    let duration_on = duration("duration_on")?;
+    let schedule = match field("schedule") {
+        "" => Schedule::Daily,
+        // Schedules without fields, e.g. Cycling, may be given by name alone
+        value => serde_json::from_str(value)
+            .or_else(|_| serde_json::from_value(value.into()))
+            .map_err(|e| format!("schedule: {}", e))?,
+    };
+    // Schedules without a single start time are read as daily ones at midnight, then given
+    // their own schedule
+    let start_time = match schedule {
+        Schedule::Daily | Schedule::Weekly(_) => field("start_time"),
+        _ => "00:00",
+    };
+    let description = Some(field("description").to_owned()).filter(|d| !d.is_empty());
+    let timezone = Some(field("timezone").to_owned()).filter(|tz| !tz.is_empty());
+    let mut n = NewDaily {
+        name: field("name").to_owned(),
+        description,
+        duration_on,
+        duration_unit: unit,
+        output: OutputRef::Alias(field("output").to_owned()),
+        output_alias: None,
+        start_time: start_time.to_owned(),
+        timezone,
+        enabled: csv_bool("enabled", field("enabled"))?,
+        normally_on: csv_bool("normally_on", field("normally_on"))?,
+        active_low: csv_bool("active_low", field("active_low"))?,
+    };
+    state.fill_new_daily(&mut n);
+    let mut timer = IntervalTimer::from_newdaily(n).map_err(|e| e.to_string())?;
+    state.check_pin(timer.output).map_err(|e| e.to_string())?;
+    let daily = &timer.settings;
+    let (duration_on, start_time) = (daily.duration_on(), daily.start_time());
+    let settings = match schedule {
+        Schedule::Daily => Ok(daily.clone()),
+        Schedule::Weekly(days) => {
+            IntervalSettings::weekly(days, duration_on, start_time.unwrap_or_default())
+        }
+        Schedule::Windows(windows) => {
+            IntervalSettings::multi_window(windows).and_then(|settings| {
+                match settings.duration_on() == duration_on {
+                    true => Ok(settings),
+                    false => settings.with_duration_on(duration_on),
+                }
+            })
+        }
+        Schedule::Cron(expr) => IntervalSettings::cron(&expr, duration_on),
+        Schedule::Sun {
+            event,
+            latitude,
+            longitude,
+            offset_secs,
+        } => IntervalSettings::at_sun(
+            event,
+            latitude,
+            longitude,
+            chrono::Duration::seconds(offset_secs),
+            duration_on,
+        ),
+        Schedule::Cycling => {
+            IntervalSettings::cycling(duration_on, unit.duration(duration("duration_off")?))
+        }
+        Schedule::Input {
+            pin,
+            edge,
+            debounce_ms,
+        } => IntervalSettings::on_input(
+            pin,
+            edge,
+            std::time::Duration::from_millis(debounce_ms),
+            duration_on,
+        ),
+    };
+    let mut settings = settings.map_err(|e| format!("schedule: {}", e))?;
+    settings.set_timezone(timer.settings.timezone());
+    timer.settings = settings;
+    if !field("id").is_empty() {
+        timer.id = Uuid::from_str(field("id")).map_err(|e| format!("id: {}", e))?;
+    }
+    Ok(timer)
+}
+
+/// Create or update timers from the rows of a file produced by `export_csv`, e.g. after
+/// editing it in a spreadsheet. Invalid rows are reported by line number and skipped, unless
+/// `strict` is set.
+#[axum::debug_handler]
+pub async fn import_csv(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(params): Query<CsvImportParams>,
+    body: String,
+) -> Result<Json<ImportSummary>, Error> {
+    let mut records = parse_csv(&body)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Err(Error::BadRequest("The CSV file is empty".to_owned()));
+    };
+    let columns: Vec<Option<usize>> = CSV_COLUMNS
+        .iter()
+        .map(|column| header.iter().position(|h| h.trim() == *column))
+        .collect();
+    let missing: Vec<_> = CSV_COLUMNS
+        .iter()
+        .zip(&columns)
+        .filter(|(column, i)| i.is_none() && !OPTIONAL_CSV_COLUMNS.contains(column))
+        .map(|(column, _)| *column)
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::BadRequest(format!(
+            "The CSV header is missing the columns {}",
+            missing.join(", ")
+        )));
+    }
+    let mut summary = ImportSummary::default();
+    let mut timers = Vec::new();
+    for (line, row) in records {
+        match timer_from_csv_row(&state, &columns, &row) {
+            Ok(timer) => timers.push(timer),
+            Err(e) => summary.errors.push(format!("line {}: {}", line, e)),
+        }
+    }
+    if params.strict && !summary.errors.is_empty() {
+        return Err(Error::Validation(summary.errors));
+    }
+    for mut timer in timers {
+        let existing = state.get_interval_timer(timer.id)?;
+        match (params.mode, &existing) {
+            (ImportMode::Skip, Some(_)) => {
+                summary.skipped += 1;
+                continue;
+            }
+            (ImportMode::Regenerate, _) => timer.id = Uuid::new_v4(),
+            (ImportMode::Overwrite, Some(existing)) => timer.created_at = existing.created_at,
+            _ => {}
+        }
+        // A CSV has no runs, so those stored for an overwritten timer are kept
+        state.insert_interval_timer(&timer)?;
+        let action = match (params.mode, existing) {
+            (ImportMode::Overwrite, Some(_)) => AuditAction::Update,
+            _ => AuditAction::Create,
+        };
+        state.audit(action, Some(timer.get_id()), &user);
+        state.spawn_interval_timer(&timer);
+        summary.inserted += 1;
+    }
+    info!(
+        "Imported {} timers from CSV, skipped {}, {} invalid rows.",
+        summary.inserted,
+        summary.skipped,
+        summary.errors.len()
+    );
+    Ok(Json(summary))
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputState {
+    pub pin: u16,
+    /// Whether the load is on
+    pub on: bool,
+    /// Whether the pin is driven high, which differs from `on` for active-low outputs
+    pub high: bool,
+}
+
+/// The last written state of every output, e.g. for a dashboard of which zones are running
+#[axum::debug_handler]
+pub async fn list_outputs(State(state): State<AppState>) -> Json<Vec<OutputState>> {
+    let mut outputs: Vec<_> = state
+        .output_states
+        .lock()
+        .unwrap()
+        .values()
+        .map(|msg| OutputState {
+            pin: msg.output,
+            on: msg.value,
+            high: msg.pin_value(),
+        })
+        .collect();
+    outputs.sort_by_key(|output| output.pin);
+    Json(outputs)
+}
+
+/// Liveness and readiness probe: 200 if the database and GPIO manager are usable, otherwise 503
+/// with the reason
+#[axum::debug_handler]
+pub async fn healthz(State(state): State<AppState>) -> Response {
+    match state.check_health() {
+        Ok(()) => (StatusCode::OK, "ok").into_response(),
+        Err(reason) => {
+            error!("Health check failed: {}", reason);
+            (StatusCode::SERVICE_UNAVAILABLE, reason).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::mpsc;
+
+    fn state() -> AppState {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (tx, _) = mpsc::channel(8);
+        AppState::new(Arc::new(db), tx)
+    }
+
+    fn daily(name: &str, enabled: bool) -> IntervalTimer {
+        let start = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let mut timer =
+            IntervalTimer::once_daily(Some(name.to_owned()), None, Duration::from_secs(600), start)
+                .unwrap();
+        timer.enabled = enabled;
+        timer
+    }
+
+    #[tokio::test]
+    async fn reschedule_restarts_each_enabled_timer_and_is_audited() {
+        let state = state();
+        for timer in [daily("a", true), daily("b", true), daily("c", false)] {
+            state.insert_interval_timer(&timer).unwrap();
+        }
+        let user = AuthUser(Some("bob".to_owned()));
+        let Json(response) = reschedule_timers(State(state.clone()), user).await.unwrap();
+        assert_eq!(response.rescheduled, 2);
+        assert_eq!(state.running_timer_ids().len(), 2);
+        let entries = state.recent_audit_entries(10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AuditAction::Reschedule);
+        assert_eq!(entries[0].timer_id, None);
+        assert_eq!(entries[0].user.as_deref(), Some("bob"));
+    }
+
+    async fn list_with(state: &AppState, headers: HeaderMap) -> Response {
+        list_timers(
+            State(state.clone()),
+            Query(ListParams::default()),
+            Query(TimerFilter::default()),
+            headers,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn if_none_match(response: &Response) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        headers
+    }
+
+    #[tokio::test]
+    async fn listing_is_not_modified_until_a_timer_changes() {
+        let state = state();
+        let mut timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+
+        let first = list_with(&state, HeaderMap::new()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let headers = if_none_match(&first);
+        let again = list_with(&state, headers.clone()).await;
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            again.headers().get(header::ETAG),
+            first.headers().get(header::ETAG)
+        );
+
+        timer.name = Some("renamed".to_owned());
+        state.insert_interval_timer(&timer).unwrap();
+        assert_eq!(list_with(&state, headers).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn a_timer_is_not_modified_until_it_is_edited() {
+        let state = state();
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let get = |headers| get_timer(Ok(Path(timer.get_id())), State(state.clone()), headers);
+
+        let first = get(HeaderMap::new()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let headers = if_none_match(&first);
+        let again = get(headers.clone()).await.unwrap();
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            again.headers().get(header::ETAG),
+            first.headers().get(header::ETAG)
+        );
+
+        patch(&state, timer.get_id(), serde_json::json!({"name": "b"}))
+            .await
+            .unwrap();
+        let edited = get(headers).await.unwrap();
+        assert_eq!(edited.status(), StatusCode::OK);
+        assert_ne!(
+            edited.headers().get(header::ETAG),
+            first.headers().get(header::ETAG)
+        );
+        assert_eq!(json_body(edited).await["name"], "b");
+    }
+
+    #[tokio::test]
+    async fn history_is_not_modified_until_another_run() {
+        let state = state();
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let history = |headers| {
+            crate::history::timer_history(
+                Ok(Path(timer.get_id())),
+                State(state.clone()),
+                Query(Default::default()),
+                headers,
+            )
+        };
+
+        let first = history(HeaderMap::new()).await.unwrap();
+        let headers = if_none_match(&first);
+        let again = history(headers.clone()).await.unwrap();
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+
+        let now = Utc::now();
+        state.record_history(
+            timer.get_id(),
+            now,
+            None,
+            crate::history::FireOutcome::Skipped,
+        );
+        assert_eq!(history(headers).await.unwrap().status(), StatusCode::OK);
+    }
+
+    async fn export(state: &AppState) -> String {
+        text_body(export_csv(State(state.clone())).await.unwrap()).await
+    }
+
+    async fn import(state: &AppState, mode: ImportMode, csv: String) -> ImportSummary {
+        let params = CsvImportParams { mode, strict: true };
+        let user = AuthUser(None);
+        let Json(summary) = import_csv(State(state.clone()), user, Query(params), csv)
+            .await
+            .unwrap();
+        summary
+    }
+
+    #[tokio::test]
+    async fn csv_round_trip_keeps_schedule_timezone_and_flags() {
+        let from = state();
+        let days = vec![chrono::Weekday::Mon, chrono::Weekday::Thu];
+        let start = NaiveTime::from_hms_opt(5, 30, 0).unwrap();
+        let mut weekly = IntervalTimer::new(
+            Some("weekly".to_owned()),
+            Some("beds, by the fence".to_owned()),
+            IntervalSettings::weekly(days, Duration::from_secs(900), start).unwrap(),
+        );
+        weekly.set_timezone(Some(chrono_tz::Europe::Berlin));
+        let mut cycling = IntervalTimer::new(
+            Some("cycling".to_owned()),
+            None,
+            IntervalSettings::cycling(Duration::from_secs(120), Duration::from_secs(3600)).unwrap(),
+        );
+        cycling.normally_on = true;
+        cycling.active_low = true;
+        for timer in [&weekly, &cycling] {
+            from.insert_interval_timer(timer).unwrap();
+        }
+
+        let to = state();
+        let summary = import(&to, ImportMode::Skip, export(&from).await).await;
+        assert_eq!(summary.inserted, 2);
+        for timer in [&weekly, &cycling] {
+            let imported = to.get_interval_timer(timer.get_id()).unwrap().unwrap();
+            assert_eq!(imported.description, timer.description);
+            assert_eq!(imported.normally_on, timer.normally_on);
+            assert_eq!(imported.active_low, timer.active_low);
+            let (settings, expected) = (imported.settings(), timer.settings());
+            assert_eq!(settings.schedule(), expected.schedule());
+            assert_eq!(settings.timezone(), expected.timezone());
+            assert_eq!(settings.start_time(), expected.start_time());
+            assert_eq!(settings.duration_on(), expected.duration_on());
+            assert_eq!(settings.duration_off(), expected.duration_off());
+        }
+    }
+
+    #[tokio::test]
+    async fn the_export_has_a_header_and_quotes_awkward_fields() {
+        let state = state();
+        let mut timer = daily("front, lawn", false);
+        timer.description = Some("says \"hi\"".to_owned());
+        state.insert_interval_timer(&timer).unwrap();
+
+        let response = export_csv(State(state.clone())).await.unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+        let csv = text_body(response).await;
+        let mut lines = csv.split("\r\n");
+        assert_eq!(
+            lines.next(),
+            Some(
+                "id,name,description,output,start_time,duration_on,enabled,timezone,\
+                 normally_on,active_low,schedule,duration_off"
+            )
+        );
+        let row = format!(
+            "{},\"front, lawn\",\"says \"\"hi\"\"\",{},06:00,10,false,,false,false,,",
+            timer.get_id(),
+            timer.output
+        );
+        assert_eq!(lines.next(), Some(row.as_str()));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[tokio::test]
+    async fn csv_overwrite_replaces_the_settings_and_keeps_the_runs() {
+        use chrono::TimeZone;
+
+        let state = state();
+        let mut timer = daily("a", true);
+        timer.fire_count = 7;
+        timer.last_fired_at = Some(chrono::Local.with_ymd_and_hms(2024, 5, 1, 6, 0, 0).unwrap());
+        state.insert_interval_timer(&timer).unwrap();
+        let csv = format!(
+            "id,name,output,start_time,duration_on,schedule,duration_off\r\n\
+             {},a,{},,2,Cycling,30\r\n",
+            timer.get_id(),
+            timer.output
+        );
+
+        let summary = import(&state, ImportMode::Overwrite, csv).await;
+        assert_eq!(summary.inserted, 1);
+        let replaced = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(replaced.settings().schedule(), &Schedule::Cycling);
+        assert_eq!(
+            replaced.settings().duration_off(),
+            state.duration_unit.duration(30)
+        );
+        assert_eq!(replaced.fire_count, 7);
+        assert_eq!(replaced.last_fired_at, timer.last_fired_at);
+        assert_eq!(replaced.created_at, timer.created_at);
+    }
+
+    #[tokio::test]
+    async fn csv_import_reports_bad_schedules_by_line() {
+        let state = state();
+        let csv = "name,output,start_time,duration_on,schedule\n\
+                   a,5,06:00,10,\"{\"\"Weekly\"\":[]}\"\n\
+                   b,5,,10,Cycling\n"
+            .to_owned();
+        let params = CsvImportParams {
+            mode: ImportMode::Skip,
+            strict: false,
+        };
+        let Json(summary) = import_csv(State(state.clone()), AuthUser(None), Query(params), csv)
+            .await
+            .unwrap();
+        assert_eq!(summary.inserted, 0);
+        assert_eq!(summary.errors.len(), 2);
+        assert!(summary.errors[0].starts_with("line 2: schedule: "));
+        assert!(summary.errors[1].starts_with("line 3: duration_off: "));
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn listing_returns_every_timer_with_when_it_next_runs() {
+        let state = state();
+        let (a, b) = (daily("a", true), daily("b", false));
+        for timer in [&a, &b] {
+            state.insert_interval_timer(timer).unwrap();
+        }
+        let response = list_with(&state, HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-total-count"], "2");
+        let body = json_body(response).await;
+        let views = body.as_array().unwrap();
+        let mut ids: Vec<_> = views
+            .iter()
+            .map(|view| view["id"].as_str().unwrap())
+            .collect();
+        ids.sort();
+        let mut expected = [a.get_id().to_string(), b.get_id().to_string()];
+        expected.sort();
+        assert_eq!(ids, expected);
+        for view in views {
+            let next = view["next_fire_secs"].as_i64().unwrap();
+            assert!((0..=24 * 60 * 60).contains(&next));
+            assert_eq!(view["duration_human"], "10 min");
+        }
+    }
+
+    /// Send a request for `uri` through a router holding `state`
+    async fn get(router: axum::Router<AppState>, state: &AppState, uri: &str) -> Response {
+        use tower::ServiceExt;
+        let request = axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        router
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn getting_a_timer_by_id() {
+        let state = state();
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let router = || axum::Router::new().route("/api/timers/:id", axum::routing::get(get_timer));
+
+        let uri = format!("/api/timers/{}", timer.get_id());
+        let response = get(router(), &state, &uri).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["id"], timer.get_id().to_string());
+        assert_eq!(body["name"], "a");
+
+        let uri = format!("/api/timers/{}", Uuid::new_v4());
+        let response = get(router(), &state, &uri).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(response).await["error"], "NotFound");
+    }
+
+    #[tokio::test]
+    async fn listing_a_page_returns_its_slice_and_the_total() {
+        let state = state();
+        for name in ["a", "b", "c", "d", "e"] {
+            state.insert_interval_timer(&daily(name, true)).unwrap();
+        }
+        let router = || axum::Router::new().route("/api/timers", axum::routing::get(list_timers));
+        let ids = |body: serde_json::Value| -> Vec<String> {
+            let timers = body.as_array().unwrap();
+            timers.iter().map(|t| t["id"].to_string()).collect()
+        };
+        let all = ids(json_body(get(router(), &state, "/api/timers").await).await);
+        assert_eq!(all.len(), 5);
+
+        let response = get(router(), &state, "/api/timers?page=2&per_page=2").await;
+        assert_eq!(response.headers()["x-total-count"], "5");
+        assert_eq!(ids(json_body(response).await), all[2..4]);
+        // The last page is short, and pages past it are empty
+        let last = get(router(), &state, "/api/timers?page=3&per_page=2").await;
+        assert_eq!(ids(json_body(last).await), all[4..]);
+        let past = get(router(), &state, "/api/timers?page=4&per_page=2").await;
+        assert!(ids(json_body(past).await).is_empty());
+    }
+
+    #[test]
+    fn page_params_are_clamped() {
+        let page = |page, per_page| PageParams { page, per_page };
+        assert_eq!(
+            page(None, None).per_page(),
+            crate::handlers::DEFAULT_PER_PAGE
+        );
+        assert_eq!(
+            page(None, Some(1000)).per_page(),
+            crate::handlers::MAX_PER_PAGE
+        );
+        assert_eq!(page(None, Some(0)).per_page(), 1);
+        // Page 0 is treated as the first page
+        assert_eq!(page(Some(0), Some(10)).offset(), 0);
+        assert_eq!(page(Some(3), Some(10)).offset(), 20);
+    }
+
+    /// An app state whose outputs are written to simulated pins
+    fn wired() -> (AppState, crate::pins::MockBackend) {
+        let backend = crate::pins::MockBackend::new();
+        let boxed = Box::new(backend.clone());
+        let (man, tx) = crate::util::GpioManager::with_backend(boxed, 8).unwrap();
+        let states = man.output_states();
+        man.run().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tx).with_output_states(states);
+        (state, backend)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn running_a_timer_now_turns_its_output_on_for_the_duration() {
+        let (state, backend) = wired();
+        let mut timer = daily("a", true);
+        timer.output = 17;
+        state.insert_interval_timer(&timer).unwrap();
+        let run = |duration| {
+            run_timer_now(
+                Ok(Path(timer.get_id())),
+                State(state.clone()),
+                AuthUser(None),
+                Query(RunParams { duration }),
+            )
+        };
+
+        let Json(response) = run(Some(2)).await.unwrap();
+        assert_eq!(response.output, 17);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(17, true)]);
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(17, true), (17, false)]);
+
+        let err = run(Some(0)).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidDuration));
+    }
+
+    #[tokio::test]
+    async fn outputs_report_what_was_last_written() {
+        let (state, backend) = wired();
+        let write = |output, value, active_low| {
+            let msg = crate::util::GpioOutMessage {
+                output,
+                value,
+                active_low,
+            };
+            state.gpio_tx.send(crate::util::GpioMessage::Out(msg))
+        };
+        write(7, true, false).await.unwrap();
+        write(8, true, true).await.unwrap();
+        write(9, true, false).await.unwrap();
+        write(9, false, false).await.unwrap();
+        let (resp, done) = tokio::sync::oneshot::channel();
+        state
+            .gpio_tx
+            .send(crate::util::GpioMessage::Flush(resp))
+            .await
+            .unwrap();
+        done.await.unwrap();
+
+        let Json(outputs) = list_outputs(State(state)).await;
+        let reported: Vec<_> = outputs.iter().map(|o| (o.pin, o.on, o.high)).collect();
+        // An active-low output is on while its pin is low
+        assert_eq!(
+            reported,
+            [(7, true, true), (8, true, false), (9, false, false)]
+        );
+        assert!(backend.writes.lock().unwrap().contains(&(8, false)));
+    }
+
+    async fn text_body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn healthz_is_ok_while_the_gpio_manager_is_listening() {
+        let (state, _backend) = wired();
+        let response = healthz(State(state)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text_body(response).await, "ok");
+    }
+
+    #[tokio::test]
+    async fn healthz_is_unavailable_once_the_gpio_manager_has_stopped() {
+        // Nothing receives from the state's channel
+        let response = healthz(State(state())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(text_body(response).await, "GPIO manager channel is closed");
+    }
+
+    #[tokio::test]
+    async fn the_backup_holds_every_timer_as_stored() {
+        let state = state();
+        let mut timer = daily("a", true);
+        timer.fire_count = 3;
+        state.insert_interval_timer(&timer).unwrap();
+        state.insert_interval_timer(&daily("b", false)).unwrap();
+
+        let response = export_timers(State(state)).await.unwrap();
+        let disposition = &response.headers()[header::CONTENT_DISPOSITION];
+        assert_eq!(disposition, "attachment; filename=\"sploosh-backup.json\"");
+        let backup: Backup = serde_json::from_str(&text_body(response).await).unwrap();
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_eq!(backup.timers.len(), 2);
+        let a = backup.timers.iter().find(|t| t.get_id() == timer.get_id());
+        assert_eq!(a.unwrap().fire_count, 3);
+    }
+
+    async fn restore(
+        state: &AppState,
+        mode: ImportMode,
+        timers: Vec<IntervalTimer>,
+    ) -> Result<ImportSummary, Error> {
+        let backup = Backup {
+            version: BACKUP_VERSION,
+            timers,
+        };
+        let params = Query(ImportParams { mode });
+        let imported = import_timers(
+            State(state.clone()),
+            AuthUser(None),
+            params,
+            Ok(Json(backup)),
+        );
+        Ok(imported.await?.0)
+    }
+
+    #[tokio::test]
+    async fn importing_a_backup_in_each_mode() {
+        let state = state();
+        let existing = daily("existing", true);
+        state.insert_interval_timer(&existing).unwrap();
+        let mut changed = existing.clone();
+        changed.name = Some("changed".to_owned());
+        let new = daily("new", true);
+        let backup = || vec![changed.clone(), new.clone()];
+
+        let summary = restore(&state, ImportMode::Skip, backup()).await.unwrap();
+        assert_eq!((summary.inserted, summary.skipped), (1, 1));
+        let kept = state
+            .get_interval_timer(existing.get_id())
+            .unwrap()
+            .unwrap();
+        assert_eq!(kept.name.as_deref(), Some("existing"));
+
+        let summary = restore(&state, ImportMode::Overwrite, backup())
+            .await
+            .unwrap();
+        assert_eq!((summary.inserted, summary.skipped), (2, 0));
+        let replaced = state
+            .get_interval_timer(existing.get_id())
+            .unwrap()
+            .unwrap();
+        assert_eq!(replaced.name.as_deref(), Some("changed"));
+
+        let summary = restore(&state, ImportMode::Regenerate, backup())
+            .await
+            .unwrap();
+        assert_eq!(summary.inserted, 2);
+        assert_eq!(state.count_interval_timers(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_backup_is_refused_whole_if_any_timer_is_invalid() {
+        let state = state().with_allowed_pins(1..=40);
+        let (mut good, mut bad) = (daily("good", true), daily("bad", true));
+        (good.output, bad.output) = (3, 41);
+        let err = restore(&state, ImportMode::Skip, vec![good, bad])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidPin(41)));
+        assert_eq!(state.count_interval_timers(), 0);
+
+        let backup = Backup {
+            version: BACKUP_VERSION + 1,
+            timers: Vec::new(),
+        };
+        let params = Query(ImportParams::default());
+        let imported = import_timers(State(state), AuthUser(None), params, Ok(Json(backup)));
+        let err = imported.await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert!(matches!(err, Error::UnsupportedVersion(_)));
+    }
+
+    async fn patch(state: &AppState, id: Uuid, body: serde_json::Value) -> Result<Response, Error> {
+        let patch = serde_json::from_value(body).unwrap();
+        patch_timer(
+            Ok(Path(id)),
+            State(state.clone()),
+            AuthUser(None),
+            Ok(Json(patch)),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn changing_a_timer_moves_only_its_updated_at() {
+        let state = state();
+        let mut timer = daily("a", true);
+        let last_year = Local::now() - chrono::Duration::days(365);
+        (timer.created_at, timer.updated_at) = (last_year, last_year);
+        state.insert_interval_timer(&timer).unwrap();
+
+        let before = Local::now();
+        let response = patch(&state, timer.get_id(), serde_json::json!({"name": "b"})).await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        let patched = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(patched.created_at, last_year);
+        assert!(patched.updated_at >= before);
+    }
+
+    #[tokio::test]
+    async fn patching_the_name_leaves_everything_else() {
+        let state = state();
+        let mut timer = daily("a", true);
+        timer.description = Some("by the gate".to_owned());
+        timer.output = 12;
+        timer.active_low = true;
+        state.insert_interval_timer(&timer).unwrap();
+        let id = timer.get_id();
+
+        patch(&state, id, serde_json::json!({"name": " b "}))
+            .await
+            .unwrap();
+        let patched = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(patched.name.as_deref(), Some("b"));
+        assert_eq!(patched.description, timer.description);
+        assert_eq!((patched.output, patched.active_low), (12, true));
+        assert_eq!(
+            patched.settings().duration_on(),
+            timer.settings().duration_on()
+        );
+        assert_eq!(
+            patched.settings().start_time(),
+            timer.settings().start_time()
+        );
+        // Renaming doesn't touch the schedule, so nothing was restarted
+        assert!(state.running_timer_ids().is_empty());
+
+        patch(&state, id, serde_json::json!({"duration_on": 20}))
+            .await
+            .unwrap();
+        let patched = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(
+            patched.settings().duration_on(),
+            Duration::from_secs(20 * 60)
+        );
+        assert_eq!(patched.name.as_deref(), Some("b"));
+        assert_eq!(state.running_timer_ids(), [id]);
+    }
+
+    #[tokio::test]
+    async fn a_bad_patch_changes_nothing() {
+        let state = state();
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let id = timer.get_id();
+
+        let bad = serde_json::json!({"name": "b", "start_time": "25:00", "timezone": "Mars/Base"});
+        let err = patch(&state, id, bad).await.unwrap_err();
+        let Error::Validation(problems) = &err else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        assert_eq!(problems.len(), 2);
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        let stored = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(stored.name.as_deref(), Some("a"));
+
+        let missing = patch(&state, Uuid::new_v4(), serde_json::json!({"name": "b"})).await;
+        assert_eq!(missing.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn post_bulk(state: &AppState, items: serde_json::Value) -> Response {
+        use tower::ServiceExt;
+        let router = axum::Router::new()
+            .route("/api/timers/bulk", axum::routing::post(create_timers))
+            .with_state(state.clone());
+        let request = axum::http::Request::post("/api/timers/bulk")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(items.to_string()))
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn one_invalid_zone_aborts_the_whole_batch() {
+        let state = state();
+        let zone = |name: &str, duration_on: u32| {
+            serde_json::json!({
+                "name": name,
+                "duration_on": duration_on,
+                "output": 3,
+                "start_time": "06:00",
+            })
+        };
+
+        let bad = serde_json::json!([zone("one", 10), zone("", 0), zone("three", 10)]);
+        let response = post_bulk(&state, bad).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        let fields = body["fields"].as_array().unwrap();
+        assert_eq!(fields.len(), 2);
+        assert!(fields
+            .iter()
+            .all(|f| f.as_str().unwrap().starts_with("1: ")));
+        assert!(state.get_all_interval_timers().unwrap().is_empty());
+
+        let good = serde_json::json!([zone("one", 10), zone("two", 15)]);
+        let response = post_bulk(&state, good).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let ids = json_body(response).await;
+        assert_eq!(ids.as_array().unwrap().len(), 2);
+        for id in ids.as_array().unwrap() {
+            let id = id.as_str().unwrap().parse().unwrap();
+            assert!(state.get_interval_timer(id).unwrap().is_some());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_firing_turns_the_pin_on_then_off() {
+        let (state, backend) = wired();
+        let fire = |output, seconds| {
+            let body = TestFire {
+                output,
+                seconds,
+                active_low: None,
+            };
+            test_fire(State(state.clone()), AuthUser(None), Ok(Json(body)))
+        };
+
+        let begun = tokio::time::Instant::now();
+        assert_eq!(fire(4, 3).await.unwrap(), StatusCode::NO_CONTENT);
+        assert_eq!(begun.elapsed().as_secs(), 3);
+        let (resp, flushed) = tokio::sync::oneshot::channel();
+        let flush = crate::util::GpioMessage::Flush(resp);
+        state.gpio_tx.send(flush).await.unwrap();
+        flushed.await.unwrap();
+        assert_eq!(*backend.writes.lock().unwrap(), [(4, true), (4, false)]);
+
+        for seconds in [0, MAX_TEST_FIRE_SECS + 1] {
+            let err = fire(4, seconds).await.unwrap_err();
+            assert!(matches!(err, Error::Validation(_)), "{:?}", err);
+        }
+        assert!(matches!(fire(0, 3).await, Err(Error::InvalidPin(0))));
+        assert_eq!(backend.writes.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn listing_filters_by_name_and_pin_together() {
+        let state = state();
+        for (name, output) in [("Front Lawn", 7), ("back lawn", 8), ("Beds", 7)] {
+            let mut timer = daily(name, true);
+            timer.output = output;
+            state.insert_interval_timer(&timer).unwrap();
+        }
+        let names = |uri: &'static str| {
+            let state = state.clone();
+            async move {
+                let router =
+                    axum::Router::new().route("/api/timers", axum::routing::get(list_timers));
+                let response = get(router, &state, uri).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = json_body(response).await;
+                let mut names: Vec<_> = body
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| t["name"].as_str().unwrap().to_owned())
+                    .collect();
+                names.sort();
+                names
+            }
+        };
+
+        assert_eq!(
+            names("/api/timers?name_contains=LAWN").await,
+            ["Front Lawn", "back lawn"]
+        );
+        assert_eq!(names("/api/timers?pin=7").await, ["Beds", "Front Lawn"]);
+        assert_eq!(
+            names("/api/timers?name_contains=lawn&pin=7").await,
+            ["Front Lawn"]
+        );
+        // A blank field, as a search form submits, doesn't filter
+        assert_eq!(names("/api/timers?name_contains=&pin=").await.len(), 3);
+        assert!(names("/api/timers?name_contains=hedge").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn manual_runs_are_refused_while_the_gpio_queue_is_full() {
+        use tower::ServiceExt;
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = AppState::new(Arc::new(db), tx.clone());
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let run = || {
+            run_timer_now(
+                Ok(Path(timer.get_id())),
+                State(state.clone()),
+                AuthUser(None),
+                Query(RunParams { duration: Some(1) }),
+            )
+        };
+
+        let (flushed, _) = tokio::sync::oneshot::channel();
+        tx.try_send(crate::util::GpioMessage::Flush(flushed))
+            .unwrap();
+        assert!(matches!(run().await.unwrap_err(), Error::Busy));
+        let request = axum::http::Request::post("/api/test-fire")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(r#"{"output": 17, "seconds": 5}"#))
+            .unwrap();
+        let response = axum::Router::new()
+            .route("/api/test-fire", axum::routing::post(test_fire))
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json_body(response).await["error"], "Busy");
+        assert!(state.recent_audit_entries(10).unwrap().is_empty());
+
+        // Once the manager catches up there's room again
+        rx.recv().await.unwrap();
+        let Json(response) = run().await.unwrap();
+        assert_eq!(response.id, timer.get_id());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_schedule_expands_daily_and_weekly_timers_in_order() {
+        use chrono::{TimeZone, Weekday};
+        let monday_noon = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap();
+        let clock = crate::util::MockClock::new(monday_noon);
+        let state = state().with_clock(Arc::new(clock));
+        let utc = |d, h| Utc.with_ymd_and_hms(2024, 6, d, h, 0, 0).unwrap();
+
+        let mut lawn = daily("lawn", true);
+        lawn.set_timezone(Some(chrono_tz::UTC));
+        let mut off = daily("off", false);
+        off.set_timezone(Some(chrono_tz::UTC));
+        let days = vec![Weekday::Sun, Weekday::Wed];
+        let start = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+        let mut beds = IntervalTimer::weekly(
+            Some("beds".to_owned()),
+            None,
+            days,
+            Duration::from_secs(1800),
+            start,
+        )
+        .unwrap();
+        beds.set_timezone(Some(chrono_tz::UTC));
+        for timer in [&lawn, &off, &beds] {
+            state.insert_interval_timer(timer).unwrap();
+        }
+
+        let params = ScheduleParams { hours: Some(48) };
+        let Json(runs) = schedule(State(state.clone()), Query(params)).await.unwrap();
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|run| {
+                let (start, end) = (run.start.with_timezone(&Utc), run.end.with_timezone(&Utc));
+                (run.name.as_deref().unwrap(), start, end - start)
+            })
+            .collect();
+        let (ten_minutes, half_hour) =
+            (chrono::Duration::minutes(10), chrono::Duration::minutes(30));
+        assert_eq!(
+            runs,
+            [
+                ("lawn", utc(4, 6), ten_minutes),
+                ("lawn", utc(5, 6), ten_minutes),
+                ("beds", utc(5, 7), half_hour),
+            ]
+        );
+
+        for hours in [0, MAX_SCHEDULE_HOURS + 1] {
+            let params = ScheduleParams { hours: Some(hours) };
+            let err = schedule(State(state.clone()), Query(params))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::Validation(_)), "{} hours", hours);
+        }
+    }
+
+    #[tokio::test]
+    async fn server_time_agrees_with_itself_across_timezones() {
+        use chrono::{FixedOffset, TimeZone};
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 16, 0, 0).unwrap();
+        let state = state()
+            .with_clock(Arc::new(crate::util::MockClock::new(now)))
+            .with_default_timezone(Some(chrono_tz::America::New_York));
+        let router = axum::Router::new().route("/api/time", axum::routing::get(server_time));
+        let response = get(router, &state, "/api/time").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+
+        let utc: DateTime<Utc> = body["utc"].as_str().unwrap().parse().unwrap();
+        let local: DateTime<FixedOffset> = body["local"].as_str().unwrap().parse().unwrap();
+        assert!((utc - now).num_seconds().abs() <= 1);
+        assert_eq!(local, utc);
+        let offset = body["local_offset_secs"].as_i64().unwrap();
+        assert_eq!(offset, i64::from(local.offset().local_minus_utc()));
+        assert_eq!(body["timezone"], "America/New_York");
+        // Eastern daylight time is four hours behind
+        let in_timezone: NaiveDateTime = body["timezone_time"].as_str().unwrap().parse().unwrap();
+        assert_eq!(in_timezone, (utc - chrono::Duration::hours(4)).naive_utc());
+        assert!(body["uptime_secs"].is_u64());
+
+        let Json(time) = server_time(State(state.with_default_timezone(None))).await;
+        assert_eq!((time.timezone, time.timezone_time), (None, None));
+    }
+
+    #[tokio::test]
+    async fn a_duplicate_has_a_new_id_and_the_same_settings() {
+        let state = state();
+        let mut source = daily("Front lawn", true);
+        source.output = 12;
+        source.active_low = true;
+        source.fire_count = 40;
+        state.insert_interval_timer(&source).unwrap();
+        let duplicate = |id| duplicate_timer(Ok(Path(id)), State(state.clone()), AuthUser(None));
+
+        let response = duplicate(source.get_id()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location_header = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = json_body(response).await;
+        let id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+        assert_ne!(id, source.get_id());
+        assert_eq!(location_header, format!("/timer/{}", id));
+
+        let copy = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(copy.name.as_deref(), Some("Front lawn (copy)"));
+        assert_eq!((copy.output, copy.active_low), (12, true));
+        assert_eq!(copy.fire_count, 0);
+        let (a, b) = (copy.settings(), source.settings());
+        assert_eq!(a.duration_on(), b.duration_on());
+        assert_eq!(a.start_time(), b.start_time());
+        assert_eq!(a.schedule(), b.schedule());
+        assert_eq!(state.running_timer_ids(), [id]);
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 2);
+
+        let err = duplicate(Uuid::new_v4()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_record_is_left_out_rather_than_failing_the_listing() {
+        let state = state();
+        let (a, b) = (daily("a", true), daily("b", true));
+        state.insert_interval_timer(&a).unwrap();
+        state.insert_interval_timer(&b).unwrap();
+        let bad = Uuid::new_v4();
+        state
+            .db
+            .insert(bad.as_bytes(), &b"{\"id\": 7, not json"[..])
+            .unwrap();
+
+        let router = axum::Router::new().route("/api/timers", axum::routing::get(list_timers));
+        let response = get(router, &state, "/api/timers").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        let mut names: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|timer| timer["name"].as_str().unwrap().to_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(state.corrupt_keys(), [bad.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn outputs_may_be_given_by_alias() {
+        let aliases = [("Front Lawn".to_owned(), 17)].into_iter().collect();
+        let state = state().with_pin_aliases(aliases);
+        let create = |output: serde_json::Value| {
+            let body = serde_json::json!({
+                "name": format!("zone {}", output),
+                "duration_on": 10,
+                "output": output,
+                "start_time": "06:00",
+            });
+            let n = serde_json::from_value(body).unwrap();
+            create_timer(State(state.clone()), AuthUser(None), Ok(Json(n)))
+        };
+
+        let response = create(serde_json::json!(" Front Lawn ")).await.unwrap();
+        let body = json_body(response).await;
+        assert_eq!(body["output"], 17);
+        assert_eq!(body["output_alias"], "Front Lawn");
+        let response = create(serde_json::json!("17")).await.unwrap();
+        assert_eq!(
+            json_body(response).await["output_alias"],
+            serde_json::Value::Null
+        );
+
+        let err = create(serde_json::json!("Back Lawn")).await.unwrap_err();
+        let Error::Validation(problems) = err else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        assert_eq!(problems, ["output: unknown pin alias \"Back Lawn\""]);
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_malformed_duration_skips_its_row_unless_strict() {
+        let csv = "name,output,start_time,duration_on\n\
+                   front,5,06:00,10\n\
+                   back,6,06:30,ten\n\
+                   beds,7,07:00,15\n";
+        let import = |state: &AppState, strict| {
+            let params = CsvImportParams {
+                mode: ImportMode::Skip,
+                strict,
+            };
+            import_csv(
+                State(state.clone()),
+                AuthUser(None),
+                Query(params),
+                csv.to_owned(),
+            )
+        };
+
+        let lenient = state();
+        let Json(summary) = import(&lenient, false).await.unwrap();
+        assert_eq!(summary.inserted, 2);
+        assert_eq!(summary.errors.len(), 1);
+        assert!(
+            summary.errors[0].starts_with("line 3: duration_on: "),
+            "{:?}",
+            summary.errors
+        );
+        let mut names: Vec<_> = lenient
+            .get_all_interval_timers()
+            .unwrap()
+            .into_iter()
+            .map(|timer| timer.name.unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["beds", "front"]);
+
+        let strict = state();
+        let err = import(&strict, true).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert!(strict.get_all_interval_timers().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_last_run_is_shown_and_survives_edits() {
+        use chrono::TimeZone;
+        let ran_at = Utc.with_ymd_and_hms(2024, 6, 2, 5, 0, 0).unwrap();
+        let state = state().with_clock(Arc::new(crate::util::MockClock::new(ran_at)));
+        let timer = daily("a", true);
+        state.insert_interval_timer(&timer).unwrap();
+        let router = || axum::Router::new().route("/api/timers/:id", axum::routing::get(get_timer));
+        let uri = format!("/api/timers/{}", timer.get_id());
+        let shown = |body: &serde_json::Value| {
+            let last: DateTime<Local> = body["last_fired_at"].as_str()?.parse().unwrap();
+            Some(last.with_timezone(&Utc))
+        };
+
+        let body = json_body(get(router(), &state, &uri).await).await;
+        assert_eq!(shown(&body), None);
+        assert_eq!(state.record_fire(timer.get_id()).unwrap(), Some(1));
+        let body = json_body(get(router(), &state, &uri).await).await;
+        let last = shown(&body).unwrap();
+        assert!((last - ran_at).num_seconds().abs() <= 1, "{}", last);
+
+        // Editing the timer keeps it
+        let response = patch(&state, timer.get_id(), serde_json::json!({"name": "b"})).await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        let body = json_body(get(router(), &state, &uri).await).await;
+        assert_eq!(
+            (body["name"].as_str(), shown(&body)),
+            (Some("b"), Some(last))
+        );
+    }
+
+    #[tokio::test]
+    async fn a_timer_is_active_within_its_window_even_across_midnight() {
+        use chrono::TimeZone;
+        let state = state();
+        let late = NaiveTime::from_hms_opt(23, 30, 0).unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        let mut timer = IntervalTimer::once_daily(None, None, hour, late).unwrap();
+        timer.set_timezone(Some(chrono_tz::UTC));
+        state.insert_interval_timer(&timer).unwrap();
+        let active_at = |d, h, m, timer: &IntervalTimer| {
+            let now = Utc.with_ymd_and_hms(2024, 6, d, h, m, 0).unwrap();
+            let clock = crate::util::MockClock::new(now);
+            let state = state.clone().with_clock(Arc::new(clock));
+            let id = Ok(Path(timer.get_id()));
+            async move {
+                let Json(activity) = timer_active(id, State(state)).await.unwrap();
+                assert_eq!(activity.active, activity.until.is_some());
+                activity.until
+            }
+        };
+        let half_past_midnight = NaiveTime::from_hms_opt(0, 30, 0);
+
+        assert_eq!(active_at(1, 23, 45, &timer).await, half_past_midnight);
+        assert_eq!(active_at(2, 0, 15, &timer).await, half_past_midnight);
+        assert_eq!(active_at(2, 0, 30, &timer).await, None);
+        assert_eq!(active_at(2, 12, 0, &timer).await, None);
+        assert_eq!(active_at(2, 23, 29, &timer).await, None);
+
+        timer.enabled = false;
+        state.insert_interval_timer(&timer).unwrap();
+        assert_eq!(active_at(1, 23, 45, &timer).await, None);
+        let missing = timer_active(Ok(Path(Uuid::new_v4())), State(state.clone())).await;
+        assert_eq!(missing.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn api_durations_are_read_and_shown_in_the_configured_unit() {
+        for (unit, name, stored) in [
+            (
+                DurationUnit::Minutes,
+                "minutes",
+                Duration::from_secs(90 * 60),
+            ),
+            (DurationUnit::Seconds, "seconds", Duration::from_secs(90)),
+        ] {
+            let state = state().with_duration_unit(unit);
+            let n = serde_json::from_value(serde_json::json!({
+                "name": "a",
+                "duration_on": 90,
+                "output": 5,
+                "start_time": "06:00",
+            }))
+            .unwrap();
+            let response = create_timer(State(state.clone()), AuthUser(None), Ok(Json(n)))
+                .await
+                .unwrap();
+            let body = json_body(response).await;
+            assert_eq!(body["settings"]["duration_on"], 90, "{}", name);
+            assert_eq!(body["duration_unit"], name);
+            let id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+            let timer = state.get_interval_timer(id).unwrap().unwrap();
+            assert_eq!(timer.settings().duration_on(), stored, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn posting_json_creates_a_timer_or_explains_why_not() {
+        use tower::ServiceExt;
+        let state = state();
+        let post = |body: &'static str| {
+            let request = axum::http::Request::post("/api/timers")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            axum::Router::new()
+                .route("/api/timers", axum::routing::post(create_timer))
+                .with_state(state.clone())
+                .oneshot(request)
+        };
+
+        let created =
+            post(r#"{"name": "a", "duration_on": 5, "output": 4, "start_time": "06:15"}"#);
+        let created = created.await.unwrap();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let body = json_body(created).await;
+        assert_eq!((&body["name"], &body["output"]), (&"a".into(), &4.into()));
+        assert_eq!(body["settings"]["start_time"], "06:15:00");
+
+        for (request, error) in [
+            (r#"{"name": "a", "duration_on": 5"#, "BadRequest"),
+            (
+                r#"{"name": "a", "output": 4, "start_time": "06:15"}"#,
+                "BadRequest",
+            ),
+            (
+                r#"{"name": "a", "duration_on": 0, "output": 4, "start_time": "6am"}"#,
+                "Validation",
+            ),
+        ] {
+            let response = post(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", request);
+            let body = json_body(response).await;
+            assert_eq!(body["error"], error, "{}", request);
+            assert!(!body["message"].as_str().unwrap().is_empty());
+        }
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,225 @@
+//! An append-only record of changes to timers and manual runs, so that a shared controller can
+//! show who did what
+use crate::{
+    auth::AuthUser,
+    events::{timer_label, SchedulerEventKind},
+    util::AppState,
+    Error,
+};
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::error;
+use uuid::Uuid;
+
+/// Name of the sled tree holding the audit log, keyed by big-endian sequence number so that
+/// entries are in the order they were made
+const AUDIT_LOG: &str = "audit";
+
+/// Entries returned by `GET /api/audit` when no limit is given
+pub const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    /// An output was turned on by hand rather than by its schedule
+    Fire,
+    /// Every timer's task was restarted from the database
+    Reschedule,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub action: AuditAction,
+    /// The timer acted on, if any. Test fires of a bare output and rescheduling every timer
+    /// have none.
+    pub timer_id: Option<Uuid>,
+    /// The user who made the change, if authentication is enabled
+    pub user: Option<String>,
+}
+
+impl AppState {
+    fn audit_log(&self) -> Result<sled::Tree, Error> {
+        Ok(self.db.open_tree(AUDIT_LOG)?)
+    }
+
+    /// Append an entry to the audit log, dropping the oldest entries beyond the maximum
+    pub fn try_audit(
+        &self,
+        action: AuditAction,
+        timer_id: Option<Uuid>,
+        user: &AuthUser,
+    ) -> Result<(), Error> {
+        let entry = AuditEntry {
+            at: self.clock.now(),
+            action,
+            timer_id,
+            user: user.0.clone(),
+        };
+        let log = self.audit_log()?;
+        let len = self.audit_len.get_or_init(|| AtomicUsize::new(log.len()));
+        let key = self.db.generate_id()?.to_be_bytes();
+        log.insert(key, serde_json::to_vec(&entry)?)?;
+        let excess = (len.fetch_add(1, Ordering::SeqCst) + 1).saturating_sub(self.audit_max);
+        for _ in 0..excess {
+            if log.pop_min()?.is_some() {
+                len.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `try_audit`, but only logging failures, since the change being audited has
+    /// already been made by the time it's recorded
+    pub fn audit(&self, action: AuditAction, timer_id: Option<Uuid>, user: &AuthUser) {
+        if let Err(e) = self.try_audit(action, timer_id, user) {
+            error!("Failed to record {:?} in the audit log: {}", action, e);
+        }
+        if let Some(id) = timer_id {
+            self.publish_audited(action, id);
+        }
+    }
+
+    /// Tell anyone following `GET /events` about an audited action on a timer
+    fn publish_audited(&self, action: AuditAction, id: Uuid) {
+        let (kind, what) = match action {
+            AuditAction::Create => (SchedulerEventKind::Created, "was created"),
+            AuditAction::Update => (SchedulerEventKind::Updated, "was updated"),
+            AuditAction::Delete => (SchedulerEventKind::Deleted, "was deleted"),
+            AuditAction::Restore => (SchedulerEventKind::Restored, "was restored"),
+            AuditAction::Fire => (SchedulerEventKind::Fired, "was run by hand"),
+            AuditAction::Reschedule => (SchedulerEventKind::Updated, "was rescheduled"),
+        };
+        // Deleted timers are no longer around to be named
+        let label = match self.get_interval_timer(id) {
+            Ok(Some(timer)) => timer_label(&timer),
+            _ => id.to_string(),
+        };
+        self.publish(kind, id, &label, what);
+    }
+
+    /// Up to `limit` of the most recent audit entries, newest first
+    pub fn recent_audit_entries(&self, limit: usize) -> Result<Vec<AuditEntry>, Error> {
+        self.audit_log()?
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditParams {
+    /// Maximum number of entries to return, newest first
+    pub limit: Option<usize>,
+}
+
+#[axum::debug_handler]
+pub async fn list_audit(
+    State(state): State<AppState>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<Vec<AuditEntry>>, Error> {
+    let limit = params.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    Ok(Json(state.recent_audit_entries(limit)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    fn state(audit_max: usize) -> AppState {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (tx, _) = mpsc::channel(1);
+        AppState::new(Arc::new(db), tx).with_audit_max(audit_max)
+    }
+
+    #[test]
+    fn an_entry_records_the_action_timer_and_user() {
+        let state = state(10);
+        let id = Uuid::new_v4();
+        let user = AuthUser(Some("alice".to_owned()));
+        state.audit(AuditAction::Create, Some(id), &user);
+        let entries = state.recent_audit_entries(10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AuditAction::Create);
+        assert_eq!(entries[0].timer_id, Some(id));
+        assert_eq!(entries[0].user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn the_oldest_entries_are_dropped_beyond_the_maximum() {
+        let state = state(3);
+        let ids: Vec<_> = (0..5).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            state.audit(AuditAction::Update, Some(*id), &AuthUser::default());
+        }
+        let kept: Vec<_> = state
+            .recent_audit_entries(10)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.timer_id.unwrap())
+            .collect();
+        assert_eq!(kept, [ids[4], ids[3], ids[2]]);
+        assert_eq!(state.audit_log().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn entries_already_in_the_log_count_towards_the_maximum() {
+        let db = Arc::new(sled::Config::new().temporary(true).open().unwrap());
+        let (tx, _) = mpsc::channel(1);
+        let before = AppState::new(db.clone(), tx.clone()).with_audit_max(2);
+        for _ in 0..2 {
+            before.audit(AuditAction::Fire, None, &AuthUser::default());
+        }
+        // A restart starts counting from what's stored
+        let after = AppState::new(db, tx).with_audit_max(2);
+        after.audit(AuditAction::Fire, None, &AuthUser::default());
+        assert_eq!(after.recent_audit_entries(10).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn creating_through_the_api_is_audited_once() {
+        let state = state(10);
+        let zone = serde_json::json!([{
+            "name": "lawn",
+            "duration_on": 10,
+            "output": 3,
+            "start_time": "06:00",
+        }]);
+        let items = Ok(Json(serde_json::from_value(zone).unwrap()));
+        let user = AuthUser(Some("carol".to_owned()));
+        let (_, Json(ids)) = crate::api::create_timers(State(state.clone()), user, items)
+            .await
+            .unwrap();
+        state.audit(AuditAction::Fire, None, &AuthUser::default());
+
+        let params = AuditParams { limit: Some(1) };
+        let Json(latest) = list_audit(State(state.clone()), Query(params))
+            .await
+            .unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].action, AuditAction::Fire);
+        let Json(all) = list_audit(State(state), Query(AuditParams::default()))
+            .await
+            .unwrap();
+        let created: Vec<_> = all
+            .iter()
+            .filter(|entry| entry.action == AuditAction::Create)
+            .collect();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].timer_id, Some(ids[0]));
+        assert_eq!(created[0].user.as_deref(), Some("carol"));
+    }
+}
@@ -0,0 +1,205 @@
+//! Optional HTTP Basic authentication, so that not just anyone on the network can change
+//! timers or fire relays
+use crate::Error;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, fmt};
+use tracing::warn;
+
+/// The user who made a request, if it carried the right credentials
+#[derive(Debug, Default, Clone)]
+pub struct AuthUser(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// A password which is left out of debug output, so that logging the config doesn't leak it
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Password(String);
+
+impl Password {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Password {
+    fn from(password: String) -> Password {
+        Password(password)
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+/// The credentials required for requests, and whether reads need them too
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    username: String,
+    password: Password,
+    protect_reads: bool,
+}
+
+impl BasicAuth {
+    pub fn new(username: String, password: Password) -> BasicAuth {
+        BasicAuth {
+            username,
+            password,
+            protect_reads: false,
+        }
+    }
+
+    /// Require the credentials for reads as well as writes
+    pub fn with_protect_reads(mut self, protect_reads: bool) -> BasicAuth {
+        self.protect_reads = protect_reads;
+        self
+    }
+
+    /// Whether a request needs credentials. The health check is always open so that
+    /// monitoring keeps working.
+    pub fn required(&self, method: &Method, path: &str) -> bool {
+        let read = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+        path != "/healthz" && (self.protect_reads || !read)
+    }
+
+    /// Whether the `Authorization` header holds the expected credentials
+    pub fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(encoded) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        let Ok(decoded) = STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+        let expected = format!("{}:{}", self.username, self.password.expose());
+        constant_time_eq(&decoded, expected.as_bytes())
+    }
+}
+
+/// Compare without returning early, so the time taken doesn't reveal how much of a guess was
+/// right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware refusing requests without the right credentials with a 401, and noting the user
+/// on those with them as an `AuthUser`
+pub async fn require_auth(
+    State(auth): State<BasicAuth>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if auth.authorized(request.headers()) {
+        let user = AuthUser(Some(auth.username.clone()));
+        request.extensions_mut().insert(user);
+        return next.run(request).await;
+    }
+    if !auth.required(request.method(), request.uri().path()) {
+        return next.run(request).await;
+    }
+    warn!("Unauthorized {} {}", request.method(), request.uri().path());
+    let mut response = Error::Unauthorized.into_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static("Basic realm=\"sploosh\""),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    /// Echoes the user the middleware noted, if any
+    async fn whoami(AuthUser(user): AuthUser) -> String {
+        user.unwrap_or_default()
+    }
+
+    fn protected(protect_reads: bool) -> Router {
+        let auth = BasicAuth::new("ann".to_owned(), "hunter2".to_owned().into())
+            .with_protect_reads(protect_reads);
+        Router::new()
+            .route("/timers", get(whoami).post(whoami))
+            .route("/healthz", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(auth, require_auth))
+    }
+
+    async fn send(app: Router, method: Method, uri: &str, credentials: Option<&str>) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(credentials) = credentials {
+            let value = format!("Basic {}", STANDARD.encode(credentials));
+            request = request.header(header::AUTHORIZATION, value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_need_the_right_credentials() {
+        for credentials in [None, Some("ann:hunter3"), Some("bob:hunter2")] {
+            let response = send(protected(false), Method::POST, "/timers", credentials).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers()[header::WWW_AUTHENTICATE],
+                "Basic realm=\"sploosh\""
+            );
+        }
+
+        let response = send(
+            protected(false),
+            Method::POST,
+            "/timers",
+            Some("ann:hunter2"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"ann");
+    }
+
+    #[tokio::test]
+    async fn reads_are_open_unless_protected() {
+        let open = send(protected(false), Method::GET, "/timers", None).await;
+        assert_eq!(open.status(), StatusCode::OK);
+        let closed = send(protected(true), Method::GET, "/timers", None).await;
+        assert_eq!(closed.status(), StatusCode::UNAUTHORIZED);
+        // Monitoring can always reach the health check
+        let health = send(protected(true), Method::GET, "/healthz", None).await;
+        assert_eq!(health.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn passwords_are_left_out_of_debug_output() {
+        let auth = BasicAuth::new("ann".to_owned(), "hunter2".to_owned().into());
+        assert!(!format!("{:?}", auth).contains("hunter2"));
+    }
+}
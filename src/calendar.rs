@@ -0,0 +1,189 @@
+//! The watering schedule as an iCalendar document, so that it can be subscribed to from a
+//! calendar app
+use crate::{util::AppState, Error, IntervalTimer, Schedule};
+use axum::{extract::State, http::header, response::IntoResponse};
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use std::fmt::Write;
+
+/// The two letter code iCalendar uses for a day of the week
+fn ics_weekday(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Escape text for use in a property value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Write one recurring event. `rrule` is the recurrence rule without the `RRULE:` prefix.
+fn write_event(
+    out: &mut String,
+    timer: &IntervalTimer,
+    uid: &str,
+    date: NaiveDate,
+    start: NaiveTime,
+    duration: std::time::Duration,
+    rrule: &str,
+) {
+    let start = date.and_time(start).format("%Y%m%dT%H%M%S");
+    // Timers without a timezone run in the server's local time, written as floating time
+    let dtstart = match timer.settings.tz {
+        Some(tz) => format!("DTSTART;TZID={}:{}", tz.name(), start),
+        None => format!("DTSTART:{}", start),
+    };
+    let summary = timer.name.as_deref().unwrap_or("Timer");
+    let _ = write!(out, "BEGIN:VEVENT\r\n");
+    let _ = write!(out, "UID:{}@sploosh\r\n", uid);
+    let _ = write!(out, "DTSTAMP:{}\r\n", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let _ = write!(out, "{}\r\n", dtstart);
+    let _ = write!(out, "DURATION:PT{}S\r\n", duration.as_secs());
+    let _ = write!(out, "RRULE:{}\r\n", rrule);
+    let _ = write!(out, "SUMMARY:{}\r\n", escape(summary));
+    let _ = write!(out, "DESCRIPTION:Output {}\r\n", timer.output);
+    let _ = write!(out, "END:VEVENT\r\n");
+}
+
+/// Render the runs of every enabled timer as recurring events, starting from the day each
+/// timer was created. Schedules which don't repeat at a fixed time of day, i.e. cron, sun and
+/// cycling timers, are left out.
+pub fn render(timers: &[IntervalTimer]) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "BEGIN:VCALENDAR\r\n");
+    let _ = write!(out, "VERSION:2.0\r\n");
+    let _ = write!(out, "PRODID:-//sploosh//Watering schedule//EN\r\n");
+    for timer in timers.iter().filter(|timer| timer.enabled) {
+        let id = timer.get_id();
+        let created = timer.created_at.date_naive();
+        let settings = &timer.settings;
+        match (&settings.schedule, settings.start_time) {
+            (Schedule::Daily, Some(start)) => write_event(
+                &mut out,
+                timer,
+                &id.to_string(),
+                created,
+                start,
+                settings.duration_on,
+                "FREQ=DAILY",
+            ),
+            (Schedule::Weekly(days), Some(start)) if !days.is_empty() => {
+                // Start on the first selected day so that DTSTART is itself a run
+                let mut date = created;
+                while !days.contains(&date.weekday()) {
+                    date += Duration::days(1);
+                }
+                let byday: Vec<_> = days.iter().map(|day| ics_weekday(*day)).collect();
+                write_event(
+                    &mut out,
+                    timer,
+                    &id.to_string(),
+                    date,
+                    start,
+                    settings.duration_on,
+                    &format!("FREQ=WEEKLY;BYDAY={}", byday.join(",")),
+                )
+            }
+            (Schedule::Windows(windows), _) => {
+                for (i, (start, duration)) in windows.iter().enumerate() {
+                    write_event(
+                        &mut out,
+                        timer,
+                        &format!("{}-{}", id, i),
+                        created,
+                        *start,
+                        *duration,
+                        "FREQ=DAILY",
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    let _ = write!(out, "END:VCALENDAR\r\n");
+    out
+}
+
+#[axum::debug_handler]
+pub async fn calendar(State(state): State<AppState>) -> Result<impl IntoResponse, Error> {
+    let timers = state.get_all_interval_timers()?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        render(&timers),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntervalSettings;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    /// A timer created on Monday 3 June 2024
+    fn created_monday(name: &str, settings: IntervalSettings) -> IntervalTimer {
+        let mut timer = IntervalTimer::new(Some(name.to_owned()), None, settings);
+        timer.created_at = chrono::Local
+            .with_ymd_and_hms(2024, 6, 3, 12, 0, 0)
+            .unwrap();
+        timer
+    }
+
+    fn lines_starting(ics: &str, prefix: &str) -> Vec<String> {
+        ics.split("\r\n")
+            .filter(|line| line.starts_with(prefix))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn each_timer_with_a_time_of_day_is_one_event() {
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let ten = Duration::from_secs(600);
+        let daily = created_monday(
+            "Lawn, front",
+            IntervalSettings::once_daily(ten, at(6, 30)).unwrap(),
+        );
+        let days = vec![Weekday::Fri, Weekday::Wed];
+        let mut weekly = created_monday(
+            "Beds",
+            IntervalSettings::weekly(days, ten, at(18, 0)).unwrap(),
+        );
+        weekly.set_timezone(Some(chrono_tz::Europe::London));
+        let mut disabled =
+            created_monday("Off", IntervalSettings::once_daily(ten, at(7, 0)).unwrap());
+        disabled.enabled = false;
+        let cycling = created_monday("Drip", IntervalSettings::cycling(ten, ten).unwrap());
+
+        let ics = render(&[daily, weekly, disabled, cycling]);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(lines_starting(&ics, "BEGIN:VEVENT").len(), 2);
+        assert_eq!(
+            lines_starting(&ics, "DTSTART"),
+            [
+                "DTSTART:20240603T063000",
+                // The first Wednesday or Friday on or after the day it was created
+                "DTSTART;TZID=Europe/London:20240605T180000",
+            ]
+        );
+        assert_eq!(
+            lines_starting(&ics, "RRULE"),
+            ["RRULE:FREQ=DAILY", "RRULE:FREQ=WEEKLY;BYDAY=WE,FR"]
+        );
+        assert_eq!(lines_starting(&ics, "DURATION"), ["DURATION:PT600S"; 2]);
+        assert_eq!(
+            lines_starting(&ics, "SUMMARY"),
+            ["SUMMARY:Lawn\\, front", "SUMMARY:Beds"]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Settings loaded from a TOML or JSON file with `--config`, which command line flags override
+use crate::{auth::Password, handlers::DurationUnit, util::DEFAULT_GPIO_CHANNEL_CAPACITY, Error};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address to serve the web interface on
+    pub bind: String,
+    /// Path to the database directory
+    pub db: Option<PathBuf>,
+    /// Lowest output pin timers may control
+    pub min_pin: u16,
+    /// Highest output pin timers may control
+    pub max_pin: u16,
+    /// IANA name of the timezone used for timers which don't specify one, instead of the
+    /// server's local timezone
+    pub timezone: Option<String>,
+    /// Log GPIO writes instead of using sysfs
+    pub simulate: bool,
+    /// Flush the database to disk after every write
+    pub durable: bool,
+    /// Refuse to store a timer with the same name as another
+    pub unique_names: bool,
+    /// Hours deleted timers can be restored for before they are purged
+    pub trash_retention_hours: u32,
+    /// Requests changing timers allowed per client per minute, or 0 for no limit
+    pub rate_limit_per_minute: u32,
+    /// Username required for requests which change timers, along with `password`
+    pub username: Option<String>,
+    /// Password required along with `username`
+    pub password: Option<Password>,
+    /// Require the credentials for reads as well
+    pub protect_reads: bool,
+    /// The most entries kept in the audit log
+    pub audit_max_entries: usize,
+    /// The most runs kept in each timer's history
+    pub history_max_entries: usize,
+    /// The most outputs which may be on at once, or `None` for no limit
+    pub max_concurrent_outputs: Option<usize>,
+    /// Times a failed GPIO write is retried before giving up
+    pub gpio_write_retries: u32,
+    /// Milliseconds before the first retry of a failed GPIO write, doubling for each one after
+    pub gpio_retry_delay_ms: u64,
+    /// Seconds over which to spread the starts of timers sharing a start time
+    pub start_stagger_secs: u64,
+    /// Seconds an output may stay on before it is forced off, or `None` for no limit
+    pub max_on_duration_secs: Option<u64>,
+    /// Turn outputs which were on when the controller stopped back on for the rest of their
+    /// run, rather than off
+    pub resume_on_boot: bool,
+    /// Bring durations outside the allowed range to the nearest allowed one rather than
+    /// rejecting the timer
+    pub clamp_durations: bool,
+    /// Messages queued for the GPIO manager before senders wait for room
+    pub gpio_channel_capacity: usize,
+    /// Names for output pins, e.g. `"Front Lawn" = 476`, which may be given instead of the
+    /// pin's number
+    pub pin_aliases: HashMap<String, u16>,
+    /// `http://` URLs which each output change and failed write is POSTed to as JSON
+    pub webhooks: Vec<String>,
+    /// `http://` URL asked before each scheduled run whether to skip it, e.g. after rain
+    pub skip_check_url: Option<String>,
+    /// The unit durations are given and shown in as plain numbers, `minutes` or `seconds`
+    pub duration_unit: DurationUnit,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "0.0.0.0:3000".to_owned(),
+            db: None,
+            min_pin: 1,
+            max_pin: u16::MAX,
+            timezone: None,
+            simulate: false,
+            durable: false,
+            unique_names: false,
+            trash_retention_hours: 7 * 24,
+            rate_limit_per_minute: 60,
+            username: None,
+            password: None,
+            protect_reads: false,
+            audit_max_entries: 1000,
+            history_max_entries: 100,
+            max_concurrent_outputs: None,
+            gpio_write_retries: 3,
+            gpio_retry_delay_ms: 100,
+            start_stagger_secs: 0,
+            max_on_duration_secs: None,
+            resume_on_boot: false,
+            clamp_durations: false,
+            gpio_channel_capacity: DEFAULT_GPIO_CHANNEL_CAPACITY,
+            pin_aliases: HashMap::new(),
+            webhooks: Vec::new(),
+            skip_check_url: None,
+            duration_unit: DurationUnit::Minutes,
+        }
+    }
+}
+
+impl Config {
+    /// Load a config file, parsed as JSON if it has a `.json` extension and as TOML otherwise.
+    /// Settings missing from the file keep their defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| Error::Anyhow(e.into()))?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            toml::from_str(&contents).map_err(|e| Error::Anyhow(e.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_config_files_are_read_as_json() {
+        let path = std::env::temp_dir().join(format!("sploosh-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"durable": true, "pin_aliases": {"Front Lawn": 476}}"#,
+        )
+        .unwrap();
+        let loaded = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert!(loaded.durable);
+        assert_eq!(loaded.pin_aliases["Front Lawn"], 476);
+        assert_eq!(loaded.bind, Config::default().bind);
+    }
+
+    #[test]
+    fn a_missing_config_file_is_an_error() {
+        assert!(Config::load("/nonexistent/sploosh.toml").is_err());
+    }
+}
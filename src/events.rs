@@ -0,0 +1,173 @@
+//! Human-readable scheduler events streamed as server-sent events, which a browser dashboard
+//! can follow with a plain `EventSource` rather than a websocket
+use crate::{util::AppState, IntervalTimer};
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// How many events a slow client may fall behind by before it starts missing the oldest
+pub const SCHEDULER_EVENTS_CAPACITY: usize = 64;
+
+/// Broadcasts every scheduler event to each subscriber
+pub type SchedulerEvents = broadcast::Sender<SchedulerEvent>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulerEventKind {
+    /// A timer turned its output on, on schedule or by hand
+    Fired,
+    /// The skip check asked for a run to be skipped
+    Skipped,
+    /// Writing to a timer's output failed during a run
+    Failed,
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+impl SchedulerEventKind {
+    /// The name of the server-sent event, e.g. for `EventSource.addEventListener`
+    pub fn name(self) -> &'static str {
+        match self {
+            SchedulerEventKind::Fired => "fired",
+            SchedulerEventKind::Skipped => "skipped",
+            SchedulerEventKind::Failed => "failed",
+            SchedulerEventKind::Created => "created",
+            SchedulerEventKind::Updated => "updated",
+            SchedulerEventKind::Deleted => "deleted",
+            SchedulerEventKind::Restored => "restored",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchedulerEvent {
+    pub at: DateTime<Utc>,
+    pub kind: SchedulerEventKind,
+    pub timer_id: Uuid,
+    /// What happened, e.g. `Timer "Front lawn" turned on for 10 min`
+    pub message: String,
+}
+
+/// How a timer is referred to in event messages: by its name if it has one, else by its id
+pub fn timer_label(timer: &IntervalTimer) -> String {
+    match timer.name.as_deref() {
+        Some(name) if !name.is_empty() => format!("\"{}\"", name),
+        _ => timer.get_id().to_string(),
+    }
+}
+
+impl AppState {
+    /// Tell everyone following `GET /events` what happened to a timer, e.g. `what` of
+    /// `"was deleted"`. It's fine for nobody to be listening.
+    pub fn publish(&self, kind: SchedulerEventKind, timer_id: Uuid, label: &str, what: &str) {
+        let _ = self.scheduler_events.send(SchedulerEvent {
+            at: self.clock.now(),
+            kind,
+            timer_id,
+            message: format!("Timer {} {}", label, what),
+        });
+    }
+}
+
+/// Stream scheduler events to the client as they happen. Each is sent as a server-sent event
+/// named after its kind with the event as JSON data. A client which falls behind misses the
+/// oldest events, and is told how many with a comment, rather than holding up the scheduler.
+pub async fn scheduler_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = state.scheduler_events.subscribe();
+    let stream = stream::unfold(events, |mut events| async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event stream client missed {} scheduler events", missed);
+                    let comment = format!("missed {} events", missed);
+                    return Some((Ok(Event::default().comment(comment)), events));
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            match Event::default().event(event.kind.name()).json_data(&event) {
+                Ok(sse) => return Some((Ok(sse), events)),
+                Err(e) => error!("{}", e),
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::create_timer, auth::AuthUser, handlers::NewDaily};
+    use axum::{body::Body, extract::Json, http::Request, routing::get, Router};
+    use futures_util::StreamExt;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn follow(state: &AppState) -> axum::body::BodyDataStream {
+        let request = Request::get("/events").body(Body::empty()).unwrap();
+        let response = Router::new()
+            .route("/events", get(scheduler_events))
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        response.into_body().into_data_stream()
+    }
+
+    async fn next_frame(stream: &mut axum::body::BodyDataStream) -> String {
+        let frame = stream.next().await.unwrap().unwrap();
+        String::from_utf8(frame.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn creating_a_timer_is_streamed_to_followers() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tokio::sync::mpsc::channel(8).0);
+        let mut stream = follow(&state).await;
+
+        let n: NewDaily = serde_json::from_value(serde_json::json!({
+            "name": "Front lawn",
+            "duration_on": 10,
+            "output": 5,
+            "start_time": "06:00",
+        }))
+        .unwrap();
+        create_timer(State(state.clone()), AuthUser(None), Ok(Json(n)))
+            .await
+            .unwrap();
+        let frame = next_frame(&mut stream).await;
+        assert!(frame.starts_with("event: created\n"), "{:?}", frame);
+        let data = frame.lines().find_map(|line| line.strip_prefix("data: "));
+        let event: serde_json::Value = serde_json::from_str(data.unwrap()).unwrap();
+        assert_eq!(event["kind"], "created");
+        assert_eq!(event["message"], "Timer \"Front lawn\" was created");
+    }
+
+    #[tokio::test]
+    async fn a_client_which_falls_behind_is_told_what_it_missed() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tokio::sync::mpsc::channel(8).0);
+        let mut stream = follow(&state).await;
+        let id = Uuid::new_v4();
+        for n in 0..SCHEDULER_EVENTS_CAPACITY + 3 {
+            state.publish(SchedulerEventKind::Fired, id, "x", &n.to_string());
+        }
+
+        assert_eq!(next_frame(&mut stream).await, ": missed 3 events\n\n");
+        let frame = next_frame(&mut stream).await;
+        assert!(frame.contains("\"message\":\"Timer x 3\""), "{:?}", frame);
+    }
+}
@@ -1,57 +1,150 @@
 use crate::{
-    util::{naive_now, AppState, DailyTimer, GpioOutMessage, Layout},
-    Error, IntervalTimer,
+    api::{created_json, TimerPatch},
+    audit::AuditAction,
+    auth::AuthUser,
+    util::{format_duration, format_time_of_day, parse_time_of_day, AppState, Layout},
+    Error, IntervalTimer, Schedule,
 };
 use axum::{
-    extract::{Path, State},
-    response::Redirect,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
     Form,
 };
-use chrono::Duration;
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use std::{num::ParseIntError, str::FromStr, time::Duration};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Whether the client would rather have JSON than a page, judging by its `Accept` header
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html"))
+}
+
 #[axum::debug_handler]
 pub async fn new_daily_form(
     State(state): State<AppState>,
-    Form(n): Form<NewDaily>,
-) -> Result<Redirect, Error> {
+    user: AuthUser,
+    headers: HeaderMap,
+    Form(mut n): Form<NewDaily>,
+) -> Result<Response, Error> {
+    // Unchecked checkboxes aren't submitted at all
+    n.enabled = Some(n.enabled.unwrap_or(false));
+    state.fill_new_daily(&mut n);
     let timer = IntervalTimer::from_newdaily(n)?;
+    state.check_pin(timer.output)?;
     let prev = state.insert_interval_timer(&timer)?;
     info!(
         "Inserted timer {:?} into the database. Previous value: {:?}",
         &timer, &prev
     );
-    let timer = DailyTimer::new(
-        timer.settings.start_time.unwrap_or(naive_now()),
-        GpioOutMessage {
-            output: 476,
-            value: true,
-        }
-        .into(),
-        Duration::from_std(timer.settings.duration_on).unwrap(),
-        state.gpio_tx.clone(),
-    );
-    timer.run();
-
-    Ok(Redirect::to("/"))
+    state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+    state.spawn_interval_timer(&timer);
+    if wants_json(&headers) {
+        created_json(&timer, state.duration_unit)
+    } else {
+        Ok(Redirect::to(&format!("/timer/{}", timer.get_id())).into_response())
+    }
 }
 
 #[axum::debug_handler]
 pub async fn update_daily_form(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
-    Form(n): Form<NewDaily>,
+    user: AuthUser,
+    Form(mut n): Form<NewDaily>,
 ) -> Result<Redirect, Error> {
-    let mut timer = IntervalTimer::from_newdaily(n)?;
-    timer.id = id;
+    let Some(mut timer) = state.get_interval_timer(id)? else {
+        return Err(Error::NotFound(format!("Timer with ID {}", &id)));
+    };
+    state.fill_new_daily(&mut n);
+    // Only the fields on the form change, so that the schedule is kept. Unchecked checkboxes
+    // aren't submitted at all, and timers without a start time have no start time field.
+    let patch = TimerPatch {
+        name: Some(n.name),
+        description: n.description,
+        duration_on: Some(n.duration_on),
+        duration_unit: n.duration_unit,
+        output: Some(n.output),
+        output_alias: n.output_alias,
+        start_time: Some(n.start_time).filter(|time| !time.trim().is_empty()),
+        timezone: Some(n.timezone.unwrap_or_default()),
+        enabled: Some(n.enabled.unwrap_or(false)),
+        normally_on: Some(n.normally_on.unwrap_or(false)),
+        active_low: Some(n.active_low.unwrap_or(false)),
+    };
+    patch.apply(&mut timer)?;
+    state.check_pin(timer.output)?;
     let prev = state.insert_interval_timer(&timer)?;
     info!(
-        "Inserted timer {:?} into the database. Previous value: {:?}",
+        "Updated timer {:?} in the database. Previous value: {:?}",
         &timer, &prev
     );
-    Ok(Redirect::to("/"))
+    state.audit(AuditAction::Update, Some(id), &user);
+    // Replaces the task running the old schedule
+    state.spawn_interval_timer(&timer);
+    Ok(Redirect::to(&format!("/timer/{}", id)))
+}
+
+#[axum::debug_handler]
+pub async fn delete_timer(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Redirect, Error> {
+    match state.delete_interval_timer(id)? {
+        Some(timer) => {
+            state.cancel_timer(&timer.get_id());
+            info!("Deleted timer {:?} from the database.", &timer);
+            state.audit(AuditAction::Delete, Some(id), &user);
+            Ok(Redirect::to(&format!("/all_timers?deleted={}", id)))
+        }
+        None => Err(Error::NotFound(format!("Timer with ID {}", &id))),
+    }
+}
+
+/// Bring a deleted timer back from the trash and restart its schedule
+#[axum::debug_handler]
+pub async fn restore_timer(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Redirect, Error> {
+    match state.restore_interval_timer(id)? {
+        Some(timer) => {
+            state.spawn_interval_timer(&timer);
+            info!("Restored timer {:?} from the trash.", &timer);
+            state.audit(AuditAction::Restore, Some(id), &user);
+            Ok(Redirect::to(&format!("/timer/{}", id)))
+        }
+        None => Err(Error::NotFound(format!("Deleted timer with ID {}", &id))),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn enable_timer(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Redirect, Error> {
+    state.set_timer_enabled(id, true)?;
+    state.audit(AuditAction::Update, Some(id), &user);
+    Ok(Redirect::to(&format!("/timer/{}", id)))
+}
+
+#[axum::debug_handler]
+pub async fn disable_timer(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Redirect, Error> {
+    state.set_timer_enabled(id, false)?;
+    state.audit(AuditAction::Update, Some(id), &user);
+    Ok(Redirect::to(&format!("/timer/{}", id)))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,14 +152,200 @@ pub struct NewDaily {
     /// The name of the new timer
     pub name: String,
     pub description: Option<String>,
-    /// Duration in seconds
+    /// Duration in `duration_unit`
     pub duration_on: u32,
-    /// Time of day to run, in %H:%M format
+    /// The unit `duration_on` is in, which is the server's configured unit rather than
+    /// anything submitted
+    #[serde(skip)]
+    pub duration_unit: DurationUnit,
+    /// The GPIO output pin to control, by number or by one of the configured aliases
+    pub output: OutputRef,
+    /// The alias `output` was given as, once it has been resolved to a pin
+    #[serde(skip)]
+    pub output_alias: Option<String>,
+    /// Time of day to run, in %H:%M or %H:%M:%S format
+    #[serde(default)]
     pub start_time: String,
+    /// IANA name of the timezone the start time is in, e.g. `America/New_York`. Defaults to the
+    /// server's local timezone.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Whether the timer should run. Defaults to enabled.
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Whether the output is on outside the timer's window and off during it. Defaults to off.
+    #[serde(default)]
+    pub normally_on: Option<bool>,
+    /// Whether the output's relay turns the load on when driven low. Defaults to active-high.
+    #[serde(default)]
+    pub active_low: Option<bool>,
+}
+
+/// An output pin as submitted for a new timer, either by number or by alias, e.g. "Front Lawn"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OutputRef {
+    Pin(u16),
+    Alias(String),
+}
+
+impl OutputRef {
+    /// The pin, if given by number. Forms submit every field as text, so numeric aliases
+    /// count as pins.
+    pub fn pin(&self) -> Option<u16> {
+        match self {
+            OutputRef::Pin(pin) => Some(*pin),
+            OutputRef::Alias(alias) => alias.trim().parse().ok(),
+        }
+    }
+}
+
+impl From<u16> for OutputRef {
+    fn from(pin: u16) -> Self {
+        OutputRef::Pin(pin)
+    }
+}
+
+/// The longest a daily timer can be on for, in minutes
+pub const MAX_DURATION_MINS: u32 = 24 * 60 - 1;
+
+/// The unit durations are given and shown in as plain numbers, e.g. a timer's `duration_on` in
+/// forms, the API and CSV
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationUnit {
+    #[default]
+    Minutes,
+    Seconds,
+}
+
+impl DurationUnit {
+    /// Seconds in one of the unit
+    pub fn secs(self) -> u64 {
+        match self {
+            DurationUnit::Minutes => 60,
+            DurationUnit::Seconds => 1,
+        }
+    }
+
+    /// `value` of the unit as a duration
+    pub fn duration(self, value: u32) -> Duration {
+        Duration::from_secs(u64::from(value) * self.secs())
+    }
+
+    /// A duration as a whole number of the unit, rounded down
+    pub fn value(self, duration: Duration) -> u64 {
+        duration.as_secs() / self.secs()
+    }
+
+    /// Parse a whole number of the unit, e.g. "10" is ten minutes in minutes and ten seconds
+    /// in seconds
+    pub fn parse(self, value: &str) -> Result<Duration, ParseIntError> {
+        Ok(self.duration(value.trim().parse()?))
+    }
+
+    /// The longest a daily timer can be on for, in the unit
+    pub fn max_duration(self) -> u32 {
+        MAX_DURATION_MINS * (60 / self.secs()) as u32
+    }
+
+    /// The unit's name for messages, e.g. "minutes"
+    pub fn name(self) -> &'static str {
+        match self {
+            DurationUnit::Minutes => "minutes",
+            DurationUnit::Seconds => "seconds",
+        }
+    }
+
+    /// The unit's name abbreviated for form labels, e.g. "mins"
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            DurationUnit::Minutes => "mins",
+            DurationUnit::Seconds => "secs",
+        }
+    }
+}
+
+/// The nearest duration in `unit` which a daily timer can be on for
+pub fn clamp_duration(value: u32, unit: DurationUnit) -> u32 {
+    value.clamp(1, unit.max_duration())
+}
+
+impl NewDaily {
+    /// Use the given timezone if none was submitted
+    pub fn or_timezone(&mut self, tz: Option<Tz>) {
+        let blank = self
+            .timezone
+            .as_deref()
+            .is_none_or(|name| name.trim().is_empty());
+        if let (true, Some(tz)) = (blank, tz) {
+            self.timezone = Some(tz.name().to_owned());
+        }
+    }
+
+    /// How long the timer is on for
+    pub fn duration(&self) -> Duration {
+        self.duration_unit.duration(self.duration_on)
+    }
+
+    /// Bring `duration_on` within the allowed range rather than rejecting it
+    pub fn clamp_duration(&mut self) {
+        let clamped = clamp_duration(self.duration_on, self.duration_unit);
+        if clamped != self.duration_on {
+            warn!(
+                "Clamped duration of timer {:?} from {} to {} {}",
+                self.name,
+                self.duration_on,
+                clamped,
+                self.duration_unit.name()
+            );
+            self.duration_on = clamped;
+        }
+    }
+
+    /// Check every field, collecting the problems with all of them rather than stopping at
+    /// the first
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push("name: must not be empty".to_owned());
+        }
+        let unit = self.duration_unit;
+        if !(1..=unit.max_duration()).contains(&self.duration_on) {
+            problems.push(format!(
+                "duration_on: must be between 1 and {} {}",
+                unit.max_duration(),
+                unit.name()
+            ));
+        }
+        match (&self.output, self.output.pin()) {
+            (_, Some(0)) => problems.push("output: must not be 0".to_owned()),
+            (OutputRef::Alias(alias), None) => {
+                problems.push(format!("output: unknown pin alias {:?}", alias.trim()))
+            }
+            _ => {}
+        }
+        if parse_time_of_day(&self.start_time).is_err() {
+            problems.push("start_time: must be in HH:MM or HH:MM:SS format".to_owned());
+        }
+        if let Some(name) = self.timezone.as_deref().map(str::trim) {
+            if !name.is_empty() && Tz::from_str(name).is_err() {
+                problems.push(format!("timezone: unknown timezone {}", name));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(problems))
+        }
+    }
 }
 
 #[axum::debug_handler]
-pub async fn new_timer() -> impl axum::response::IntoResponse {
+pub async fn new_timer(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    let unit = state.duration_unit;
     let template = Layout {
         head: markup::new! {
             title { "Home" }
@@ -87,11 +366,26 @@ pub async fn new_timer() -> impl axum::response::IntoResponse {
                             textarea[id = "description", name = "description", rows = 7] {}
                         }
                         div .six.columns {
-                            label[for = "duration_on"] { "Duration (mins)" }
-                            input[id = "duration_ob", name = "duration_on", type = "number", required];
+                            label[for = "duration_on"] { "Duration (" @unit.abbreviation() ")" }
+                            input[id = "duration_on", name = "duration_on", type = "number", min = 1, max = unit.max_duration(), required];
                             label[for = "start_time"] { "Start Time" }
-                            input[id = "start_time", name = "start_time", type = "time", required];
-                            br {}
+                            input[id = "start_time", name = "start_time", type = "time", step = 1, required];
+                            label[for = "output"] { "Output Pin" }
+                            input[id = "output", name = "output", type = "text", placeholder = "Pin number or alias", required];
+                            label[for = "timezone"] { "Timezone" }
+                            input[id = "timezone", name = "timezone", type = "text", placeholder = "Server local time"];
+                            label[for = "enabled"] {
+                                input[id = "enabled", name = "enabled", type = "checkbox", value = "true", checked];
+                                span ."label-body" { "Enabled" }
+                            }
+                            label[for = "normally_on"] {
+                                input[id = "normally_on", name = "normally_on", type = "checkbox", value = "true"];
+                                span ."label-body" { "Normally on (off during the window)" }
+                            }
+                            label[for = "active_low"] {
+                                input[id = "active_low", name = "active_low", type = "checkbox", value = "true"];
+                                span ."label-body" { "Active low (relay switches on when the pin is low)" }
+                            }
                             button[type = "submit"] { "Submit" }
                         }
                     }
@@ -102,9 +396,138 @@ pub async fn new_timer() -> impl axum::response::IntoResponse {
     axum::response::Html(template.to_string())
 }
 
+/// When the timer next runs, e.g. "in 3h 5m", or a dash if it has no fixed start time
+fn next_run(timer: &IntervalTimer) -> String {
+    match timer.time_until_next_fire() {
+        Some(until) => format!("in {}h {}m", until.num_hours(), until.num_minutes() % 60),
+        None => "-".to_owned(),
+    }
+}
+
+/// A timer's start time, along with when it actually starts if that's staggered
+fn start_time_label(timer: &IntervalTimer, stagger: std::time::Duration) -> String {
+    let Some(start) = timer.settings().start_time() else {
+        return String::new();
+    };
+    match timer.effective_start_time(stagger) {
+        Some(effective) if effective != start => format!(
+            "{} (starts {})",
+            format_time_of_day(start),
+            format_time_of_day(effective)
+        ),
+        _ => format_time_of_day(start),
+    }
+}
+
+/// The most timers shown on one page
+pub const MAX_PER_PAGE: usize = 100;
+pub const DEFAULT_PER_PAGE: usize = 25;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    /// Page to show, starting at 1
+    pub page: Option<usize>,
+    /// Timers per page, at most `MAX_PER_PAGE`
+    pub per_page: Option<usize>,
+}
+
+/// Filters for the timer lists. Every filter given must match.
+#[derive(Debug, Default, Deserialize)]
+pub struct TimerFilter {
+    /// Only timers whose name contains this, ignoring case
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub name_contains: Option<String>,
+    /// Only timers controlling this output
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub pin: Option<u16>,
+}
+
+impl TimerFilter {
+    pub fn is_empty(&self) -> bool {
+        self.name_contains.is_none() && self.pin.is_none()
+    }
+
+    pub fn matches(&self, timer: &IntervalTimer) -> bool {
+        let name_matches = match &self.name_contains {
+            Some(needle) => timer
+                .name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&needle.to_lowercase())),
+            None => true,
+        };
+        let pin_matches = match self.pin {
+            Some(pin) => timer.output == pin,
+            None => true,
+        };
+        name_matches && pin_matches
+    }
+
+    /// The filters as query parameters to append to a link, starting with `&`
+    fn query(&self) -> String {
+        let mut query = String::new();
+        if let Some(name) = &self.name_contains {
+            query.push_str("&name_contains=");
+            for byte in name.bytes() {
+                if byte.is_ascii_alphanumeric() {
+                    query.push(byte as char);
+                } else {
+                    query.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+        if let Some(pin) = self.pin {
+            query.push_str(&format!("&pin={}", pin));
+        }
+        query
+    }
+}
+
+/// Set after a delete, so that the list can offer to undo it
+#[derive(Debug, Default, Deserialize)]
+pub struct DeletedParams {
+    pub deleted: Option<Uuid>,
+}
+
+/// Treat an empty query parameter, as sent by a blank form field, as if it were missing
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+impl PageParams {
+    pub fn page(&self) -> usize {
+        self.page.unwrap_or(1).max(1)
+    }
+    pub fn per_page(&self) -> usize {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+    /// The number of timers before the current page
+    pub fn offset(&self) -> usize {
+        (self.page() - 1).saturating_mul(self.per_page())
+    }
+}
+
 #[axum::debug_handler]
-pub async fn alltimers(State(state): State<AppState>) -> impl axum::response::IntoResponse {
-    let all = state.get_all_interval_timers()?;
+pub async fn alltimers(
+    State(state): State<AppState>,
+    Query(params): Query<PageParams>,
+    Query(filter): Query<TimerFilter>,
+    Query(DeletedParams { deleted }): Query<DeletedParams>,
+) -> impl axum::response::IntoResponse {
+    let (page, per_page) = (params.page(), params.per_page());
+    let (all, total) = state.find_interval_timers(&filter, params.offset(), per_page)?;
+    let pages = total.div_ceil(per_page).max(1);
+    let filter_query = filter.query();
     let template = Layout {
         head: markup::new! {
             title { "All Timers" }
@@ -116,6 +539,30 @@ pub async fn alltimers(State(state): State<AppState>) -> impl axum::response::In
                         h1 { "All Timers" }
                     }
                 }
+                @if let Some(id) = deleted {
+                    div .row {
+                        form[action = format!("/timer/{}/restore", id), method = "post"] {
+                            "Timer deleted. "
+                            input[type = "submit", value = "Undo"];
+                        }
+                    }
+                }
+                form[action = "/all_timers", method = "get"] {
+                    div .row {
+                        div .six.columns {
+                            label[for = "name_contains"] { "Name contains" }
+                            input ."u-full-width"[id = "name_contains", name = "name_contains", type = "text", value = filter.name_contains.clone()];
+                        }
+                        div .three.columns {
+                            label[for = "pin"] { "Output" }
+                            input ."u-full-width"[id = "pin", name = "pin", type = "number", min = 1, value = filter.pin];
+                        }
+                        div .three.columns {
+                            label { "\u{a0}" }
+                            input ."button-primary"[type = "submit", value = "Search"];
+                        }
+                    }
+                }
                 table ."u-full-width" {
                     thead {
                         tr {
@@ -123,6 +570,11 @@ pub async fn alltimers(State(state): State<AppState>) -> impl axum::response::In
                             th {"Description"}
                             th {"Duration"}
                             th {"Start Time"}
+                            th {"Output"}
+                            th {"Enabled"}
+                            th {"Next Run"}
+                            th {"Created"}
+                            th {"Updated"}
                         }
                     }
                     tbody {
@@ -132,20 +584,62 @@ pub async fn alltimers(State(state): State<AppState>) -> impl axum::response::In
                                     a [href=format!("/timer/{}", t.id)] { @t.name }
                                 }
                                 td { @t.description}
-                                td { @format!("{:?}", t.settings.duration_on)}
-                                td { @t.settings.start_time.unwrap_or_default().to_string()}
+                                td { @format_duration(t.settings().duration_on()) }
+                                td { @start_time_label(t, state.start_stagger) }
+                                td { @t.output_label() }
+                                td { @if t.enabled { "Yes" } else { "No" } }
+                                td { @next_run(t) }
+                                td { @t.created_at.format("%Y-%m-%d %H:%M").to_string() }
+                                td { @t.updated_at.format("%Y-%m-%d %H:%M").to_string() }
                             }
                         }
                     }
                 }
+                div .row {
+                    @if page > 1 {
+                        a[href = format!("/all_timers?page={}&per_page={}{}", page - 1, per_page, filter_query)] { "Previous" }
+                        " "
+                    }
+                    "Page " @page " of " @pages
+                    @if page < pages {
+                        " "
+                        a[href = format!("/all_timers?page={}&per_page={}{}", page + 1, per_page, filter_query)] { "Next" }
+                    }
+                }
             }
         },
     };
     Result::<_, Error>::Ok(axum::response::Html(template.to_string()))
 }
 
+/// Stylesheets embedded in the binary, served as files of their own so that browsers cache
+/// them rather than downloading them with every page
+const NORMALIZE_CSS: &str = include_str!("../static/css/normalize.css");
+const SKELETON_CSS: &str = include_str!("../static/css/skeleton.css");
+
+/// The stylesheets only change along with the binary, so they can be cached for a while
+const CSS_CACHE_CONTROL: &str = "public, max-age=604800";
+
+fn stylesheet(css: &'static str) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+            (header::CACHE_CONTROL, CSS_CACHE_CONTROL),
+        ],
+        css,
+    )
+}
+
+pub async fn normalize_css() -> impl IntoResponse {
+    stylesheet(NORMALIZE_CSS)
+}
+
+pub async fn skeleton_css() -> impl IntoResponse {
+    stylesheet(SKELETON_CSS)
+}
+
 #[axum::debug_handler]
-pub async fn root(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+pub async fn root(State(_state): State<AppState>) -> impl axum::response::IntoResponse {
     let template = Layout {
         head: markup::new! {
             title { "Homepage" }
@@ -169,7 +663,8 @@ pub async fn view_timer(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
 ) -> impl axum::response::IntoResponse {
-    if let Some(timer) = state.get_interval_timer(&id)? {
+    if let Some(timer) = state.get_interval_timer(id)? {
+        let unit = state.duration_unit;
         let template = Layout {
             head: markup::new! {
                 title { "Timer" }
@@ -180,6 +675,38 @@ pub async fn view_timer(
                         div .twelve.columns {
                             h1 { @timer.name }
                             p { @timer.description}
+                            p { "Starts at " @start_time_label(&timer, state.start_stagger) }
+                            p {
+                                @if timer.normally_on { "Turns off for " } else { "Runs for " }
+                                @format_duration(timer.settings().duration_on())
+                            }
+                            p { "Output " @timer.output_label() }
+                            p { "Next run " @next_run(&timer) }
+                            p {
+                                "Last ran: "
+                                @if let Some(at) = timer.last_fired_at {
+                                    @at.format("%Y-%m-%d %H:%M").to_string()
+                                } else {
+                                    "never"
+                                }
+                            }
+                            p {
+                                "Fired " @timer.fire_count
+                                @if timer.fire_count == 1 { " time" } else { " times" }
+                            }
+                            p {
+                                "Created " @timer.created_at.format("%Y-%m-%d %H:%M").to_string()
+                                ", last updated " @timer.updated_at.format("%Y-%m-%d %H:%M").to_string()
+                            }
+                            @if timer.enabled {
+                                form[action = format!("/timer/{}/disable", timer.id), method = "post"] {
+                                    button[type = "submit"] { "Disable" }
+                                }
+                            } else {
+                                form[action = format!("/timer/{}/enable", timer.id), method = "post"] {
+                                    button[type = "submit"] { "Enable" }
+                                }
+                            }
                         }
                     }
                 form[action = format!("/new_submit/{}",timer.id), method = "post"] {
@@ -188,14 +715,31 @@ pub async fn view_timer(
                                 label[for = "name"] { "Name" }
                                 input[id = "name", name = "name", type = "text", value = timer.name.clone(), required];
                                 label[for = "Description"] { "Description" }
-                                textarea[id = "description", name = "description", rows = 7, value = timer.description.clone() ] {}
+                                textarea[id = "description", name = "description", rows = 7] { @timer.description }
                             }
                             div .six.columns {
-                                label[for = "duration_on"] { "Duration (mins)" }
-                                input[id = "duration_ob", name = "duration_on", type = "number", value = timer.settings.duration_on.as_secs(), required];
-                                label[for = "start_time"] { "Start Time" }
-                                input[id = "start_time", name = "start_time", type = "time", value = timer.settings.start_time.unwrap().format("%-I:%M %p").to_string(), required];
-                                br {}
+                                label[for = "duration_on"] { "Duration (" @unit.abbreviation() ")" }
+                                input[id = "duration_on", name = "duration_on", type = "number", min = 1, max = unit.max_duration(), value = unit.value(timer.settings().duration_on()), required];
+                                @if matches!(timer.settings().schedule(), Schedule::Daily | Schedule::Weekly(_)) {
+                                    label[for = "start_time"] { "Start Time" }
+                                    input[id = "start_time", name = "start_time", type = "time", step = 1, value = timer.settings().start_time().map(format_time_of_day), required];
+                                }
+                                label[for = "output"] { "Output Pin" }
+                                input[id = "output", name = "output", type = "text", placeholder = "Pin number or alias", value = timer.output_alias.clone().unwrap_or_else(|| timer.output.to_string()), required];
+                                label[for = "timezone"] { "Timezone" }
+                                input[id = "timezone", name = "timezone", type = "text", value = timer.settings().timezone().map(|tz| tz.name().to_owned()), placeholder = "Server local time"];
+                                label[for = "enabled"] {
+                                    input[id = "enabled", name = "enabled", type = "checkbox", value = "true", checked = timer.enabled];
+                                    span ."label-body" { "Enabled" }
+                                }
+                                label[for = "normally_on"] {
+                                    input[id = "normally_on", name = "normally_on", type = "checkbox", value = "true", checked = timer.normally_on];
+                                    span ."label-body" { "Normally on (off during the window)" }
+                                }
+                                label[for = "active_low"] {
+                                    input[id = "active_low", name = "active_low", type = "checkbox", value = "true", checked = timer.active_low];
+                                    span ."label-body" { "Active low (relay switches on when the pin is low)" }
+                                }
                                 button[type = "submit"] { "Save" }
                             }
                         }
@@ -208,3 +752,407 @@ pub async fn view_timer(
         Err(Error::NotFound(format!("Timer with ID {}", &id)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+    use std::sync::Arc;
+
+    fn app() -> AppState {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        AppState::new(Arc::new(db), tokio::sync::mpsc::channel(8).0)
+    }
+
+    /// Store a daily timer at 06:00 on the default output
+    fn stored(state: &AppState, name: &str) -> IntervalTimer {
+        let start = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let timer =
+            IntervalTimer::once_daily(Some(name.to_owned()), None, Duration::from_secs(60), start)
+                .unwrap();
+        state.insert_interval_timer(&timer).unwrap();
+        timer
+    }
+
+    fn location(response: impl IntoResponse) -> String {
+        let response = response.into_response();
+        assert!(response.status().is_redirection());
+        let location = response.headers().get(header::LOCATION).unwrap();
+        location.to_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn deleting_moves_the_timer_to_the_trash_and_back() {
+        let state = app();
+        let id = stored(&state, "lawn").get_id();
+        let user = AuthUser(Some("ann".to_owned()));
+
+        let deleted = delete_timer(Path(id), State(state.clone()), user.clone()).await;
+        assert_eq!(
+            location(deleted.unwrap()),
+            format!("/all_timers?deleted={}", id)
+        );
+        assert!(state.get_interval_timer(id).unwrap().is_none());
+        let audit = state.recent_audit_entries(1).unwrap();
+        assert_eq!(audit[0].action, AuditAction::Delete);
+
+        let restored = restore_timer(Path(id), State(state.clone()), user).await;
+        assert_eq!(location(restored.unwrap()), format!("/timer/{}", id));
+        assert!(state.get_interval_timer(id).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn deleting_an_unknown_timer_is_not_found() {
+        let state = app();
+        let err = delete_timer(Path(Uuid::new_v4()), State(state.clone()), AuthUser(None))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), axum::http::StatusCode::NOT_FOUND);
+        assert!(state.recent_audit_entries(1).unwrap().is_empty());
+    }
+
+    /// Decode a form as the browser would submit it
+    async fn form(body: &'static str) -> Form<NewDaily> {
+        use axum::extract::FromRequest;
+        let request = axum::http::Request::post("/")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        Form::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn editing_keeps_the_schedule_and_applies_the_form() {
+        let state = app();
+        let start = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let days = vec![chrono::Weekday::Tue, chrono::Weekday::Sat];
+        let settings =
+            crate::IntervalSettings::weekly(days.clone(), Duration::from_secs(60), start).unwrap();
+        let description = Some("along the fence".to_owned());
+        let timer = IntervalTimer::new(Some("beds".to_owned()), description.clone(), settings);
+        state.insert_interval_timer(&timer).unwrap();
+        let id = timer.get_id();
+
+        // The enabled checkbox is left unchecked, so it isn't submitted
+        let edit = form(
+            "name=borders&description=along+the+fence&duration_on=25&output=9&start_time=07%3A15",
+        )
+        .await;
+        let response = update_daily_form(Path(id), State(state.clone()), AuthUser(None), edit);
+        assert_eq!(location(response.await.unwrap()), format!("/timer/{}", id));
+
+        let edited = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(edited.name.as_deref(), Some("borders"));
+        assert_eq!(edited.description, description);
+        assert_eq!(edited.output, 9);
+        assert!(!edited.enabled);
+        let settings = edited.settings();
+        assert_eq!(settings.schedule(), &Schedule::Weekly(days));
+        assert_eq!(settings.start_time(), NaiveTime::from_hms_opt(7, 15, 0));
+        assert_eq!(settings.duration_on(), state.duration_unit.duration(25));
+
+        // Emptying the textarea removes the description
+        let edit = form("name=borders&description=&duration_on=25&output=9").await;
+        let response = update_daily_form(Path(id), State(state.clone()), AuthUser(None), edit);
+        assert_eq!(location(response.await.unwrap()), format!("/timer/{}", id));
+        let edited = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(edited.description, None);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_edit_leaves_the_timer_alone() {
+        let state = app();
+        let timer = stored(&state, "lawn");
+        let edit = form("name=&duration_on=0&output=9&start_time=06%3A00").await;
+        let response = update_daily_form(
+            Path(timer.get_id()),
+            State(state.clone()),
+            AuthUser(None),
+            edit,
+        );
+        let Error::Validation(problems) = response.await.unwrap_err() else {
+            panic!("expected a validation error");
+        };
+        assert!(problems.iter().any(|p| p.starts_with("name:")));
+        assert!(problems.iter().any(|p| p.starts_with("duration_on:")));
+        let unchanged = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(unchanged.name.as_deref(), Some("lawn"));
+    }
+
+    async fn create(state: &AppState, body: &'static str) -> Result<IntervalTimer, Error> {
+        let response = new_daily_form(
+            State(state.clone()),
+            AuthUser(None),
+            HeaderMap::new(),
+            form(body).await,
+        )
+        .await?;
+        let id = location(response)
+            .trim_start_matches("/timer/")
+            .parse()
+            .unwrap();
+        Ok(state.get_interval_timer(id)?.unwrap())
+    }
+
+    #[tokio::test]
+    async fn timers_keep_the_timezone_they_are_given_or_the_default() {
+        let state = app().with_default_timezone(Some(chrono_tz::Europe::Paris));
+        let body = "name=a&duration_on=5&output=3&start_time=06%3A00&timezone=Asia%2FTokyo";
+        let tokyo = create(&state, body).await.unwrap();
+        assert_eq!(tokyo.settings().timezone(), Some(chrono_tz::Asia::Tokyo));
+
+        let body = "name=b&duration_on=5&output=3&start_time=06%3A00&timezone=";
+        let paris = create(&state, body).await.unwrap();
+        assert_eq!(paris.settings().timezone(), Some(chrono_tz::Europe::Paris));
+    }
+
+    #[tokio::test]
+    async fn an_unknown_timezone_is_rejected() {
+        let state = app();
+        let body = "name=a&duration_on=5&output=3&start_time=06%3A00&timezone=Mars%2FOlympus";
+        let err = create(&state, body).await.unwrap_err();
+        assert_eq!(err.status(), axum::http::StatusCode::BAD_REQUEST);
+        let Error::Validation(problems) = err else {
+            panic!("expected a validation error, got {:?}", err);
+        };
+        assert_eq!(problems, ["timezone: unknown timezone Mars/Olympus"]);
+        assert_eq!(state.count_interval_timers(), 0);
+    }
+
+    #[tokio::test]
+    async fn each_timer_drives_the_output_it_was_given() {
+        let state = app().with_allowed_pins(1..=40);
+        let body = "name=a&duration_on=5&output=12&start_time=06%3A00";
+        assert_eq!(create(&state, body).await.unwrap().output, 12);
+
+        let body = "name=b&duration_on=5&output=41&start_time=06%3A00";
+        let err = create(&state, body).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidPin(41)), "{:?}", err);
+        assert_eq!(err.status(), axum::http::StatusCode::BAD_REQUEST);
+
+        let body = "name=c&duration_on=5&output=0&start_time=06%3A00";
+        let Error::Validation(problems) = create(&state, body).await.unwrap_err() else {
+            panic!("expected a validation error");
+        };
+        assert_eq!(problems, ["output: must not be 0"]);
+        assert_eq!(state.count_interval_timers(), 1);
+    }
+
+    #[tokio::test]
+    async fn disabling_keeps_the_timer_and_its_task() {
+        let state = app();
+        let id = stored(&state, "lawn").get_id();
+        let toggle = |enabled: bool| {
+            let (state, user) = (State(state.clone()), AuthUser(None));
+            async move {
+                let response = match enabled {
+                    true => enable_timer(Path(id), state, user).await,
+                    false => disable_timer(Path(id), state, user).await,
+                };
+                response.map(location)
+            }
+        };
+
+        assert_eq!(toggle(true).await.unwrap(), format!("/timer/{}", id));
+        assert_eq!(state.running_timer_ids(), [id]);
+        toggle(false).await.unwrap();
+        assert!(!state.get_interval_timer(id).unwrap().unwrap().enabled);
+        assert_eq!(state.running_timer_ids(), [id]);
+        toggle(true).await.unwrap();
+        assert!(state.get_interval_timer(id).unwrap().unwrap().enabled);
+        assert_eq!(state.running_timer_ids(), [id]);
+
+        let missing = disable_timer(Path(Uuid::new_v4()), State(state), AuthUser(None)).await;
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn form_durations_are_stored_in_the_configured_unit() {
+        let body = "name=a&duration_on=10&output=3&start_time=06%3A00";
+        let minutes = create(&app(), body).await.unwrap();
+        assert_eq!(minutes.settings().duration_on(), Duration::from_secs(600));
+
+        let state = app().with_duration_unit(DurationUnit::Seconds);
+        let seconds = create(&state, body).await.unwrap();
+        assert_eq!(seconds.settings().duration_on(), Duration::from_secs(10));
+        // The form shows the stored duration back in the same unit
+        assert_eq!(
+            DurationUnit::Seconds.value(seconds.settings().duration_on()),
+            10
+        );
+        assert_eq!(
+            DurationUnit::Minutes.value(minutes.settings().duration_on()),
+            10
+        );
+    }
+
+    #[tokio::test]
+    async fn viewing_a_timer_without_a_start_time_renders() {
+        let state = app();
+        let unset = crate::IntervalSettings::new(Duration::from_secs(60), Duration::ZERO, None);
+        let cycling =
+            crate::IntervalSettings::cycling(Duration::from_secs(60), Duration::from_secs(300))
+                .unwrap();
+        for settings in [unset, cycling] {
+            let timer = IntervalTimer::new(Some("drip".to_owned()), None, settings);
+            state.insert_interval_timer(&timer).unwrap();
+            assert_eq!(
+                start_time_label(&timer, std::time::Duration::ZERO),
+                String::new()
+            );
+            let response = view_timer(Path(timer.get_id()), State(state.clone()))
+                .await
+                .into_response();
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_bad_start_time_is_the_clients_fault() {
+        let state = app();
+        for body in [
+            "name=a&duration_on=10&output=3&start_time=noon",
+            "name=a&duration_on=10&output=3&start_time=25%3A00",
+        ] {
+            let response = create(&state, body).await.unwrap_err().into_response();
+            assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        }
+        assert!(state.get_all_interval_timers().unwrap().is_empty());
+    }
+
+    fn problems(n: &NewDaily) -> Vec<String> {
+        match n.validate() {
+            Ok(()) => Vec::new(),
+            Err(Error::Validation(problems)) => problems,
+            Err(e) => panic!("expected a validation error, got {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn new_daily_validation_names_each_bad_field() {
+        let Form(mut n) = form("name=lawn&duration_on=10&output=3&start_time=06%3A00").await;
+        assert!(problems(&n).is_empty());
+
+        n.name = "   ".to_owned();
+        assert_eq!(problems(&n), ["name: must not be empty"]);
+
+        n.name = "lawn".to_owned();
+        n.duration_on = 0;
+        assert_eq!(problems(&n).len(), 1);
+        assert!(problems(&n)[0].starts_with("duration_on:"));
+        n.duration_on = DurationUnit::Minutes.max_duration() + 1;
+        assert!(problems(&n)[0].starts_with("duration_on:"));
+        n.duration_on = DurationUnit::Minutes.max_duration();
+        assert!(problems(&n).is_empty());
+
+        // Every problem is reported at once, so the form can show them together
+        let Form(n) = form("name=&duration_on=0&output=3&start_time=later").await;
+        let fields: Vec<_> = problems(&n)
+            .iter()
+            .map(|p| p.split(':').next().unwrap().to_owned())
+            .collect();
+        assert_eq!(fields, ["name", "duration_on", "start_time"]);
+    }
+
+    #[tokio::test]
+    async fn json_clients_get_the_created_timer_and_its_location() {
+        let state = app();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+        let body = "name=hedge&duration_on=5&output=3&start_time=19%3A00";
+        let response = new_daily_form(
+            State(state.clone()),
+            AuthUser(None),
+            headers,
+            form(body).await,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::CREATED);
+        let location_header = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id: Uuid = created["id"].as_str().unwrap().parse().unwrap();
+        assert_eq!(location_header, format!("/timer/{}", id));
+        let stored = state.get_interval_timer(id).unwrap().unwrap();
+        assert_eq!(stored.name.as_deref(), Some("hedge"));
+
+        // Browsers, which accept HTML as well, are still redirected
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            "text/html,application/json".parse().unwrap(),
+        );
+        let body = "name=beds&duration_on=5&output=3&start_time=19%3A00";
+        let response = new_daily_form(State(state), AuthUser(None), headers, form(body).await)
+            .await
+            .unwrap();
+        assert!(location(response).starts_with("/timer/"));
+    }
+
+    #[tokio::test]
+    async fn start_times_keep_their_seconds() {
+        let state = app();
+        let precise = create(
+            &state,
+            "name=a&duration_on=1&output=3&start_time=06%3A30%3A15",
+        )
+        .await
+        .unwrap();
+        let start = precise.settings().start_time().unwrap();
+        assert_eq!(start, NaiveTime::from_hms_opt(6, 30, 15).unwrap());
+        assert_eq!(format_time_of_day(start), "06:30:15");
+
+        let whole = create(&state, "name=b&duration_on=1&output=3&start_time=06%3A30")
+            .await
+            .unwrap();
+        let start = whole.settings().start_time().unwrap();
+        assert_eq!(start, NaiveTime::from_hms_opt(6, 30, 0).unwrap());
+        // Seconds are only shown when there are some
+        assert_eq!(format_time_of_day(start), "06:30");
+    }
+
+    #[tokio::test]
+    async fn lenient_servers_clamp_durations_out_of_range() {
+        let lenient = app().with_clamp_durations(true);
+        let too_long = "name=long&duration_on=5000&output=3&start_time=06%3A00";
+        let too_short = "name=short&duration_on=0&output=3&start_time=06%3A00";
+
+        let timer = create(&lenient, too_long).await.unwrap();
+        let longest = Duration::from_secs(u64::from(MAX_DURATION_MINS) * 60);
+        assert_eq!(timer.settings().duration_on(), longest);
+        let timer = create(&lenient, too_short).await.unwrap();
+        assert_eq!(timer.settings().duration_on(), Duration::from_secs(60));
+
+        let strict = app();
+        for body in [too_long, too_short] {
+            let err = create(&strict, body).await.unwrap_err();
+            assert!(matches!(err, Error::Validation(_)), "{:?}", err);
+        }
+        assert!(strict.get_all_interval_timers().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stylesheets_are_served_as_cacheable_css() {
+        for (response, css) in [
+            (normalize_css().await.into_response(), NORMALIZE_CSS),
+            (skeleton_css().await.into_response(), SKELETON_CSS),
+        ] {
+            assert_eq!(response.status(), axum::http::StatusCode::OK);
+            let headers = response.headers();
+            assert_eq!(headers[header::CONTENT_TYPE], "text/css; charset=utf-8");
+            assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=604800");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(!css.is_empty());
+            assert_eq!(body, css.as_bytes());
+        }
+    }
+}
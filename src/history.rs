@@ -0,0 +1,273 @@
+//! A bounded log of each timer's runs, so that users can see the schedule is working and have a
+//! trail to follow when it isn't
+use crate::{
+    api::{etag_of, not_modified_or},
+    events::{timer_label, SchedulerEventKind},
+    util::AppState,
+    Error,
+};
+use axum::{
+    extract::{rejection::PathRejection, Path, Query, State},
+    http::HeaderMap,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// Name of the sled tree holding the fire log, keyed by timer id then big-endian sequence
+/// number so that each timer's entries are together in the order they were made
+const FIRE_LOG: &str = "fire_log";
+
+/// Entries returned by `GET /timer/:id/history` when no limit is given
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// How long after a run was due to end a failed write to its output still counts against it,
+/// allowing for write retries and waiting for a free output
+const FAILURE_GRACE_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FireOutcome {
+    /// The output was turned on
+    Fired,
+    /// The skip check asked for the run to be skipped
+    Skipped,
+    /// Writing to the output failed during the run
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FireRecord {
+    /// When the run started, or was due to for skipped runs
+    pub on_at: DateTime<Utc>,
+    /// When the run was due to end. Skipped runs have none.
+    pub off_at: Option<DateTime<Utc>>,
+    pub outcome: FireOutcome,
+}
+
+impl AppState {
+    fn fire_log(&self) -> Result<sled::Tree, Error> {
+        Ok(self.db.open_tree(FIRE_LOG)?)
+    }
+
+    /// Append a run to a timer's history, dropping its oldest entries beyond the maximum
+    pub fn try_record_history(&self, id: Uuid, record: &FireRecord) -> Result<(), Error> {
+        let log = self.fire_log()?;
+        let mut key = id.as_bytes().to_vec();
+        key.extend_from_slice(&self.db.generate_id()?.to_be_bytes());
+        log.insert(key, serde_json::to_vec(record)?)?;
+        let excess = log
+            .scan_prefix(id.as_bytes())
+            .count()
+            .saturating_sub(self.history_max);
+        for key in log.scan_prefix(id.as_bytes()).keys().take(excess) {
+            log.remove(key?)?;
+        }
+        Ok(())
+    }
+
+    /// Like `try_record_history`, but only logging failures, since the run has already
+    /// happened by the time it's recorded
+    pub fn record_history(
+        &self,
+        id: Uuid,
+        on_at: DateTime<Utc>,
+        off_at: Option<DateTime<Utc>>,
+        outcome: FireOutcome,
+    ) {
+        let record = FireRecord {
+            on_at,
+            off_at,
+            outcome,
+        };
+        if let Err(e) = self.try_record_history(id, &record) {
+            error!(
+                "Failed to record {:?} in the history of timer {}: {}",
+                outcome, id, e
+            );
+        }
+    }
+
+    /// Up to `limit` of a timer's most recent runs, newest first
+    pub fn timer_history(&self, id: Uuid, limit: usize) -> Result<Vec<FireRecord>, Error> {
+        self.fire_log()?
+            .scan_prefix(id.as_bytes())
+            .values()
+            .rev()
+            .take(limit)
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    /// Forget a timer's runs, e.g. once it has been purged from the trash
+    pub fn clear_history(&self, id: Uuid) -> Result<(), Error> {
+        let log = self.fire_log()?;
+        for key in log.scan_prefix(id.as_bytes()).keys() {
+            log.remove(key?)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the latest run of each timer controlling `output` as failed if it was under way
+    /// at `at`, returning how many were marked
+    pub fn record_write_failure(&self, output: u16, at: DateTime<Utc>) -> Result<usize, Error> {
+        let log = self.fire_log()?;
+        let grace = Duration::seconds(FAILURE_GRACE_SECS);
+        let mut marked = 0;
+        for timer in self.get_all_interval_timers()? {
+            if timer.output != output {
+                continue;
+            }
+            let Some(entry) = log.scan_prefix(timer.get_id().as_bytes()).next_back() else {
+                continue;
+            };
+            let (key, value) = entry?;
+            let mut record: FireRecord = serde_json::from_slice(&value)?;
+            let under_way =
+                record.on_at <= at && record.off_at.is_some_and(|off| at <= off + grace);
+            if record.outcome == FireOutcome::Fired && under_way {
+                record.outcome = FireOutcome::Failed;
+                log.insert(key, serde_json::to_vec(&record)?)?;
+                marked += 1;
+                let what = format!("failed to write to output {}", output);
+                self.publish(
+                    SchedulerEventKind::Failed,
+                    timer.get_id(),
+                    &timer_label(&timer),
+                    &what,
+                );
+            }
+        }
+        Ok(marked)
+    }
+
+    /// Spawn a task marking runs as failed in their timers' history when the GPIO manager gives
+    /// up on a write to their output
+    pub fn spawn_history_recorder(&self) -> JoinHandle<()> {
+        let state = self.clone();
+        let mut events = self.output_events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Timer history missed {} output events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if event.ok {
+                    continue;
+                }
+                let at = event.at.with_timezone(&Utc);
+                if let Err(e) = state.record_write_failure(event.output, at) {
+                    error!(
+                        "Failed to record a failed write to output {}: {}",
+                        event.output, e
+                    );
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryParams {
+    /// Maximum number of entries to return, newest first
+    pub limit: Option<usize>,
+}
+
+/// A timer's recent runs, newest first, or `304 Not Modified` if none have been made since
+/// those with the ETag given in `If-None-Match`
+#[axum::debug_handler]
+pub async fn timer_history(
+    id: Result<Path<Uuid>, PathRejection>,
+    State(state): State<AppState>,
+    Query(params): Query<HistoryParams>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let Path(id) = id?;
+    if state.get_interval_timer(id)?.is_none() {
+        return Err(Error::NotFound(format!("Timer with ID {}", &id)));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let records = state.timer_history(id, limit)?;
+    let etag = etag_of(serde_json::to_vec(&records)?);
+    not_modified_or(&headers, &etag, || Ok(Json(records)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntervalTimer;
+    use chrono::TimeZone;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn history_is_newest_first_limited_and_capped() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state =
+            AppState::new(Arc::new(db), tokio::sync::mpsc::channel(1).0).with_history_max(4);
+        let (timer, other) = (
+            IntervalTimer::new(None, None, crate::IntervalSettings::default()),
+            IntervalTimer::new(None, None, crate::IntervalSettings::default()),
+        );
+        state.insert_interval_timer(&timer).unwrap();
+        let day = |d| Utc.with_ymd_and_hms(2024, 6, d, 6, 0, 0).unwrap();
+        for d in 1..=6 {
+            let outcome = match d {
+                3 => FireOutcome::Skipped,
+                _ => FireOutcome::Fired,
+            };
+            state.record_history(
+                timer.get_id(),
+                day(d),
+                Some(day(d) + Duration::minutes(10)),
+                outcome,
+            );
+        }
+        state.record_history(other.get_id(), day(7), None, FireOutcome::Fired);
+
+        let history = |limit| {
+            let params = HistoryParams { limit };
+            timer_history(
+                Ok(Path(timer.get_id())),
+                State(state.clone()),
+                Query(params),
+                HeaderMap::new(),
+            )
+        };
+        let response = history(Some(2)).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let records: Vec<FireRecord> = serde_json::from_slice(&body).unwrap();
+        let starts: Vec<_> = records.iter().map(|record| record.on_at).collect();
+        assert_eq!(starts, [day(6), day(5)]);
+
+        // Only the newest four are kept
+        let records = state
+            .timer_history(timer.get_id(), DEFAULT_HISTORY_LIMIT)
+            .unwrap();
+        let starts: Vec<_> = records.iter().map(|record| record.on_at).collect();
+        assert_eq!(starts, [day(6), day(5), day(4), day(3)]);
+        assert_eq!(records[3].outcome, FireOutcome::Skipped);
+        assert_eq!(state.timer_history(other.get_id(), 10).unwrap().len(), 1);
+
+        state.clear_history(timer.get_id()).unwrap();
+        assert!(state.timer_history(timer.get_id(), 10).unwrap().is_empty());
+        let err = timer_history(
+            Ok(Path(other.get_id())),
+            State(state.clone()),
+            Query(HistoryParams::default()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+    }
+}
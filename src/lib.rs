@@ -1,7 +1,8 @@
-#![feature(async_closure)]
 extern crate bytes;
 extern crate chrono;
-use chrono::NaiveTime;
+use chrono::{
+    DateTime, Datelike, Days, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday,
+};
 extern crate tokio;
 extern crate uuid;
 pub use uuid::Uuid;
@@ -10,24 +11,138 @@ use serde::{Deserialize, Serialize};
 extern crate serde_json;
 extern crate thiserror;
 
-use std::time::Duration;
+use chrono_tz::Tz;
+use croner::Cron;
+use std::{str::FromStr, time::Duration};
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod calendar;
+pub mod config;
+pub mod events;
 pub mod handlers;
 use handlers::NewDaily;
+pub mod history;
+pub mod metrics;
+pub mod openapi;
+pub mod pins;
+pub mod rate_limit;
+pub mod request_log;
+pub mod store;
+pub mod sun;
+use sun::SunEvent;
 pub mod util;
-use util::{naive_now, Error};
+pub mod webhook;
+pub mod ws;
+use util::{naive_now, parse_time_of_day, Error};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The output pin used by timers which don't specify one
+pub const DEFAULT_OUTPUT: u16 = 476;
+
+fn default_output() -> u16 {
+    DEFAULT_OUTPUT
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// The layout version of stored timers. Bump it along with a step in `migrate` when a change
+/// needs more than a `#[serde(default)]` to read older records.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Upgrade a stored timer from an older layout in place, so that it deserializes as the
+/// current one
+fn migrate(record: &mut serde_json::Value) {
+    let Some(fields) = record.as_object_mut() else {
+        return;
+    };
+    let version = fields
+        .get("schema_version")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0);
+    if version < 1 {
+        // Unversioned records may predate the timestamps. Fix them to the time of the upgrade
+        // rather than letting them default to whenever the record is loaded.
+        let now = serde_json::to_value(Local::now()).unwrap_or_default();
+        for field in ["created_at", "updated_at"] {
+            fields.entry(field).or_insert_with(|| now.clone());
+        }
+    }
+    fields.insert("schema_version".to_owned(), SCHEMA_VERSION.into());
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct IntervalTimer {
     id: Uuid,
     pub name: Option<String>,
     pub description: Option<String>,
     settings: IntervalSettings,
+    /// The GPIO output pin controlled by the timer
+    #[serde(default = "default_output")]
+    pub output: u16,
+    /// The configured alias the output was chosen by, for display
+    #[serde(default)]
+    pub output_alias: Option<String>,
+    /// Disabled timers are kept in the database but don't run
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Whether the output rests on and the schedule turns it off instead, e.g. to stop a pump
+    /// during quiet hours
+    #[serde(default)]
+    pub normally_on: bool,
+    /// Whether the output's load is turned on by driving the pin low, as on many relay boards
+    #[serde(default)]
+    pub active_low: bool,
+    /// Times the timer has turned its output on, e.g. to tell when a valve is due for service
+    #[serde(default)]
+    pub fire_count: u64,
+    /// When the timer last turned its output on, e.g. to confirm a zone ran overnight
+    #[serde(default)]
+    pub last_fired_at: Option<DateTime<Local>>,
+    /// Records from before these were added get the time they are loaded
+    #[serde(default = "Local::now")]
+    pub created_at: DateTime<Local>,
+    #[serde(default = "Local::now")]
+    pub updated_at: DateTime<Local>,
+    /// The layout version the record was stored with, 0 for records from before it was tracked
+    #[serde(default)]
+    pub schema_version: u32,
 }
 
 impl IntervalTimer {
     pub fn get_id(&self) -> Uuid {
         self.id
     }
+
+    /// The output for display, with the alias it was chosen by if any, e.g. "Front Lawn (476)"
+    pub fn output_label(&self) -> String {
+        match &self.output_alias {
+            Some(alias) => format!("{} ({})", alias, self.output),
+            None => self.output.to_string(),
+        }
+    }
+
+    /// When and for how long the timer runs
+    ///
+    /// ```
+    /// use chrono::NaiveTime;
+    /// use sploosh::{IntervalTimer, Schedule};
+    /// use std::time::Duration;
+    ///
+    /// let start = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+    /// let timer = IntervalTimer::once_daily(None, None, Duration::from_secs(600), start).unwrap();
+    /// let settings = timer.settings();
+    /// assert_eq!(settings.duration_on(), Duration::from_secs(600));
+    /// // A daily timer is off for the rest of the day
+    /// assert_eq!(settings.duration_off(), Duration::from_secs(24 * 60 * 60 - 600));
+    /// assert_eq!(settings.start_time(), Some(start));
+    /// assert_eq!(settings.schedule(), &Schedule::Daily);
+    /// assert_eq!(settings.timezone(), None);
+    /// ```
+    pub fn settings(&self) -> &IntervalSettings {
+        &self.settings
+    }
     pub fn new(
         name: Option<String>,
         description: Option<String>,
@@ -39,6 +154,16 @@ impl IntervalTimer {
             name,
             description,
             settings,
+            output: DEFAULT_OUTPUT,
+            output_alias: None,
+            enabled: true,
+            normally_on: false,
+            active_low: false,
+            fire_count: 0,
+            last_fired_at: None,
+            schema_version: SCHEMA_VERSION,
+            created_at: Local::now(),
+            updated_at: Local::now(),
         }
     }
 
@@ -48,14 +173,8 @@ impl IntervalTimer {
         duration_on: Duration,
         start_time: NaiveTime,
     ) -> Result<IntervalTimer, Error> {
-        let id = Uuid::new_v4();
         let settings = IntervalSettings::once_daily(duration_on, start_time)?;
-        Ok(IntervalTimer {
-            id,
-            name,
-            description,
-            settings,
-        })
+        Ok(IntervalTimer::new(name, description, settings))
     }
 
     pub fn daily_now(
@@ -63,51 +182,371 @@ impl IntervalTimer {
         description: Option<String>,
         duration_on: Duration,
     ) -> Result<IntervalTimer, Error> {
-        let id = Uuid::new_v4();
         let settings = IntervalSettings::daily_now(duration_on)?;
-        Ok(IntervalTimer {
-            id,
-            name,
-            description,
-            settings,
-        })
+        Ok(IntervalTimer::new(name, description, settings))
     }
 
-    pub fn from_newdaily(n: NewDaily) -> Result<Self, Error> {
-        let id = Uuid::new_v4();
-        let name = Some(n.name.to_owned());
+    pub fn weekly(
+        name: Option<String>,
+        description: Option<String>,
+        days: Vec<Weekday>,
+        duration_on: Duration,
+        start_time: NaiveTime,
+    ) -> Result<IntervalTimer, Error> {
+        let settings = IntervalSettings::weekly(days, duration_on, start_time)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which turns on for each of the given windows of start time and duration
+    /// every day
+    pub fn multi_window(
+        name: Option<String>,
+        description: Option<String>,
+        windows: Vec<(NaiveTime, Duration)>,
+    ) -> Result<IntervalTimer, Error> {
+        let settings = IntervalSettings::multi_window(windows)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which turns on at each time matching a cron expression
+    pub fn cron(
+        name: Option<String>,
+        description: Option<String>,
+        expr: &str,
+        duration_on: Duration,
+    ) -> Result<IntervalTimer, Error> {
+        let settings = IntervalSettings::cron(expr, duration_on)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which alternates between on for `duration_on` and off for `duration_off`
+    /// forever, regardless of the time of day
+    pub fn cycling(
+        name: Option<String>,
+        description: Option<String>,
+        duration_on: Duration,
+        duration_off: Duration,
+    ) -> Result<IntervalTimer, Error> {
+        let settings = IntervalSettings::cycling(duration_on, duration_off)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which turns on for `duration_on` whenever the input pin changes in the direction
+    /// of `edge` and holds its new value for `debounce`
+    pub fn on_input(
+        name: Option<String>,
+        description: Option<String>,
+        pin: u16,
+        edge: Edge,
+        debounce: Duration,
+        duration_on: Duration,
+    ) -> Result<IntervalTimer, Error> {
+        let settings = IntervalSettings::on_input(pin, edge, debounce, duration_on)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which turns on every day at sunset at the given location, shifted by `offset`
+    pub fn at_sunset(
+        name: Option<String>,
+        description: Option<String>,
+        latitude: f64,
+        longitude: f64,
+        offset: chrono::Duration,
+        duration_on: Duration,
+    ) -> Result<IntervalTimer, Error> {
+        let settings =
+            IntervalSettings::at_sun(SunEvent::Sunset, latitude, longitude, offset, duration_on)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    /// A timer which turns on every day at sunrise at the given location, shifted by `offset`
+    pub fn at_sunrise(
+        name: Option<String>,
+        description: Option<String>,
+        latitude: f64,
+        longitude: f64,
+        offset: chrono::Duration,
+        duration_on: Duration,
+    ) -> Result<IntervalTimer, Error> {
+        let settings =
+            IntervalSettings::at_sun(SunEvent::Sunrise, latitude, longitude, offset, duration_on)?;
+        Ok(IntervalTimer::new(name, description, settings))
+    }
+
+    pub fn from_newdaily(mut n: NewDaily) -> Result<Self, Error> {
+        n.validate()?;
+        let name = Some(n.name.trim().to_owned());
         let description = n.description.to_owned();
+        let output = n.output.pin().unwrap_or_default();
+        let output_alias = n.output_alias.take();
+        let enabled = n.enabled.unwrap_or(true);
+        let normally_on = n.normally_on.unwrap_or(false);
+        let active_low = n.active_low.unwrap_or(false);
         let settings = IntervalSettings::from_newdaily(n)?;
         Ok(IntervalTimer {
-            id,
-            name,
-            description,
-            settings,
+            output,
+            output_alias,
+            enabled,
+            normally_on,
+            active_low,
+            ..IntervalTimer::new(name, description, settings)
         })
     }
 
+    /// Interpret the schedule in the given timezone, or the system's local timezone if `None`
+    pub fn set_timezone(&mut self, tz: Option<Tz>) {
+        self.settings.set_timezone(tz);
+    }
+
+    /// A copy of the timer with a new id, its name suffixed with " (copy)" and no runs recorded
+    pub fn duplicate(&self) -> IntervalTimer {
+        let now = Local::now();
+        IntervalTimer {
+            id: Uuid::new_v4(),
+            name: self.name.as_ref().map(|name| format!("{} (copy)", name)),
+            fire_count: 0,
+            last_fired_at: None,
+            schema_version: SCHEMA_VERSION,
+            created_at: now,
+            updated_at: now,
+            ..self.clone()
+        }
+    }
+
+    /// The first start strictly after `now` out of the timer's start times, on a day it runs.
+    /// Both are wall clock times in the timer's timezone. `None` for timers without fixed
+    /// start times, e.g. cron or sun timers.
+    pub fn next_fire(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let starts = match &self.settings.schedule {
+            Schedule::Daily | Schedule::Weekly(_) => vec![self.settings.start_time?],
+            Schedule::Windows(windows) => windows.iter().map(|(start, _)| *start).collect(),
+            Schedule::Cron(_)
+            | Schedule::Sun { .. }
+            | Schedule::Cycling
+            | Schedule::Input { .. } => return None,
+        };
+        let days = match &self.settings.schedule {
+            Schedule::Weekly(days) => Some(days),
+            _ => None,
+        };
+        // A timer with fixed start times runs at least once a week, so its next start is at
+        // most a week from today
+        (0..=7)
+            .filter_map(|offset| now.date().checked_add_days(Days::new(offset)))
+            .filter(|date| days.is_none_or(|days| days.contains(&date.weekday())))
+            .flat_map(|date| starts.iter().map(move |start| date.and_time(*start)))
+            .filter(|&start| start > now)
+            .min()
+    }
+
+    /// How long until the timer next fires, in its timezone
+    pub fn time_until_next_fire(&self) -> Option<chrono::Duration> {
+        let tz = self.settings.tz;
+        let next = self.next_fire(util::local_datetime(tz))?;
+        util::time_until_datetime(next, tz)
+    }
+
+    /// How far to shift the timer's start so that timers starting at the same time are spread
+    /// over `stagger`. It's derived from the timer's id so that it's the same across restarts.
+    pub fn start_offset(&self, stagger: Duration) -> Duration {
+        let secs = u128::from(stagger.as_secs());
+        Duration::from_secs((self.id.as_u128() % (secs + 1)) as u64)
+    }
+
+    /// The time of day the timer actually starts once shifted by its `start_offset`, wrapping
+    /// past midnight
+    pub fn effective_start_time(&self, stagger: Duration) -> Option<NaiveTime> {
+        let offset = chrono::Duration::from_std(self.start_offset(stagger)).ok()?;
+        Some(self.settings.start_time? + offset)
+    }
+
+    /// The start and end of every run starting from `from` up to `to`, with starts shifted by
+    /// their `start_offset`. Only timers with fixed start times have runs listed.
+    pub fn runs_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        stagger: Duration,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        match self.settings.tz {
+            Some(tz) => self.runs_between_in(&tz, from, to, stagger),
+            None => self.runs_between_in(&Local, from, to, stagger),
+        }
+    }
+
+    /// The end of the run under way at `now` going by the timer's schedule, with starts shifted
+    /// by their `start_offset`, or `None` if it's between runs. Only timers with fixed start
+    /// times are ever in a run.
+    pub fn active_until(&self, now: DateTime<Utc>, stagger: Duration) -> Option<DateTime<Utc>> {
+        // No run lasts a day, so one under way started within the last day
+        let from = now - chrono::Duration::days(1);
+        let to = now + chrono::Duration::seconds(1);
+        self.runs_between(from, to, stagger)
+            .into_iter()
+            .filter(|&(start, end)| start <= now && now < end)
+            .map(|(_, end)| end)
+            .max()
+    }
+
+    /// The next `n` start times at or after `from`, soonest first, without any start stagger.
+    /// Fewer are returned for timers without fixed start times.
+    pub fn upcoming(&self, from: DateTime<Local>, n: usize) -> Vec<DateTime<Local>> {
+        let mut starts = Vec::with_capacity(n);
+        let mut window_start = from.with_timezone(&Utc);
+        while starts.len() < n {
+            // Timers with fixed start times run at least once a week
+            let window_end = window_start + chrono::Duration::weeks(1);
+            let runs = self.runs_between(window_start, window_end, Duration::ZERO);
+            if runs.is_empty() {
+                break;
+            }
+            let mut week: Vec<_> = runs.into_iter().map(|(start, _)| start).collect();
+            week.sort();
+            starts.extend(week.into_iter().map(|start| start.with_timezone(&Local)));
+            window_start = window_end;
+        }
+        starts.truncate(n);
+        starts
+    }
+
+    fn runs_between_in<T: TimeZone>(
+        &self,
+        tz: &T,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        stagger: Duration,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let windows: Vec<(NaiveTime, chrono::Duration)> = match &self.settings.schedule {
+            Schedule::Daily | Schedule::Weekly(_) => {
+                let duration = chrono::Duration::from_std(self.settings.duration_on);
+                match (self.settings.start_time, duration) {
+                    (Some(start), Ok(duration)) => vec![(start, duration)],
+                    _ => return Vec::new(),
+                }
+            }
+            Schedule::Windows(windows) => windows
+                .iter()
+                .filter_map(|(start, duration)| {
+                    Some((*start, chrono::Duration::from_std(*duration).ok()?))
+                })
+                .collect(),
+            Schedule::Cron(_)
+            | Schedule::Sun { .. }
+            | Schedule::Cycling
+            | Schedule::Input { .. } => return Vec::new(),
+        };
+        let days = match &self.settings.schedule {
+            Schedule::Weekly(days) => Some(days),
+            _ => None,
+        };
+        let offset = chrono::Duration::from_std(self.start_offset(stagger)).unwrap_or_default();
+        let mut runs = Vec::new();
+        // Start from the day before in case a run from then is shifted past midnight
+        let from_date = from.with_timezone(tz).date_naive();
+        let mut date = from_date.pred_opt().unwrap_or(from_date);
+        let last = to.with_timezone(tz).date_naive();
+        while date <= last {
+            if days.is_none_or(|days| days.contains(&date.weekday())) {
+                for (start, duration) in &windows {
+                    let naive = date.and_time(*start) + offset;
+                    // Like the timers, a start skipped by a daylight saving change is an hour late
+                    let start = tz.from_local_datetime(&naive).earliest().or_else(|| {
+                        tz.from_local_datetime(&(naive + chrono::Duration::hours(1)))
+                            .earliest()
+                    });
+                    match start.map(|start| start.with_timezone(&Utc)) {
+                        Some(start) if start >= from && start < to => {
+                            runs.push((start, start + *duration))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            date = match date.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        runs
+    }
+
     /// Serialize the struct into a JSON string
     pub fn to_json_string(&self) -> Result<String, Error> {
-        serde_json::to_string(self).map_err(|e| util::Error::Json(e))
+        serde_json::to_string(self).map_err(util::Error::Json)
     }
     /// Serialize the struct to a JSON Vec<u8>
     pub fn to_json_vec(&self) -> Result<Vec<u8>, Error> {
-        serde_json::to_vec(self).map_err(|e| util::Error::Json(e))
+        serde_json::to_vec(self).map_err(util::Error::Json)
     }
-    /// Deserialize a struct from bytes of JSON text
+    /// Deserialize a struct from bytes of JSON text, upgrading records stored by older versions
     pub fn from_json_slice(slice: impl AsRef<[u8]>) -> Result<Self, Error> {
-        serde_json::from_slice(slice.as_ref()).map_err(|e| util::Error::Json(e))
+        let mut record: serde_json::Value = serde_json::from_slice(slice.as_ref())?;
+        migrate(&mut record);
+        serde_json::from_value(record).map_err(util::Error::Json)
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Which days a timer runs on
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    /// Every day at the start time
+    #[default]
+    Daily,
+    /// At the start time on the given days of the week
+    Weekly(Vec<Weekday>),
+    /// Every day for each of the given windows of start time and duration, sorted by start
+    /// time
+    Windows(Vec<(NaiveTime, Duration)>),
+    /// At each time matching the cron expression, in local time
+    Cron(String),
+    /// Every day at sunrise or sunset at the given location, shifted by the offset
+    Sun {
+        event: SunEvent,
+        latitude: f64,
+        longitude: f64,
+        offset_secs: i64,
+    },
+    /// On for the on duration then off for the off duration, repeating from when the timer
+    /// starts
+    Cycling,
+    /// On for the on duration whenever the input pin changes in the direction of the edge and
+    /// stays that way for the debounce window, e.g. for a button or a soil moisture sensor
+    Input {
+        pin: u16,
+        edge: Edge,
+        debounce_ms: u64,
+    },
+}
+
+/// Which change of an input's value triggers a timer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Edge {
+    /// From low to high
+    Rising,
+    /// From high to low
+    Falling,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct IntervalSettings {
+    /// How long the output stays on for each run. Non-zero for every schedule.
     duration_on: Duration,
+    /// How long the output stays off between runs: the rest of the day for `Daily`, `Weekly`
+    /// and `Windows`, the off part of each cycle for `Cycling`, and zero for schedules
+    /// triggered by something other than time passing (`Cron`, `Sun` and `Input`)
     duration_off: Duration,
+    /// When each run starts, for `Daily`, `Weekly` and `Windows` (the earliest window) only
     start_time: Option<NaiveTime>,
+    #[serde(default)]
+    schedule: Schedule,
+    /// Timezone the schedule is interpreted in, or the system's local timezone if `None`
+    #[serde(default)]
+    tz: Option<Tz>,
 }
 
 impl IntervalSettings {
+    /// Settings with a daily schedule and the given durations as they are, without any
+    /// validation. Prefer `try_new` or one of the schedule constructors.
     pub fn new(
         duration_on: Duration,
         duration_off: Duration,
@@ -117,32 +556,529 @@ impl IntervalSettings {
             duration_on,
             duration_off,
             start_time,
+            schedule: Schedule::Daily,
+            tz: None,
         }
     }
 
+    /// Like `new`, but rejecting a zero on or off duration, which would leave the output
+    /// never turning on or never turning off
+    pub fn try_new(
+        duration_on: Duration,
+        duration_off: Duration,
+        start_time: Option<NaiveTime>,
+    ) -> Result<IntervalSettings, Error> {
+        if duration_on.is_zero() || duration_off.is_zero() {
+            return Err(Error::InvalidDuration);
+        }
+        Ok(IntervalSettings::new(duration_on, duration_off, start_time))
+    }
+
     pub fn once_daily(
         duration_on: Duration,
         start_time: NaiveTime,
     ) -> Result<IntervalSettings, Error> {
-        if duration_on.is_zero() {
+        let day = Duration::from_secs(60 * 60 * 24);
+        // A timer on for the whole day would never turn off
+        if duration_on.is_zero() || duration_on >= day {
             Err(Error::InvalidDuration)
         } else {
-            let duration_off = Duration::from_secs(60 * 60 * 24) - duration_on; // 24h-duration_on
+            let duration_off = day - duration_on;
             Ok(IntervalSettings {
                 duration_on,
                 duration_off,
                 start_time: Some(start_time),
+                schedule: Schedule::Daily,
+                tz: None,
             })
         }
     }
+
+    /// Run at the start time only on the given days of the week
+    pub fn weekly(
+        mut days: Vec<Weekday>,
+        duration_on: Duration,
+        start_time: NaiveTime,
+    ) -> Result<IntervalSettings, Error> {
+        if days.is_empty() {
+            return Err(Error::NoDays);
+        }
+        days.sort_by_key(|d| d.num_days_from_monday());
+        days.dedup();
+        let mut settings = IntervalSettings::once_daily(duration_on, start_time)?;
+        settings.schedule = Schedule::Weekly(days);
+        Ok(settings)
+    }
+    /// Run every day for each of the given windows. The start time and on duration of the
+    /// settings are those of the earliest window.
+    pub fn multi_window(
+        mut windows: Vec<(NaiveTime, Duration)>,
+    ) -> Result<IntervalSettings, Error> {
+        if windows.iter().any(|w| w.1.is_zero()) {
+            return Err(Error::InvalidDuration);
+        }
+        windows.sort_by_key(|w| w.0);
+        let Some(&(start_time, duration_on)) = windows.first() else {
+            return Err(Error::InvalidDuration);
+        };
+        // Each window must end before the next one starts, including the last window of the
+        // day against the first window of the following day
+        let day = Duration::from_secs(60 * 60 * 24);
+        let starts = windows.iter().map(|w| seconds_from_midnight(w.0));
+        let next_starts = starts
+            .clone()
+            .skip(1)
+            .chain(std::iter::once(seconds_from_midnight(start_time) + day));
+        for ((start, next_start), (_, duration)) in starts.zip(next_starts).zip(&windows) {
+            if start + *duration > next_start {
+                return Err(Error::OverlappingWindows);
+            }
+        }
+        let mut settings = IntervalSettings::once_daily(duration_on, start_time)?;
+        settings.schedule = Schedule::Windows(windows);
+        Ok(settings)
+    }
+
+    /// Run at each time matching a standard cron expression, e.g. `0 6 * * 1-5` for 06:00 on
+    /// weekdays. Settings following a cron schedule have no fixed start time.
+    pub fn cron(expr: &str, duration_on: Duration) -> Result<IntervalSettings, Error> {
+        if duration_on.is_zero() {
+            return Err(Error::InvalidDuration);
+        }
+        let cron = Cron::from_str(expr)?;
+        Ok(IntervalSettings {
+            duration_on,
+            duration_off: Duration::ZERO,
+            start_time: None,
+            schedule: Schedule::Cron(cron.pattern.to_string()),
+            tz: None,
+        })
+    }
+
+    /// Alternate between on and off for the given durations, starting whenever the timer is
+    /// scheduled. Cycling settings have no fixed start time.
+    pub fn cycling(
+        duration_on: Duration,
+        duration_off: Duration,
+    ) -> Result<IntervalSettings, Error> {
+        if duration_on.is_zero() || duration_off.is_zero() {
+            return Err(Error::InvalidDuration);
+        }
+        Ok(IntervalSettings {
+            duration_on,
+            duration_off,
+            start_time: None,
+            schedule: Schedule::Cycling,
+            tz: None,
+        })
+    }
+
+    /// Run for `duration_on` whenever the input pin changes in the direction of `edge` and
+    /// holds its new value for `debounce`. Settings triggered by an input have no fixed start
+    /// time.
+    pub fn on_input(
+        pin: u16,
+        edge: Edge,
+        debounce: Duration,
+        duration_on: Duration,
+    ) -> Result<IntervalSettings, Error> {
+        if duration_on.is_zero() {
+            return Err(Error::InvalidDuration);
+        }
+        Ok(IntervalSettings {
+            duration_on,
+            duration_off: Duration::ZERO,
+            start_time: None,
+            schedule: Schedule::Input {
+                pin,
+                edge,
+                debounce_ms: u64::try_from(debounce.as_millis()).unwrap_or(u64::MAX),
+            },
+            tz: None,
+        })
+    }
+
+    /// Run every day at sunrise or sunset at the given latitude and longitude (in degrees,
+    /// north and east positive), shifted by `offset`. Settings following the sun have no
+    /// fixed start time.
+    pub fn at_sun(
+        event: SunEvent,
+        latitude: f64,
+        longitude: f64,
+        offset: chrono::Duration,
+        duration_on: Duration,
+    ) -> Result<IntervalSettings, Error> {
+        if duration_on.is_zero() {
+            return Err(Error::InvalidDuration);
+        }
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(Error::InvalidCoordinates);
+        }
+        Ok(IntervalSettings {
+            duration_on,
+            duration_off: Duration::ZERO,
+            start_time: None,
+            schedule: Schedule::Sun {
+                event,
+                latitude,
+                longitude,
+                offset_secs: offset.num_seconds(),
+            },
+            tz: None,
+        })
+    }
+
     pub fn daily_now(duration_on: Duration) -> Result<IntervalSettings, Error> {
-        IntervalSettings::once_daily(duration_on, naive_now())
+        IntervalSettings::once_daily(duration_on, naive_now(None))
     }
 
     pub fn from_newdaily(n: NewDaily) -> Result<IntervalSettings, Error> {
-        let duration_on = Duration::from_secs(n.duration_on.into());
-        let start_time = NaiveTime::parse_from_str(n.start_time.as_ref(), "%H:%M")
-            .map_err(|e| Error::TimeParsing(e))?;
-        IntervalSettings::once_daily(duration_on, start_time)
+        let duration_on = n.duration();
+        let start_time = parse_time_of_day(n.start_time.as_ref()).map_err(Error::TimeParsing)?;
+        let tz = match n.timezone.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => {
+                Some(Tz::from_str(name).map_err(|_| Error::InvalidTimezone(name.to_owned()))?)
+            }
+            _ => None,
+        };
+        let mut settings = IntervalSettings::once_daily(duration_on, start_time)?;
+        settings.tz = tz;
+        Ok(settings)
+    }
+
+    /// Interpret the schedule in the given timezone, or the system's local timezone if `None`
+    pub fn set_timezone(&mut self, tz: Option<Tz>) {
+        self.tz = tz;
+    }
+
+    /// The same schedule with each run lasting `duration_on`, checked as it would be for new
+    /// settings. Every window of a `Windows` schedule gets the new duration.
+    pub fn with_duration_on(&self, duration_on: Duration) -> Result<IntervalSettings, Error> {
+        let mut settings = match &self.schedule {
+            Schedule::Daily | Schedule::Weekly(_) => {
+                let day = Duration::from_secs(60 * 60 * 24);
+                if duration_on.is_zero() || duration_on >= day {
+                    return Err(Error::InvalidDuration);
+                }
+                IntervalSettings {
+                    duration_on,
+                    duration_off: day - duration_on,
+                    ..self.clone()
+                }
+            }
+            Schedule::Windows(windows) => IntervalSettings::multi_window(
+                windows
+                    .iter()
+                    .map(|(start, _)| (*start, duration_on))
+                    .collect(),
+            )?,
+            Schedule::Cycling => IntervalSettings::cycling(duration_on, self.duration_off)?,
+            Schedule::Cron(_) | Schedule::Sun { .. } | Schedule::Input { .. } => {
+                if duration_on.is_zero() {
+                    return Err(Error::InvalidDuration);
+                }
+                IntervalSettings {
+                    duration_on,
+                    ..self.clone()
+                }
+            }
+        };
+        settings.tz = self.tz;
+        Ok(settings)
+    }
+
+    /// How long the output is on for each run
+    pub fn duration_on(&self) -> Duration {
+        self.duration_on
+    }
+
+    /// How long the output is off between runs of a cycling timer, or for the rest of the day
+    /// for a daily one
+    pub fn duration_off(&self) -> Duration {
+        self.duration_off
+    }
+
+    /// Time of day the timer starts, or `None` for schedules without a fixed start time
+    pub fn start_time(&self) -> Option<NaiveTime> {
+        self.start_time
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Timezone the schedule is interpreted in, or `None` for the system's local timezone
+    pub fn timezone(&self) -> Option<Tz> {
+        self.tz
+    }
+}
+
+/// The time elapsed since midnight at the given time of day
+fn seconds_from_midnight(time: NaiveTime) -> Duration {
+    Duration::from_secs(time.num_seconds_from_midnight().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(date: (i32, u32, u32), time: (u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+            .unwrap()
+            .and_hms_opt(time.0, time.1, 0)
+            .unwrap()
+    }
+
+    fn hm(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn mins(m: u64) -> Duration {
+        Duration::from_secs(m * 60)
+    }
+
+    #[test]
+    fn next_fire_is_today_when_the_start_is_later() {
+        let timer = IntervalTimer::once_daily(None, None, mins(10), hm(18, 0)).unwrap();
+        let now = at((2024, 5, 1), (9, 0));
+        assert_eq!(timer.next_fire(now), Some(at((2024, 5, 1), (18, 0))));
+    }
+
+    #[test]
+    fn next_fire_is_tomorrow_when_the_start_has_passed() {
+        let timer = IntervalTimer::once_daily(None, None, mins(10), hm(6, 0)).unwrap();
+        let now = at((2024, 5, 1), (9, 0));
+        assert_eq!(timer.next_fire(now), Some(at((2024, 5, 2), (6, 0))));
+    }
+
+    #[test]
+    fn next_fire_skips_to_the_next_day_a_weekly_timer_runs() {
+        // 2024-05-01 is a Wednesday
+        let days = vec![Weekday::Mon, Weekday::Fri];
+        let timer = IntervalTimer::weekly(None, None, days, mins(10), hm(6, 0)).unwrap();
+        let now = at((2024, 5, 1), (5, 0));
+        assert_eq!(timer.next_fire(now), Some(at((2024, 5, 3), (6, 0))));
+        let friday_after = at((2024, 5, 3), (7, 0));
+        assert_eq!(
+            timer.next_fire(friday_after),
+            Some(at((2024, 5, 6), (6, 0)))
+        );
+    }
+
+    #[test]
+    fn next_fire_waits_a_week_for_a_single_day_that_has_passed() {
+        let timer =
+            IntervalTimer::weekly(None, None, vec![Weekday::Wed], mins(10), hm(6, 0)).unwrap();
+        let now = at((2024, 5, 1), (6, 0));
+        assert_eq!(timer.next_fire(now), Some(at((2024, 5, 8), (6, 0))));
+    }
+
+    #[test]
+    fn next_fire_picks_the_nearest_window() {
+        let windows = vec![(hm(6, 0), mins(10)), (hm(18, 0), mins(10))];
+        let timer = IntervalTimer::multi_window(None, None, windows).unwrap();
+        assert_eq!(
+            timer.next_fire(at((2024, 5, 1), (12, 0))),
+            Some(at((2024, 5, 1), (18, 0)))
+        );
+        assert_eq!(
+            timer.next_fire(at((2024, 5, 1), (19, 0))),
+            Some(at((2024, 5, 2), (6, 0)))
+        );
+    }
+
+    #[test]
+    fn next_fire_is_none_without_fixed_start_times() {
+        let timer = IntervalTimer::cycling(None, None, mins(1), mins(2)).unwrap();
+        assert_eq!(timer.next_fire(at((2024, 5, 1), (12, 0))), None);
+    }
+
+    #[test]
+    fn a_cron_timer_keeps_its_expression_and_has_no_start_time() {
+        let timer = IntervalTimer::cron(None, None, "0 6 * * 1-5", mins(15)).unwrap();
+        let stored = IntervalTimer::from_json_slice(timer.to_json_vec().unwrap()).unwrap();
+        let settings = stored.settings();
+        assert_eq!(
+            settings.schedule(),
+            &Schedule::Cron("0 6 * * 1-5".to_owned())
+        );
+        assert_eq!(settings.start_time(), None);
+        assert_eq!(settings.duration_on(), mins(15));
+        assert_eq!(settings.duration_off(), Duration::ZERO);
+    }
+
+    #[test]
+    fn cron_timers_reject_bad_expressions_and_zero_durations() {
+        let err = IntervalSettings::cron("every morning", mins(15)).unwrap_err();
+        assert!(matches!(err, Error::CronParse(_)));
+        assert_eq!(err.status(), axum::http::StatusCode::BAD_REQUEST);
+        let err = IntervalSettings::cron("0 6 * * *", Duration::ZERO).unwrap_err();
+        assert!(matches!(err, Error::InvalidDuration));
+    }
+
+    #[test]
+    fn cycling_settings_need_both_durations() {
+        for (on, off) in [(Duration::ZERO, mins(5)), (mins(5), Duration::ZERO)] {
+            let err = IntervalSettings::cycling(on, off).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidDuration),
+                "{:?} on, {:?} off",
+                on,
+                off
+            );
+            let err = IntervalSettings::try_new(on, off, None).unwrap_err();
+            assert!(matches!(err, Error::InvalidDuration));
+        }
+        assert!(IntervalTimer::cycling(None, None, Duration::ZERO, mins(1)).is_err());
+
+        let settings = IntervalSettings::cycling(mins(2), mins(30)).unwrap();
+        assert_eq!(
+            (settings.duration_on(), settings.duration_off()),
+            (mins(2), mins(30))
+        );
+        assert_eq!(settings.start_time(), None);
+        let settings = IntervalSettings::try_new(mins(2), mins(30), Some(hm(6, 0))).unwrap();
+        assert_eq!(settings.start_time(), Some(hm(6, 0)));
+    }
+
+    #[test]
+    fn windows_must_not_overlap_even_across_midnight() {
+        let settings =
+            IntervalSettings::multi_window(vec![(hm(18, 0), mins(30)), (hm(6, 0), mins(30))])
+                .unwrap();
+        // The windows are sorted, and the first is the timer's start time
+        assert_eq!(settings.start_time(), Some(hm(6, 0)));
+        let expected = Schedule::Windows(vec![(hm(6, 0), mins(30)), (hm(18, 0), mins(30))]);
+        assert_eq!(settings.schedule(), &expected);
+        // Back to back is fine
+        assert!(
+            IntervalSettings::multi_window(vec![(hm(6, 0), mins(30)), (hm(6, 30), mins(5))])
+                .is_ok()
+        );
+
+        for windows in [
+            vec![(hm(6, 0), mins(31)), (hm(6, 30), mins(5))],
+            vec![(hm(6, 0), mins(10)), (hm(6, 0), mins(10))],
+            // The last window of the day runs into the first of the next
+            vec![(hm(5, 0), mins(10)), (hm(23, 30), mins(6 * 60))],
+        ] {
+            let err = IntervalSettings::multi_window(windows.clone()).unwrap_err();
+            assert!(matches!(err, Error::OverlappingWindows), "{:?}", windows);
+            assert_eq!(err.status(), axum::http::StatusCode::BAD_REQUEST);
+        }
+        for windows in [vec![], vec![(hm(6, 0), Duration::ZERO)]] {
+            let err = IntervalSettings::multi_window(windows).unwrap_err();
+            assert!(matches!(err, Error::InvalidDuration));
+        }
+    }
+
+    #[test]
+    fn sun_timers_reject_coordinates_off_the_globe() {
+        let offset = chrono::Duration::zero();
+        for (lat, long) in [(90.5, 0.0), (0.0, -180.5)] {
+            let err = IntervalSettings::at_sun(SunEvent::Sunset, lat, long, offset, mins(5));
+            assert!(matches!(err, Err(Error::InvalidCoordinates)));
+        }
+        let settings = IntervalSettings::at_sun(SunEvent::Sunrise, 51.5, -0.13, offset, mins(5));
+        assert_eq!(settings.unwrap().start_time(), None);
+    }
+
+    #[test]
+    fn timers_sharing_a_start_are_spread_out_by_their_ids() {
+        let stagger = Duration::from_secs(600);
+        let with_id = |id, start| IntervalTimer {
+            id: Uuid::from_u128(id),
+            ..IntervalTimer::once_daily(None, None, mins(5), start).unwrap()
+        };
+        let (a, b) = (with_id(100, hm(6, 0)), with_id(250, hm(6, 0)));
+        assert_eq!(
+            a.effective_start_time(stagger),
+            Some(hm(6, 1) + chrono::Duration::seconds(40))
+        );
+        assert_eq!(
+            b.effective_start_time(stagger),
+            Some(hm(6, 4) + chrono::Duration::seconds(10))
+        );
+        // Without a stagger every timer starts on time
+        assert_eq!(a.effective_start_time(Duration::ZERO), Some(hm(6, 0)));
+
+        // The offset comes from the stored id, so it's the same after a restart
+        let reloaded = IntervalTimer::from_json_slice(a.to_json_vec().unwrap()).unwrap();
+        assert_eq!(reloaded.start_offset(stagger), a.start_offset(stagger));
+
+        let late = with_id(100, hm(23, 59));
+        assert_eq!(
+            late.effective_start_time(stagger),
+            Some(hm(0, 0) + chrono::Duration::seconds(40))
+        );
+    }
+
+    /// A timer as stored before outputs, flags, timestamps and schedules were added
+    const UNVERSIONED: &str = r#"{
+        "id": "6f9d2c3e-1a2b-4c5d-8e9f-0a1b2c3d4e5f",
+        "name": "Lawn",
+        "description": null,
+        "settings": {
+            "duration_on": {"secs": 600, "nanos": 0},
+            "duration_off": {"secs": 85800, "nanos": 0},
+            "start_time": "06:00:00"
+        }
+    }"#;
+
+    #[test]
+    fn records_from_before_versioning_are_upgraded_once() {
+        let timer = IntervalTimer::from_json_slice(UNVERSIONED).unwrap();
+        assert_eq!(
+            timer.get_id().to_string(),
+            "6f9d2c3e-1a2b-4c5d-8e9f-0a1b2c3d4e5f"
+        );
+        assert_eq!(timer.name.as_deref(), Some("Lawn"));
+        assert_eq!(timer.settings().duration_on(), mins(10));
+        assert_eq!(timer.settings().start_time(), Some(hm(6, 0)));
+        assert_eq!(*timer.settings().schedule(), Schedule::Daily);
+        assert_eq!(
+            (timer.output, timer.enabled, timer.normally_on),
+            (default_output(), true, false)
+        );
+        assert_eq!(timer.schema_version, SCHEMA_VERSION);
+        assert_eq!(timer.created_at, timer.updated_at);
+
+        // Rewriting it in the current layout fixes its timestamps for good
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert(timer.get_id().as_bytes(), UNVERSIONED).unwrap();
+        let (tx, _) = tokio::sync::mpsc::channel(1);
+        let state = util::AppState::new(std::sync::Arc::new(db), tx);
+        assert_eq!(state.migrate_records().unwrap(), 1);
+        assert_eq!(state.migrate_records().unwrap(), 0);
+        let first = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        let again = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(first.created_at, again.created_at);
+        assert_eq!(first.schema_version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn upcoming_starts_cross_midnight_and_week_boundaries() {
+        let utc = |d, h, m| Utc.with_ymd_and_hms(2024, 6, d, h, m, 0).unwrap();
+        let local = |d, h, m| utc(d, h, m).with_timezone(&Local);
+
+        let mut nightly = IntervalTimer::once_daily(None, None, mins(20), hm(23, 30)).unwrap();
+        nightly.set_timezone(Some(chrono_tz::UTC));
+        // Just after the 1st's run has started
+        let starts = nightly.upcoming(local(1, 23, 45), 7);
+        let expected: Vec<_> = (2..=8).map(|d| local(d, 23, 30)).collect();
+        assert_eq!(starts, expected);
+
+        let days = vec![Weekday::Mon, Weekday::Wed, Weekday::Fri];
+        let mut mwf = IntervalTimer::weekly(None, None, days, mins(10), hm(6, 0)).unwrap();
+        mwf.set_timezone(Some(chrono_tz::UTC));
+        // Friday the 7th at the start time, which counts
+        let starts = mwf.upcoming(local(7, 6, 0), 5);
+        let expected: Vec<_> = [7, 10, 12, 14, 17].map(|d| local(d, 6, 0)).to_vec();
+        assert_eq!(starts, expected);
+        assert!(mwf.upcoming(local(7, 6, 0), 0).is_empty());
+
+        let cycling = IntervalTimer::cycling(None, None, mins(5), mins(5)).unwrap();
+        assert!(cycling.upcoming(local(7, 6, 0), 3).is_empty());
     }
 }
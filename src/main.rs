@@ -1,66 +1,765 @@
 extern crate clap;
 extern crate sled;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 extern crate anyhow;
 use anyhow::Result;
 extern crate tracing;
 use tracing::{debug, error, info};
 extern crate axum;
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
 extern crate serde;
 extern crate tokio;
 extern crate tracing_subscriber;
-use chrono::{Duration, NaiveTime};
+use chrono_tz::Tz;
 use sploosh::{
-    handlers::{alltimers, new_daily_form, new_timer, view_timer},
-    util::{run_timer, AppState, GpioManager},
+    api,
+    audit::{self, AuditAction},
+    auth::{require_auth, AuthUser, BasicAuth, Password},
+    calendar,
+    config::Config,
+    events,
+    handlers::{
+        alltimers, delete_timer, disable_timer, enable_timer, new_daily_form, new_timer,
+        normalize_css, restore_timer, skeleton_css, update_daily_form, view_timer, DurationUnit,
+        NewDaily, OutputRef,
+    },
+    history, metrics, openapi,
+    rate_limit::{rate_limit, RateLimiter},
+    request_log,
+    util::{self, AppState, Error, GpioManager, GpioMessage, OUTPUT_STATE},
+    webhook::{self, Webhook},
+    ws, IntervalTimer, Uuid, DEFAULT_OUTPUT,
 };
-use std::{path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc};
+use tokio::{signal, sync::mpsc};
+use tower_http::compression::CompressionLayer;
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// Manage timers in the database directly instead of serving the web interface
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML or JSON file to load settings from. Flags override its values.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
     /// Absolute or relative path to the database directory
     #[arg(short, long)]
-    db: PathBuf,
+    db: Option<PathBuf>,
+    /// Address to serve the web interface on [default: 0.0.0.0:3000]
+    #[arg(short, long)]
+    bind: Option<String>,
+    /// Log GPIO writes instead of using sysfs, for running somewhere other than the Pi
+    #[arg(long)]
+    simulate: bool,
+    /// Lowest output pin timers may control [default: 1]
+    #[arg(long)]
+    min_pin: Option<u16>,
+    /// Highest output pin timers may control [default: 65535]
+    #[arg(long)]
+    max_pin: Option<u16>,
+    /// Timezone for timers which don't specify one [default: the server's local timezone]
+    #[arg(long)]
+    timezone: Option<String>,
+    /// Flush the database to disk after every write, so recent changes survive a power loss
+    #[arg(long)]
+    durable: bool,
+    /// Refuse to create a timer with the same name as another
+    #[arg(long)]
+    unique_names: bool,
+    /// Hours deleted timers can be restored for before they are purged [default: 168]
+    #[arg(long)]
+    trash_retention_hours: Option<u32>,
+    /// Requests changing timers allowed per client per minute, or 0 for no limit [default: 60]
+    #[arg(long)]
+    rate_limit_per_minute: Option<u32>,
+    /// Username required for requests which change timers. Needs --password too.
+    #[arg(long)]
+    username: Option<String>,
+    /// Password required along with --username. Prefer setting it in the config file, since
+    /// flags are visible to other users of the machine.
+    #[arg(long)]
+    password: Option<Password>,
+    /// Require the username and password for reads as well
+    #[arg(long)]
+    protect_reads: bool,
+    /// The most entries kept in the audit log [default: 1000]
+    #[arg(long)]
+    audit_max_entries: Option<usize>,
+    /// The most runs kept in each timer's history [default: 100]
+    #[arg(long)]
+    history_max_entries: Option<usize>,
+    /// The most outputs which may be on at once. Timers which would turn on another wait until
+    /// one turns off, then run for their full duration. [default: no limit]
+    #[arg(long)]
+    max_concurrent_outputs: Option<usize>,
+    /// Times a failed GPIO write is retried before giving up [default: 3]
+    #[arg(long)]
+    gpio_write_retries: Option<u32>,
+    /// Milliseconds before the first retry of a failed GPIO write, doubling for each one after
+    /// [default: 100]
+    #[arg(long)]
+    gpio_retry_delay_ms: Option<u64>,
+    /// Seconds over which to spread the starts of timers sharing a start time, by a fixed
+    /// offset for each timer [default: 0]
+    #[arg(long)]
+    start_stagger_secs: Option<u64>,
+    /// Seconds an output may stay on before it is forced off, whatever turned it on, as a
+    /// safety net against a stuck timer leaving a valve open [default: no limit]
+    #[arg(long)]
+    max_on_duration_secs: Option<u64>,
+    /// After a restart, turn outputs which were on back on for the rest of their run, rather
+    /// than turning them off
+    #[arg(long)]
+    resume_on_boot: bool,
+    /// Bring durations outside the allowed range to the nearest allowed one with a warning,
+    /// rather than rejecting the timer
+    #[arg(long)]
+    clamp_durations: bool,
+    /// An http:// URL to POST a JSON event to whenever an output turns on or off or a write
+    /// fails. May be given more than once.
+    #[arg(long = "webhook")]
+    webhooks: Vec<String>,
+    /// An http:// URL asked before each scheduled run whether to skip it, e.g. after rain. The
+    /// run is skipped if it answers `skip`, and goes ahead if it fails or is slow to answer.
+    #[arg(long = "skip-check")]
+    skip_check_url: Option<String>,
+    /// The unit durations are given and shown in as plain numbers, in forms, the API, CSV and
+    /// the add command [default: minutes]
+    #[arg(long, value_enum)]
+    duration_unit: Option<DurationUnit>,
+    /// Messages queued for the GPIO manager before senders wait for room. Timers wait, while
+    /// manual runs are refused with 503 Service Unavailable when it's full. [default: 32]
+    #[arg(long)]
+    gpio_channel_capacity: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print every timer as JSON, one per line
+    List,
+    /// Add a daily timer and print its id
+    Add {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        description: Option<String>,
+        /// How long to stay on for, in --duration-unit
+        #[arg(long)]
+        duration: u32,
+        /// Time of day to turn on, in %H:%M or %H:%M:%S format
+        #[arg(long)]
+        start: String,
+        /// The GPIO output pin to control, by number or alias [default: 476]
+        #[arg(long)]
+        output: Option<String>,
+        /// IANA name of the timezone the start time is in [default: --timezone]
+        #[arg(long)]
+        timezone: Option<String>,
+        /// Store the timer without running it
+        #[arg(long)]
+        disabled: bool,
+        /// Keep the output on outside the window and turn it off during it
+        #[arg(long)]
+        normally_on: bool,
+        /// The output's relay turns the load on when the pin is driven low
+        #[arg(long)]
+        active_low: bool,
+    },
+    /// Move a timer to the trash
+    Delete { id: Uuid },
+}
+
+/// Load the config file, if any, and override it with the flags which were passed
+fn config(args: Args) -> Result<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.db = args.db.or(config.db);
+    config.bind = args.bind.unwrap_or(config.bind);
+    config.simulate |= args.simulate;
+    config.durable |= args.durable;
+    config.unique_names |= args.unique_names;
+    config.trash_retention_hours = args
+        .trash_retention_hours
+        .unwrap_or(config.trash_retention_hours);
+    config.rate_limit_per_minute = args
+        .rate_limit_per_minute
+        .unwrap_or(config.rate_limit_per_minute);
+    config.username = args.username.or(config.username);
+    config.password = args.password.or(config.password);
+    config.protect_reads |= args.protect_reads;
+    config.audit_max_entries = args.audit_max_entries.unwrap_or(config.audit_max_entries);
+    config.history_max_entries = args
+        .history_max_entries
+        .unwrap_or(config.history_max_entries);
+    config.max_concurrent_outputs = args
+        .max_concurrent_outputs
+        .or(config.max_concurrent_outputs);
+    config.gpio_write_retries = args.gpio_write_retries.unwrap_or(config.gpio_write_retries);
+    config.gpio_retry_delay_ms = args
+        .gpio_retry_delay_ms
+        .unwrap_or(config.gpio_retry_delay_ms);
+    config.start_stagger_secs = args.start_stagger_secs.unwrap_or(config.start_stagger_secs);
+    config.max_on_duration_secs = args.max_on_duration_secs.or(config.max_on_duration_secs);
+    config.resume_on_boot |= args.resume_on_boot;
+    config.clamp_durations |= args.clamp_durations;
+    config.webhooks.extend(args.webhooks);
+    config.skip_check_url = args.skip_check_url.or(config.skip_check_url);
+    config.duration_unit = args.duration_unit.unwrap_or(config.duration_unit);
+    config.gpio_channel_capacity = args
+        .gpio_channel_capacity
+        .unwrap_or(config.gpio_channel_capacity);
+    config.min_pin = args.min_pin.unwrap_or(config.min_pin);
+    config.max_pin = args.max_pin.unwrap_or(config.max_pin);
+    config.timezone = args.timezone.or(config.timezone);
+    Ok(config)
+}
+
+fn open_db(config: &Config) -> Result<Arc<sled::Db>> {
+    let Some(db_path) = &config.db else {
+        anyhow::bail!("No database path given with --db or in the config file");
+    };
+    let db = util::open_db(db_path)?;
+    info!("Opened database at {:?}", db_path.display());
+    Ok(Arc::new(db))
+}
+
+/// The app state with the settings shared by the server and the subcommands
+fn app_state(
+    config: &Config,
+    db: Arc<sled::Db>,
+    gpio_tx: mpsc::Sender<GpioMessage>,
+) -> Result<AppState> {
+    let default_tz = match config.timezone.as_deref() {
+        Some(name) => Some(Tz::from_str(name).map_err(|_| Error::InvalidTimezone(name.into()))?),
+        None => None,
+    };
+    let state = AppState::new(db, gpio_tx)
+        .with_allowed_pins(config.min_pin..=config.max_pin)
+        .with_default_timezone(default_tz)
+        .with_durable(config.durable)
+        .with_unique_names(config.unique_names)
+        .with_clamp_durations(config.clamp_durations)
+        .with_pin_aliases(config.pin_aliases.clone())
+        .with_duration_unit(config.duration_unit)
+        .with_skip_check(
+            config
+                .skip_check_url
+                .as_deref()
+                .map(Webhook::from_str)
+                .transpose()?,
+        )
+        .with_trash_retention(chrono::Duration::hours(config.trash_retention_hours.into()))
+        .with_audit_max(config.audit_max_entries)
+        .with_history_max(config.history_max_entries)
+        .with_start_stagger(std::time::Duration::from_secs(config.start_stagger_secs));
+    let migrated = state.migrate_records()?;
+    if migrated > 0 {
+        info!("Upgraded {} timers stored by an older version.", migrated);
+    }
+    state.reindex()?;
+    Ok(state)
+}
+
+/// Run a subcommand against the database, without the GPIO manager or web server. The server
+/// must not be running, since it holds a lock on the database.
+fn manage(config: Config, command: Command) -> Result<()> {
+    // Nothing is scheduled, so messages for the GPIO manager are never sent
+    let (gpio_tx, _) = mpsc::channel(1);
+    let state = app_state(&config, open_db(&config)?, gpio_tx)?;
+    let user = AuthUser::default();
+    match command {
+        Command::List => {
+            for timer in state.get_all_interval_timers()? {
+                println!("{}", timer.to_json_string()?);
+            }
+        }
+        Command::Add {
+            name,
+            description,
+            duration,
+            start,
+            output,
+            timezone,
+            disabled,
+            normally_on,
+            active_low,
+        } => {
+            let mut n = NewDaily {
+                name,
+                description,
+                duration_on: duration,
+                duration_unit: config.duration_unit,
+                output: output.map_or(OutputRef::Pin(DEFAULT_OUTPUT), OutputRef::Alias),
+                output_alias: None,
+                start_time: start,
+                timezone,
+                enabled: Some(!disabled),
+                normally_on: Some(normally_on),
+                active_low: Some(active_low),
+            };
+            state.fill_new_daily(&mut n);
+            let timer = IntervalTimer::from_newdaily(n)?;
+            state.check_pin(timer.output)?;
+            state.insert_interval_timer(&timer)?;
+            state.audit(AuditAction::Create, Some(timer.get_id()), &user);
+            println!("{}", timer.get_id());
+        }
+        Command::Delete { id } => {
+            if state.delete_interval_timer(id)?.is_none() {
+                anyhow::bail!("No timer with id {}", id);
+            }
+            state.audit(AuditAction::Delete, Some(id), &user);
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
-async fn run(args: Args) -> Result<()> {
-    let db_arc = Arc::new(sled::open(&args.db)?);
-    let (man, gpio_tx) = GpioManager::new()?;
-    let _ = man.run()?;
-    info!("Opened database at {:?}", &args.db.display());
-    let state = AppState {
-        db: db_arc.clone(),
-        gpio_tx: gpio_tx.clone(),
+async fn run(config: Config) -> Result<()> {
+    let db_arc = open_db(&config)?;
+    if config.min_pin > config.max_pin {
+        anyhow::bail!("--min-pin must not be greater than --max-pin");
+    }
+    if config.max_concurrent_outputs == Some(0) {
+        anyhow::bail!("--max-concurrent-outputs must be at least 1");
+    }
+    if config.gpio_channel_capacity == 0 {
+        anyhow::bail!("--gpio-channel-capacity must be at least 1");
+    }
+    if config.max_on_duration_secs == Some(0) {
+        anyhow::bail!("--max-on-duration-secs must be at least 1");
+    }
+    let auth = match (config.username.clone(), config.password.clone()) {
+        (Some(username), Some(password)) => {
+            Some(BasicAuth::new(username, password).with_protect_reads(config.protect_reads))
+        }
+        (None, None) if config.protect_reads => {
+            anyhow::bail!("--protect-reads needs a --username and --password")
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("--username and --password must be given together"),
     };
+    let webhooks = config
+        .webhooks
+        .iter()
+        .map(|url| Webhook::from_str(url))
+        .collect::<Result<Vec<_>, _>>()?;
+    let allowed_pins = config.min_pin..=config.max_pin;
+    let (man, gpio_tx) = GpioManager::new(config.simulate, config.gpio_channel_capacity)?;
+    let man = man
+        .with_allowed_pins(allowed_pins.clone())
+        .with_max_concurrent_outputs(config.max_concurrent_outputs)
+        .with_write_retries(
+            config.gpio_write_retries,
+            std::time::Duration::from_millis(config.gpio_retry_delay_ms),
+        )
+        .with_max_on_duration(
+            config
+                .max_on_duration_secs
+                .map(std::time::Duration::from_secs),
+        )
+        .with_persisted_state(db_arc.open_tree(OUTPUT_STATE)?);
+    let (output_states, metrics, output_events) =
+        (man.output_states(), man.metrics(), man.output_events());
+    if !webhooks.is_empty() {
+        info!("Sending output events to {} webhooks", webhooks.len());
+        webhook::spawn_webhooks(webhooks, output_events.subscribe());
+    }
+    man.run()?;
+    let state = app_state(&config, db_arc.clone(), gpio_tx.clone())?
+        .with_output_states(output_states)
+        .with_output_events(output_events)
+        .with_metrics(metrics);
+    let resumed = state.restore_outputs(config.resume_on_boot).await?;
+    if resumed > 0 {
+        info!("Resumed {} runs cut short by the restart", resumed);
+    }
+    let _ = state.spawn_all_timers()?;
+    state.spawn_trash_purger();
+    state.spawn_history_recorder();
     // build our application with a route
-    let app = Router::new() // `GET /` goes to `root`
+    let mut app = routes();
+    if config.rate_limit_per_minute > 0 {
+        let limiter = RateLimiter::new(config.rate_limit_per_minute);
+        app = app.layer(middleware::from_fn_with_state(limiter, rate_limit));
+    }
+    if let Some(auth) = auth {
+        app = app.layer(middleware::from_fn_with_state(auth, require_auth));
+    }
+    let app = app.layer(request_log::layer()).with_state(state.clone());
+    let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+    info!("Shutting down");
+    state.shutdown().await?;
+
+    Ok(())
+}
+
+/// Every route served, before the optional rate limiting and authentication layers
+fn routes() -> Router<AppState> {
+    // `GET /` goes to `root`
+    Router::new()
         .route("/", get(sploosh::handlers::root))
         // `POST /new_timer
         .route("/new_submit", post(new_daily_form))
+        .route("/new_submit/:id", post(update_daily_form))
         .route("/new_timer", get(new_timer))
         .route("/all_timers", get(alltimers))
-        .route("/timer/:id", get(view_timer))
-        .with_state(state);
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await?;
+        .route("/timer/:id", get(view_timer).delete(delete_timer))
+        .route("/timer/:id/run", post(api::run_timer_now))
+        .route("/timer/:id/duplicate", post(api::duplicate_timer))
+        .route("/timer/:id/active", get(api::timer_active))
+        .route("/timer/:id/history", get(history::timer_history))
+        .route("/timer/:id/enable", post(enable_timer))
+        .route("/timer/:id/disable", post(disable_timer))
+        .route("/timer/:id/restore", post(restore_timer))
+        .route("/api/timers", get(api::list_timers).post(api::create_timer))
+        .route(
+            "/api/timers/:id",
+            get(api::get_timer).patch(api::patch_timer),
+        )
+        .route("/api/timers/bulk", post(api::create_timers))
+        .route("/api/export", get(api::export_timers))
+        .route("/api/export.csv", get(api::export_csv))
+        .route("/api/audit", get(audit::list_audit))
+        .route("/api/calendar.ics", get(calendar::calendar))
+        .route("/api/import", post(api::import_timers))
+        .route("/api/import.csv", post(api::import_csv))
+        .route("/api/outputs", get(api::list_outputs))
+        .route("/api/test-fire", post(api::test_fire))
+        .route("/api/reschedule", post(api::reschedule_timers))
+        .route("/api/schedule", get(api::schedule))
+        .route("/api/time", get(api::server_time))
+        .route("/api/openapi.json", get(openapi::openapi))
+        .route("/metrics", get(metrics::metrics))
+        .route("/healthz", get(api::healthz))
+        .route("/static/css/normalize.css", get(normalize_css))
+        .route("/static/css/skeleton.css", get(skeleton_css))
+        // Gzip responses for clients which accept it, which matters most for the stylesheets
+        // and the JSON exports. Added before the websocket and event stream routes so that
+        // they're left alone.
+        .layer(CompressionLayer::new())
+        .route("/ws", get(ws::output_events))
+        .route("/events", get(events::scheduler_events))
+}
 
-    Ok(())
+/// Resolves when the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 /// wrapper to trace the async runtime
 fn main() -> Result<()> {
-    let args = Args::parse();
-    tracing_subscriber::fmt::init();
+    let mut args = Args::parse();
+    if args.command.is_some() {
+        // Keep logs out of the way of subcommands' output
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
     debug!("Args: {:?}", args);
-    run(args)
+    let command = args.command.take();
+    let config = config(args)?;
+    debug!("Config: {:?}", config);
+    if let Some(command) = command {
+        return manage(config, command);
+    }
+    run(config)
         .map_err(|e| {
             error!("{}", e);
         })
         .unwrap();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database directory which is removed when dropped
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> TempDb {
+            TempDb(std::env::temp_dir().join(format!("sploosh-cli-{}", Uuid::new_v4())))
+        }
+
+        /// The config for the database, once any handle opened before has let go of it.
+        /// sled's flusher thread holds a dropped handle's file lock for a moment longer.
+        fn config(&self) -> Config {
+            for _ in 0..100 {
+                let probe = sled::Config::new().path(&self.0).flush_every_ms(None);
+                if probe.open().is_ok() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            Config {
+                db: Some(self.0.clone()),
+                ..Config::default()
+            }
+        }
+
+        /// The timers stored once the subcommand has let go of the database
+        fn timers(&self) -> Vec<IntervalTimer> {
+            let (gpio_tx, _) = mpsc::channel(1);
+            let state = app_state(&self.config(), open_db(&self.config()).unwrap(), gpio_tx);
+            state.unwrap().get_all_interval_timers().unwrap()
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn command(args: &[&str]) -> Command {
+        let args = Args::try_parse_from(["sploosh"].iter().chain(args)).unwrap();
+        args.command.unwrap()
+    }
+
+    #[test]
+    fn add_parses_its_flags() {
+        let add = command(&[
+            "add",
+            "--name",
+            "Lawn",
+            "--duration",
+            "15",
+            "--start",
+            "06:30",
+            "--output",
+            "7",
+            "--disabled",
+            "--active-low",
+        ]);
+        let Command::Add {
+            name,
+            duration,
+            start,
+            output,
+            disabled,
+            normally_on,
+            active_low,
+            ..
+        } = add
+        else {
+            panic!("expected add, got {:?}", add);
+        };
+        assert_eq!(
+            (name.as_str(), duration, start.as_str()),
+            ("Lawn", 15, "06:30")
+        );
+        assert_eq!(output.as_deref(), Some("7"));
+        assert!(disabled && active_low && !normally_on);
+    }
+
+    #[test]
+    fn subcommands_reject_missing_and_malformed_arguments() {
+        let missing_start = ["sploosh", "add", "--name", "Lawn", "--duration", "15"];
+        assert!(Args::try_parse_from(missing_start).is_err());
+        assert!(Args::try_parse_from(["sploosh", "delete", "not-a-uuid"]).is_err());
+        assert!(matches!(command(&["list"]), Command::List));
+    }
+
+    #[test]
+    fn add_list_and_delete_against_a_database() {
+        let db = TempDb::new();
+        let add = command(&[
+            "add",
+            "--name",
+            "Beds",
+            "--duration",
+            "20",
+            "--start",
+            "05:45",
+            "--output",
+            "7",
+            "--normally-on",
+        ]);
+        manage(db.config(), add).unwrap();
+        let timers = db.timers();
+        assert_eq!(timers.len(), 1);
+        let timer = &timers[0];
+        assert_eq!(timer.name.as_deref(), Some("Beds"));
+        assert_eq!(timer.output, 7);
+        assert!(timer.enabled && timer.normally_on && !timer.active_low);
+        assert_eq!(
+            timer.settings().duration_on(),
+            DurationUnit::default().duration(20)
+        );
+
+        manage(db.config(), Command::List).unwrap();
+        let id = timer.get_id().to_string();
+        manage(db.config(), command(&["delete", &id])).unwrap();
+        assert!(db.timers().is_empty());
+        assert!(manage(db.config(), command(&["delete", &id])).is_err());
+    }
+
+    #[test]
+    fn add_stores_nothing_when_the_timer_is_invalid() {
+        let db = TempDb::new();
+        let add = command(&[
+            "add",
+            "--name",
+            "Lawn",
+            "--duration",
+            "10",
+            "--start",
+            "25:00",
+        ]);
+        assert!(manage(db.config(), add).is_err());
+        assert!(db.timers().is_empty());
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let path = std::env::temp_dir().join(format!("sploosh-{}.toml", Uuid::new_v4()));
+        let file = r#"
+            bind = "0.0.0.0:8080"
+            db = "/var/lib/sploosh.db"
+            min_pin = 2
+            max_pin = 27
+            timezone = "Europe/Paris"
+        "#;
+        std::fs::write(&path, file).unwrap();
+        let args = Args::try_parse_from([
+            "sploosh",
+            "--config",
+            path.to_str().unwrap(),
+            "--bind",
+            "127.0.0.1:9000",
+            "--simulate",
+        ])
+        .unwrap();
+        let loaded = config(args);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.bind, "127.0.0.1:9000");
+        assert!(loaded.simulate);
+        assert_eq!(loaded.db, Some(PathBuf::from("/var/lib/sploosh.db")));
+        assert_eq!((loaded.min_pin, loaded.max_pin), (2, 27));
+        assert_eq!(loaded.timezone.as_deref(), Some("Europe/Paris"));
+        // Settings in neither keep their defaults
+        assert_eq!(
+            loaded.rate_limit_per_minute,
+            Config::default().rate_limit_per_minute
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_are_gzipped_for_clients_which_accept_it() {
+        use axum::{
+            body::Body,
+            http::{Request, StatusCode},
+        };
+        use tower::ServiceExt;
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(8).0);
+        let get = |uri: &str, gzip: bool| {
+            let mut request = Request::get(uri);
+            if gzip {
+                request = request.header("accept-encoding", "gzip");
+            }
+            let request = request.body(Body::empty()).unwrap();
+            routes().with_state(state.clone()).oneshot(request)
+        };
+        let encoding = |response: &axum::response::Response| {
+            response
+                .headers()
+                .get("content-encoding")
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        let css = get("/static/css/skeleton.css", true).await.unwrap();
+        assert_eq!(encoding(&css).as_deref(), Some("gzip"));
+        let css = get("/static/css/skeleton.css", false).await.unwrap();
+        assert_eq!(encoding(&css), None);
+        let page = get("/all_timers", true).await.unwrap();
+        assert_eq!(page.status(), StatusCode::OK);
+        assert_eq!(encoding(&page).as_deref(), Some("gzip"));
+
+        // The event stream is sent as it happens rather than buffered for compression
+        let events = get("/events", true).await.unwrap();
+        assert_eq!(events.status(), StatusCode::OK);
+        assert_eq!(encoding(&events), None);
+    }
+
+    #[tokio::test]
+    async fn timer_ids_in_paths_must_be_uuids_of_stored_timers() {
+        use axum::{
+            body::Body,
+            http::{Method, Request, StatusCode},
+        };
+        use tower::ServiceExt;
+
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(8).0);
+        let absent = Uuid::new_v4().to_string();
+        let endpoints = [
+            (Method::GET, "/timer/{}"),
+            (Method::DELETE, "/timer/{}"),
+            (Method::POST, "/timer/{}/run"),
+            (Method::POST, "/timer/{}/duplicate"),
+            (Method::GET, "/timer/{}/active"),
+            (Method::GET, "/timer/{}/history"),
+            (Method::POST, "/timer/{}/enable"),
+            (Method::GET, "/api/timers/{}"),
+        ];
+        for (method, path) in endpoints {
+            for (id, status) in [
+                ("not-a-uuid", StatusCode::BAD_REQUEST),
+                ("1234", StatusCode::BAD_REQUEST),
+                (&absent, StatusCode::NOT_FOUND),
+            ] {
+                let uri = path.replace("{}", id);
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+                let response = routes().with_state(state.clone()).oneshot(request);
+                assert_eq!(
+                    response.await.unwrap().status(),
+                    status,
+                    "{} {}",
+                    method,
+                    uri
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Counters exposed in the Prometheus text format, so that the controller can be scraped
+//! alongside other infrastructure
+use crate::{util::AppState, Error};
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Counters updated by the GPIO manager, shared with the app state through an `Arc`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Successful writes to output pins
+    pub gpio_writes: AtomicU64,
+    /// Writes to output pins which failed, including failures to open the pin
+    pub gpio_write_failures: AtomicU64,
+    /// Runs of timers whose write to their output landed, whether that turned the output on
+    /// or, for normally-on timers, off
+    pub timer_fires: AtomicU64,
+    /// Writes refused because the pin is outside the allowed range
+    pub gpio_writes_rejected: AtomicU64,
+    /// Times an output was forced off for staying on longer than the maximum
+    pub forced_offs: AtomicU64,
+}
+
+impl Metrics {
+    /// Render the counters along with the given gauges in the Prometheus text format
+    pub fn render(&self, timers: usize, corrupt_timers: usize, active_outputs: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric(
+            "sploosh_timers",
+            "gauge",
+            "Number of timers in the database.",
+            timers as u64,
+        );
+        metric(
+            "sploosh_corrupt_timer_records",
+            "gauge",
+            "Number of timer records in the database which can't be read.",
+            corrupt_timers as u64,
+        );
+        metric(
+            "sploosh_active_outputs",
+            "gauge",
+            "Number of outputs which are currently on.",
+            active_outputs as u64,
+        );
+        metric(
+            "sploosh_gpio_writes_total",
+            "counter",
+            "Successful writes to GPIO outputs.",
+            self.gpio_writes.load(Ordering::Relaxed),
+        );
+        metric(
+            "sploosh_gpio_write_failures_total",
+            "counter",
+            "Failed writes to GPIO outputs.",
+            self.gpio_write_failures.load(Ordering::Relaxed),
+        );
+        metric(
+            "sploosh_gpio_writes_rejected_total",
+            "counter",
+            "Writes to GPIO outputs outside the allowed pin range.",
+            self.gpio_writes_rejected.load(Ordering::Relaxed),
+        );
+        metric(
+            "sploosh_timer_fires_total",
+            "counter",
+            "Runs of timers which were written to their output.",
+            self.timer_fires.load(Ordering::Relaxed),
+        );
+        metric(
+            "sploosh_forced_offs_total",
+            "counter",
+            "Times an output was forced off for staying on longer than the maximum.",
+            self.forced_offs.load(Ordering::Relaxed),
+        );
+        out
+    }
+}
+
+#[axum::debug_handler]
+pub async fn metrics(State(state): State<AppState>) -> Result<impl IntoResponse, Error> {
+    let timers = state.get_all_interval_timers()?.len();
+    let corrupt_timers = state.corrupt_keys().len();
+    let active_outputs = state.active_outputs().len();
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(timers, corrupt_timers, active_outputs),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pins::MockBackend,
+        util::{GpioManager, GpioMessage, GpioOutMessage},
+    };
+    use std::{collections::HashMap, sync::Arc};
+    use tokio::sync::oneshot;
+
+    /// The value of each sample in a Prometheus text exposition
+    fn samples(text: &str) -> HashMap<&str, u64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(name, value)| (name, value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn every_metric_has_help_and_a_type() {
+        let text = Metrics::default().render(3, 1, 0);
+        let values = samples(&text);
+        assert_eq!(values["sploosh_timers"], 3);
+        assert_eq!(values["sploosh_corrupt_timer_records"], 1);
+        for name in values.keys() {
+            assert!(text.contains(&format!("# HELP {} ", name)));
+            assert!(text.contains(&format!("# TYPE {} ", name)));
+        }
+    }
+
+    #[tokio::test]
+    async fn the_endpoint_counts_writes_made_by_the_gpio_manager() {
+        let (man, tx) = GpioManager::with_backend(Box::new(MockBackend::new()), 8).unwrap();
+        let man = man.with_allowed_pins(1..=10);
+        let (counters, states) = (man.metrics(), man.output_states());
+        man.run().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tx.clone())
+            .with_metrics(counters)
+            .with_output_states(states);
+        let on = |output| GpioOutMessage {
+            output,
+            value: true,
+            active_low: false,
+        };
+        let run = |output, scheduled| GpioMessage::OnFor {
+            msg: on(output),
+            duration: chrono::Duration::minutes(10),
+            scheduled,
+        };
+        // Only the timer's run counts as a fire, not the test fire, the output turned on to
+        // rest between runs, or the write refused for being outside the allowed pins
+        for message in [
+            run(5, true),
+            run(6, false),
+            GpioMessage::Rest(on(7)),
+            on(50).into(),
+        ] {
+            tx.send(message).await.unwrap();
+        }
+        let (resp, rx) = oneshot::channel();
+        tx.send(GpioMessage::Flush(resp)).await.unwrap();
+        rx.await.unwrap();
+
+        let response = metrics(State(state)).await.unwrap().into_response();
+        let content_type = &response.headers()[header::CONTENT_TYPE];
+        assert_eq!(content_type, "text/plain; version=0.0.4");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let values = samples(&text);
+        assert_eq!(values["sploosh_gpio_writes_total"], 3);
+        assert_eq!(values["sploosh_gpio_writes_rejected_total"], 1);
+        assert_eq!(values["sploosh_timer_fires_total"], 1);
+        assert_eq!(values["sploosh_active_outputs"], 3);
+        assert_eq!(values["sploosh_timers"], 0);
+    }
+}
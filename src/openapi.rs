@@ -0,0 +1,782 @@
+//! A hand-written OpenAPI description of the JSON endpoints, so that integrators can generate
+//! clients. Keep it in step with the types in `api` and `handlers` when they change.
+use axum::Json;
+use serde_json::{json, Value};
+
+/// The OpenAPI 3.0 document served at `GET /api/openapi.json`
+pub fn document() -> Value {
+    let id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": {"type": "string", "format": "uuid"},
+    });
+    let error = json!({
+        "description": "The request failed",
+        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}},
+    });
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "sploosh",
+            "description": "Schedule GPIO outputs, e.g. irrigation valves, on a Raspberry Pi",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/api/timers": {
+                "get": {
+                    "summary": "List timers, optionally filtered and paginated",
+                    "parameters": [
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "minimum": 0}},
+                        {"name": "offset", "in": "query", "schema": {"type": "integer", "minimum": 0}},
+                        {"name": "page", "in": "query", "schema": {"type": "integer", "minimum": 1}},
+                        {"name": "per_page", "in": "query", "schema": {"type": "integer", "minimum": 1}},
+                        {"name": "name_contains", "in": "query", "schema": {"type": "string"}},
+                        {"name": "pin", "in": "query", "schema": {"type": "integer"}},
+                        {"name": "If-None-Match", "in": "header", "schema": {"type": "string"}},
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The matching timers. X-Total-Count holds how many match before pagination.",
+                            "headers": {
+                                "X-Total-Count": {"schema": {"type": "integer"}},
+                                "ETag": {"schema": {"type": "string"}},
+                            },
+                            "content": {"application/json": {"schema": {
+                                "type": "array",
+                                "items": {"$ref": "#/components/schemas/TimerView"},
+                            }}},
+                        },
+                        "304": {"description": "The matching timers are as they were with the ETag given in If-None-Match"},
+                        "default": error,
+                    },
+                },
+                "post": {
+                    "summary": "Create a daily timer",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/NewDaily"}}},
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "The new timer. Location holds its page's URL.",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerView"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/timers/bulk": {
+                "post": {
+                    "summary": "Create many daily timers at once. Nothing is stored unless all are valid.",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "type": "array",
+                            "items": {"$ref": "#/components/schemas/NewDaily"},
+                        }}},
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "The ids of the new timers, in the order given",
+                            "content": {"application/json": {"schema": {
+                                "type": "array",
+                                "items": {"type": "string", "format": "uuid"},
+                            }}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/timers/{id}": {
+                "get": {
+                    "summary": "Get a timer",
+                    "parameters": [
+                        id,
+                        {"name": "If-None-Match", "in": "header", "schema": {"type": "string"}},
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The timer",
+                            "headers": {"ETag": {"schema": {"type": "string"}}},
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerView"}}},
+                        },
+                        "304": {"description": "The timer has the ETag given in If-None-Match"},
+                        "default": error,
+                    },
+                },
+                "patch": {
+                    "summary": "Change some of a timer's fields",
+                    "parameters": [id],
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerPatch"}}},
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The changed timer",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerView"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/timer/{id}/run": {
+                "post": {
+                    "summary": "Turn a timer's output on right away",
+                    "parameters": [
+                        id,
+                        {
+                            "name": "duration",
+                            "in": "query",
+                            "description": "How long to run for in the server's --duration-unit, minutes by default. Defaults to the timer's duration.",
+                            "schema": {"type": "integer", "minimum": 1},
+                        },
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The run has started",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/RunResponse"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/timer/{id}/duplicate": {
+                "post": {
+                    "summary": "Copy a timer under a new id, with \" (copy)\" added to its name",
+                    "parameters": [id],
+                    "responses": {
+                        "201": {
+                            "description": "The copy. Location holds its page's URL.",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerView"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/timer/{id}/active": {
+                "get": {
+                    "summary": "Whether a timer is in one of its runs right now, going by its schedule",
+                    "parameters": [id],
+                    "responses": {
+                        "200": {
+                            "description": "Whether it's active and when the run ends",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TimerActivity"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/timer/{id}/history": {
+                "get": {
+                    "summary": "A timer's recent runs, newest first",
+                    "parameters": [
+                        id,
+                        {"name": "limit", "in": "query", "schema": {"type": "integer", "default": 20}},
+                        {"name": "If-None-Match", "in": "header", "schema": {"type": "string"}},
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The runs",
+                            "headers": {"ETag": {"schema": {"type": "string"}}},
+                            "content": {"application/json": {"schema": {
+                                "type": "array",
+                                "items": {"$ref": "#/components/schemas/FireRecord"},
+                            }}},
+                        },
+                        "304": {"description": "No runs have been made since those with the ETag given in If-None-Match"},
+                        "default": error,
+                    },
+                },
+            },
+            "/events": {
+                "get": {
+                    "summary": "Timer runs and changes as they happen, as server-sent events",
+                    "responses": {
+                        "200": {
+                            "description": "An event stream. Each event is named after its kind and carries a SchedulerEvent as JSON.",
+                            "content": {"text/event-stream": {"schema": {"$ref": "#/components/schemas/SchedulerEvent"}}},
+                        },
+                    },
+                },
+            },
+            "/api/test-fire": {
+                "post": {
+                    "summary": "Turn an output on for a few seconds without saving a timer",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/TestFire"}}},
+                    },
+                    "responses": {
+                        "204": {"description": "The output has been turned back off"},
+                        "default": error,
+                    },
+                },
+            },
+            "/api/reschedule": {
+                "post": {
+                    "summary": "Restart every timer's task from the database",
+                    "responses": {
+                        "200": {
+                            "description": "How many timers were rescheduled",
+                            "content": {"application/json": {"schema": {
+                                "type": "object",
+                                "required": ["rescheduled"],
+                                "properties": {"rescheduled": {"type": "integer"}},
+                            }}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/outputs": {
+                "get": {
+                    "summary": "The last written state of every output",
+                    "responses": {
+                        "200": {
+                            "description": "Outputs sorted by pin",
+                            "content": {"application/json": {"schema": {
+                                "type": "array",
+                                "items": {"$ref": "#/components/schemas/OutputState"},
+                            }}},
+                        },
+                    },
+                },
+            },
+            "/api/export": {
+                "get": {
+                    "summary": "Download every timer as a backup",
+                    "responses": {
+                        "200": {
+                            "description": "The backup",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Backup"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/export.csv": {
+                "get": {
+                    "summary": "Download every timer as CSV, with durations in the configured unit and schedules other than daily as JSON",
+                    "responses": {
+                        "200": {
+                            "description": "A header row of id,name,description,output,start_time,duration_on,enabled and a row per timer",
+                            "content": {"text/csv": {"schema": {"type": "string"}}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/import": {
+                "post": {
+                    "summary": "Restore timers from a backup",
+                    "parameters": [{
+                        "name": "mode",
+                        "in": "query",
+                        "description": "What to do with timers whose id is already in use",
+                        "schema": {"type": "string", "enum": ["skip", "overwrite", "regenerate"], "default": "skip"},
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Backup"}}},
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "How many timers were imported and skipped",
+                            "content": {"application/json": {"schema": {
+                                "type": "object",
+                                "required": ["inserted", "skipped"],
+                                "properties": {
+                                    "inserted": {"type": "integer"},
+                                    "skipped": {"type": "integer"},
+                                },
+                            }}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/import.csv": {
+                "post": {
+                    "summary": "Create or update timers from a file in the format of /api/export.csv",
+                    "parameters": [
+                        {
+                            "name": "mode",
+                            "in": "query",
+                            "description": "What to do with timers whose id is already in use",
+                            "schema": {"type": "string", "enum": ["skip", "overwrite", "regenerate"], "default": "skip"},
+                        },
+                        {
+                            "name": "strict",
+                            "in": "query",
+                            "description": "Import nothing if any row is invalid",
+                            "schema": {"type": "boolean", "default": false},
+                        },
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {"text/csv": {"schema": {"type": "string"}}},
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "How many timers were imported and skipped, and the invalid rows",
+                            "content": {"application/json": {"schema": {
+                                "type": "object",
+                                "required": ["inserted", "skipped"],
+                                "properties": {
+                                    "inserted": {"type": "integer"},
+                                    "skipped": {"type": "integer"},
+                                    "errors": {"type": "array", "items": {"type": "string"}},
+                                },
+                            }}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/audit": {
+                "get": {
+                    "summary": "The most recent changes to timers and manual runs, newest first",
+                    "parameters": [{"name": "limit", "in": "query", "schema": {"type": "integer", "default": 100}}],
+                    "responses": {
+                        "200": {
+                            "description": "The audit entries",
+                            "content": {"application/json": {"schema": {
+                                "type": "array",
+                                "items": {"$ref": "#/components/schemas/AuditEntry"},
+                            }}},
+                        },
+                        "default": error,
+                    },
+                },
+            },
+            "/api/calendar.ics": {
+                "get": {
+                    "summary": "The watering schedule as an iCalendar document",
+                    "responses": {
+                        "200": {"description": "The calendar", "content": {"text/calendar": {}}},
+                        "default": error,
+                    },
+                },
+            },
+            "/api/time": {
+                "get": {
+                    "summary": "The server's current time, timezone and uptime",
+                    "responses": {
+                        "200": {
+                            "description": "The current time",
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/ServerTime"}}},
+                        },
+                    },
+                },
+            },
+            "/healthz": {
+                "get": {
+                    "summary": "Whether the database and GPIO manager are usable",
+                    "responses": {
+                        "200": {"description": "Healthy", "content": {"text/plain": {}}},
+                        "503": {"description": "Unhealthy, with the reason", "content": {"text/plain": {}}},
+                    },
+                },
+            },
+        },
+        "components": {"schemas": schemas()},
+    })
+}
+
+fn schemas() -> Value {
+    let duration = json!({
+        "description": "A length of time: a whole number of the server's --duration-unit in timer views, and whole seconds and nanoseconds in backups",
+        "oneOf": [
+            {"type": "integer", "minimum": 0},
+            {
+                "type": "object",
+                "required": ["secs", "nanos"],
+                "properties": {
+                    "secs": {"type": "integer", "minimum": 0},
+                    "nanos": {"type": "integer", "minimum": 0},
+                },
+            },
+        ],
+    });
+    let time_of_day = json!({"type": "string", "description": "%H:%M:%S", "example": "06:30:00"});
+    json!({
+        "NewDaily": {
+            "type": "object",
+            "required": ["name", "duration_on", "output", "start_time"],
+            "properties": {
+                "name": {"type": "string"},
+                "description": {"type": "string", "nullable": true},
+                "duration_on": {"type": "integer", "minimum": 1, "description": "In the server's --duration-unit, minutes by default"},
+                "output": {
+                    "oneOf": [
+                        {"type": "integer", "minimum": 0, "maximum": 65535},
+                        {"type": "string", "description": "A pin alias from the config file"},
+                    ],
+                },
+                "start_time": {"type": "string", "description": "%H:%M or %H:%M:%S", "example": "06:30"},
+                "timezone": {
+                    "type": "string",
+                    "nullable": true,
+                    "description": "IANA name, e.g. America/New_York. Defaults to the server's.",
+                },
+                "enabled": {"type": "boolean", "nullable": true, "default": true},
+                "normally_on": {
+                    "type": "boolean",
+                    "nullable": true,
+                    "default": false,
+                    "description": "Keep the output on outside the window and turn it off during it",
+                },
+                "active_low": {
+                    "type": "boolean",
+                    "nullable": true,
+                    "default": false,
+                    "description": "The output's relay turns the load on when the pin is driven low",
+                },
+            },
+        },
+        "ServerTime": {
+            "type": "object",
+            "required": ["utc", "local", "local_offset_secs", "uptime_secs"],
+            "properties": {
+                "utc": {"type": "string", "format": "date-time"},
+                "local": {"type": "string", "format": "date-time"},
+                "local_offset_secs": {"type": "integer", "description": "Seconds east of UTC"},
+                "timezone": {
+                    "type": "string",
+                    "nullable": true,
+                    "description": "Default timezone for timers, or null for the server's",
+                },
+                "timezone_time": {"type": "string", "nullable": true, "example": "2024-05-01T06:30:00"},
+                "uptime_secs": {"type": "integer", "minimum": 0},
+            },
+        },
+        "TimerActivity": {
+            "type": "object",
+            "required": ["active"],
+            "properties": {
+                "active": {"type": "boolean"},
+                "until": {
+                    "type": "string",
+                    "nullable": true,
+                    "example": "06:30:00",
+                    "description": "When the run ends in the timer's timezone, or null if it isn't active",
+                },
+            },
+        },
+        "TimerPatch": {
+            "type": "object",
+            "description": "Fields to change. Missing fields are left as they are.",
+            "properties": {
+                "name": {"type": "string"},
+                "description": {"type": "string"},
+                "duration_on": {"type": "integer", "minimum": 1, "description": "In the server's --duration-unit, minutes by default"},
+                "output": {
+                    "oneOf": [
+                        {"type": "integer", "minimum": 0, "maximum": 65535},
+                        {"type": "string", "description": "A pin alias from the config file"},
+                    ],
+                },
+                "start_time": {"type": "string", "description": "%H:%M or %H:%M:%S"},
+                "timezone": {"type": "string", "description": "IANA name, or empty for the server's"},
+                "enabled": {"type": "boolean"},
+                "normally_on": {"type": "boolean"},
+                "active_low": {"type": "boolean"},
+            },
+        },
+        "IntervalTimer": {
+            "type": "object",
+            "required": ["id", "settings", "output", "enabled", "created_at", "updated_at"],
+            "properties": {
+                "id": {"type": "string", "format": "uuid"},
+                "name": {"type": "string", "nullable": true},
+                "description": {"type": "string", "nullable": true},
+                "settings": {"$ref": "#/components/schemas/IntervalSettings"},
+                "output": {"type": "integer"},
+                "output_alias": {"type": "string", "nullable": true},
+                "enabled": {"type": "boolean"},
+                "normally_on": {"type": "boolean"},
+                "active_low": {"type": "boolean"},
+                "last_fired_at": {"type": "string", "format": "date-time", "nullable": true},
+                "fire_count": {"type": "integer", "minimum": 0, "description": "Times the timer has turned its output on"},
+                "created_at": {"type": "string", "format": "date-time"},
+                "updated_at": {"type": "string", "format": "date-time"},
+                "schema_version": {"type": "integer", "description": "Layout version of the stored record"},
+            },
+        },
+        "TimerView": {
+            "description": "A timer along with when it will next run",
+            "allOf": [
+                {"$ref": "#/components/schemas/IntervalTimer"},
+                {
+                    "type": "object",
+                    "required": ["duration_human", "duration_unit"],
+                    "properties": {
+                        "next_fire_secs": {
+                            "type": "integer",
+                            "nullable": true,
+                            "description": "Seconds until the timer next turns on, if it has a fixed start time",
+                        },
+                        "duration_human": {"type": "string", "example": "1 h 30 min"},
+                        "duration_unit": {"type": "string", "enum": ["minutes", "seconds"]},
+                    },
+                },
+            ],
+        },
+        "IntervalSettings": {
+            "type": "object",
+            "required": ["duration_on", "duration_off"],
+            "properties": {
+                "duration_on": duration,
+                "duration_off": duration,
+                "start_time": {"allOf": [time_of_day], "nullable": true},
+                "schedule": {"$ref": "#/components/schemas/Schedule"},
+                "tz": {"type": "string", "nullable": true, "description": "IANA timezone name"},
+            },
+        },
+        "Schedule": {
+            "description": "When a timer runs. Variants without data are plain strings.",
+            "oneOf": [
+                {"type": "string", "enum": ["Daily", "Cycling"]},
+                {
+                    "type": "object",
+                    "required": ["Weekly"],
+                    "properties": {"Weekly": {"type": "array", "items": {
+                        "type": "string",
+                        "enum": ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+                    }}},
+                },
+                {
+                    "type": "object",
+                    "required": ["Windows"],
+                    "properties": {"Windows": {
+                        "type": "array",
+                        "description": "Pairs of start time and duration",
+                        "items": {"type": "array", "minItems": 2, "maxItems": 2, "items": {}},
+                    }},
+                },
+                {
+                    "type": "object",
+                    "required": ["Cron"],
+                    "properties": {"Cron": {"type": "string"}},
+                },
+                {
+                    "type": "object",
+                    "required": ["Sun"],
+                    "properties": {"Sun": {
+                        "type": "object",
+                        "required": ["event", "latitude", "longitude", "offset_secs"],
+                        "properties": {
+                            "event": {"type": "string", "enum": ["Sunrise", "Sunset"]},
+                            "latitude": {"type": "number"},
+                            "longitude": {"type": "number"},
+                            "offset_secs": {"type": "integer"},
+                        },
+                    }},
+                },
+                {
+                    "type": "object",
+                    "required": ["Input"],
+                    "properties": {"Input": {
+                        "type": "object",
+                        "required": ["pin", "edge", "debounce_ms"],
+                        "properties": {
+                            "pin": {"type": "integer"},
+                            "edge": {"type": "string", "enum": ["Rising", "Falling"]},
+                            "debounce_ms": {"type": "integer"},
+                        },
+                    }},
+                },
+            ],
+        },
+        "RunResponse": {
+            "type": "object",
+            "required": ["id", "output", "off_at"],
+            "properties": {
+                "id": {"type": "string", "format": "uuid"},
+                "output": {"type": "integer"},
+                "off_at": {"type": "string", "format": "date-time"},
+            },
+        },
+        "TestFire": {
+            "type": "object",
+            "required": ["output", "seconds"],
+            "properties": {
+                "output": {"type": "integer"},
+                "seconds": {"type": "integer", "minimum": 1, "maximum": 60},
+                "active_low": {
+                    "type": "boolean",
+                    "nullable": true,
+                    "description": "Defaults to the polarity of the timers controlling the output",
+                },
+            },
+        },
+        "OutputState": {
+            "type": "object",
+            "required": ["pin", "on", "high"],
+            "properties": {
+                "pin": {"type": "integer"},
+                "on": {"type": "boolean"},
+                "high": {"type": "boolean"},
+            },
+        },
+        "Backup": {
+            "type": "object",
+            "required": ["version", "timers"],
+            "properties": {
+                "version": {"type": "integer", "enum": [1]},
+                "timers": {"type": "array", "items": {"$ref": "#/components/schemas/IntervalTimer"}},
+            },
+        },
+        "AuditEntry": {
+            "type": "object",
+            "required": ["at", "action"],
+            "properties": {
+                "at": {"type": "string", "format": "date-time"},
+                "action": {"type": "string", "enum": ["create", "update", "delete", "restore", "fire", "reschedule"]},
+                "timer_id": {"type": "string", "format": "uuid", "nullable": true},
+                "user": {"type": "string", "nullable": true},
+            },
+        },
+        "SchedulerEvent": {
+            "type": "object",
+            "required": ["at", "kind", "timer_id", "message"],
+            "properties": {
+                "at": {"type": "string", "format": "date-time"},
+                "kind": {
+                    "type": "string",
+                    "enum": ["fired", "skipped", "failed", "created", "updated", "deleted", "restored"],
+                },
+                "timer_id": {"type": "string", "format": "uuid"},
+                "message": {"type": "string", "example": "Timer \"Front lawn\" turned on for 10 min"},
+            },
+        },
+        "FireRecord": {
+            "type": "object",
+            "required": ["on_at", "outcome"],
+            "properties": {
+                "on_at": {"type": "string", "format": "date-time", "description": "When the run started, or was due to"},
+                "off_at": {"type": "string", "format": "date-time", "nullable": true, "description": "When the run was due to end"},
+                "outcome": {"type": "string", "enum": ["fired", "skipped", "failed"]},
+            },
+        },
+        "Error": {
+            "type": "object",
+            "required": ["error", "message"],
+            "properties": {
+                "error": {"type": "string", "description": "Machine-readable code, e.g. NotFound"},
+                "message": {"type": "string"},
+                "fields": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "description": "The problem with each invalid field, for validation errors",
+                },
+            },
+        },
+    })
+}
+
+#[axum::debug_handler]
+pub async fn openapi() -> Json<Value> {
+    Json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::TimerView,
+        handlers::{DurationUnit, NewDaily, OutputRef},
+        IntervalTimer,
+    };
+    use std::collections::BTreeSet;
+
+    fn property_names(schema: &Value) -> BTreeSet<String> {
+        schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match value.as_str() {
+                        Some(target) if key == "$ref" => found.push(target),
+                        _ => refs(value, found),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+            _ => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn the_document_lists_the_core_paths_and_its_references_resolve() {
+        let Json(served) = openapi().await;
+        let doc: Value = serde_json::from_slice(&serde_json::to_vec(&served).unwrap()).unwrap();
+        assert_eq!(doc["openapi"], "3.0.3");
+        for (path, method) in [
+            ("/api/timers", "get"),
+            ("/api/timers", "post"),
+            ("/api/timers/{id}", "get"),
+            ("/api/timers/{id}", "patch"),
+            ("/api/timers/bulk", "post"),
+            ("/timer/{id}/history", "get"),
+        ] {
+            assert!(
+                doc["paths"][path][method].is_object(),
+                "{} {}",
+                method,
+                path
+            );
+        }
+
+        let mut found = vec![];
+        refs(&doc, &mut found);
+        assert!(!found.is_empty());
+        for target in found {
+            let name = target.strip_prefix("#/components/schemas/").unwrap();
+            assert!(doc["components"]["schemas"][name].is_object(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn schemas_name_the_fields_actually_sent() {
+        let doc = document();
+        let schemas = &doc["components"]["schemas"];
+
+        let new_daily = NewDaily {
+            name: "Beds".to_owned(),
+            description: None,
+            duration_on: 10,
+            duration_unit: DurationUnit::default(),
+            output: OutputRef::Pin(7),
+            output_alias: None,
+            start_time: "06:30".to_owned(),
+            timezone: None,
+            enabled: None,
+            normally_on: None,
+            active_low: None,
+        };
+        let sent = serde_json::to_value(&new_daily).unwrap();
+        let sent: BTreeSet<String> = sent.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(sent, property_names(&schemas["NewDaily"]));
+
+        let timer = IntervalTimer::new(None, None, crate::IntervalSettings::default());
+        let view = TimerView::new(&timer, DurationUnit::default()).unwrap();
+        let sent = serde_json::to_value(&view).unwrap();
+        let sent: BTreeSet<String> = sent.as_object().unwrap().keys().cloned().collect();
+        let mut described = property_names(&schemas["IntervalTimer"]);
+        described.extend(property_names(&schemas["TimerView"]["allOf"][1]));
+        assert_eq!(sent, described);
+        let settings = view.timer["settings"].as_object().unwrap();
+        let settings: BTreeSet<String> = settings.keys().cloned().collect();
+        assert_eq!(settings, property_names(&schemas["IntervalSettings"]));
+    }
+}
@@ -0,0 +1,135 @@
+//! The GPIO pins driven by the `GpioManager`, opened through a backend so that real sysfs pins
+//! can be swapped for simulated ones when running off the Pi
+use crate::Error;
+use gpio::{
+    sysfs::{SysFsGpioInput, SysFsGpioOutput},
+    GpioIn, GpioOut,
+};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+pub trait OutputPin: Debug + Send {
+    /// Drive the pin high or low
+    fn set(&mut self, high: bool) -> Result<(), Error>;
+}
+
+pub trait InputPin: Debug + Send {
+    /// Whether the pin is high
+    fn get(&mut self) -> Result<bool, Error>;
+}
+
+/// Opens the pins which the `GpioManager` reads and writes
+pub trait GpioBackend: Debug + Send {
+    fn open_output(&mut self, pin: u16) -> Result<Box<dyn OutputPin>, Error>;
+    fn open_input(&mut self, pin: u16) -> Result<Box<dyn InputPin>, Error>;
+}
+
+impl OutputPin for SysFsGpioOutput {
+    fn set(&mut self, high: bool) -> Result<(), Error> {
+        self.set_value(high).map_err(|e| Error::Anyhow(e.into()))
+    }
+}
+
+impl InputPin for SysFsGpioInput {
+    fn get(&mut self) -> Result<bool, Error> {
+        let value = self.read_value().map_err(|e| Error::Anyhow(e.into()))?;
+        Ok(value.into())
+    }
+}
+
+/// Real pins exported through `/sys/class/gpio`
+#[derive(Debug, Default)]
+pub struct SysFsBackend;
+
+impl GpioBackend for SysFsBackend {
+    fn open_output(&mut self, pin: u16) -> Result<Box<dyn OutputPin>, Error> {
+        let pin = SysFsGpioOutput::open(pin).map_err(|e| Error::Anyhow(e.into()))?;
+        Ok(Box::new(pin))
+    }
+
+    fn open_input(&mut self, pin: u16) -> Result<Box<dyn InputPin>, Error> {
+        let pin = SysFsGpioInput::open(pin).map_err(|e| Error::Anyhow(e.into()))?;
+        Ok(Box::new(pin))
+    }
+}
+
+/// In-memory pins which log writes instead of touching sysfs. Every write is recorded, and
+/// reading a pin returns the last value written to it, or low.
+#[derive(Debug, Default, Clone)]
+pub struct MockBackend {
+    /// Every write in order, as `(pin, high)`
+    pub writes: Arc<Mutex<Vec<(u16, bool)>>>,
+    /// The current level of each pin which has been written
+    pub levels: Arc<Mutex<HashMap<u16, bool>>>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    fn pin(&self, pin: u16) -> MockPin {
+        MockPin {
+            pin,
+            backend: self.clone(),
+        }
+    }
+}
+
+impl GpioBackend for MockBackend {
+    fn open_output(&mut self, pin: u16) -> Result<Box<dyn OutputPin>, Error> {
+        Ok(Box::new(self.pin(pin)))
+    }
+
+    fn open_input(&mut self, pin: u16) -> Result<Box<dyn InputPin>, Error> {
+        Ok(Box::new(self.pin(pin)))
+    }
+}
+
+#[derive(Debug)]
+pub struct MockPin {
+    pin: u16,
+    backend: MockBackend,
+}
+
+impl OutputPin for MockPin {
+    fn set(&mut self, high: bool) -> Result<(), Error> {
+        info!(
+            "Simulated pin {} set {}",
+            self.pin,
+            if high { "high" } else { "low" }
+        );
+        self.backend.writes.lock().unwrap().push((self.pin, high));
+        self.backend.levels.lock().unwrap().insert(self.pin, high);
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin {
+    fn get(&mut self) -> Result<bool, Error> {
+        let levels = self.backend.levels.lock().unwrap();
+        Ok(levels.get(&self.pin).copied().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_pins_read_back_what_was_written() {
+        let mut backend = MockBackend::new();
+        let mut out = backend.open_output(17).unwrap();
+        let mut input = backend.open_input(17).unwrap();
+        assert!(!input.get().unwrap(), "unwritten pins read low");
+        out.set(true).unwrap();
+        assert!(input.get().unwrap());
+        out.set(false).unwrap();
+        assert!(!input.get().unwrap());
+        assert_eq!(*backend.writes.lock().unwrap(), [(17, true), (17, false)]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Per-client rate limiting of requests which change timers, so that a runaway script can't
+//! flood the database or spawn thousands of timer tasks
+use crate::Error;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Clients tracked before buckets which have refilled are forgotten
+const MAX_CLIENTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A token bucket per client IP, each holding up to `per_minute` requests and refilling at
+/// that rate
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> RateLimiter {
+        RateLimiter {
+            per_minute,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a token from the client's bucket, returning whether there was one
+    pub fn check(&self, client: IpAddr) -> bool {
+        let (capacity, now) = (f64::from(self.per_minute), Instant::now());
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            (bucket.tokens + elapsed * capacity / 60.0).min(capacity)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Middleware refusing requests which change state with a 429 once the client has used up its
+/// bucket. Reads aren't limited.
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+    // Without the connection info every client shares one bucket
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |info| info.0.ip());
+    if limiter.check(client) {
+        next.run(request).await
+    } else {
+        warn!(
+            "Rate limited {} {} from {}",
+            request.method(),
+            request.uri().path(),
+            client
+        );
+        Error::RateLimited.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::post, Router};
+    use tower::ServiceExt;
+
+    fn limited(per_minute: u32) -> Router {
+        let limiter = RateLimiter::new(per_minute);
+        Router::new()
+            .route(
+                "/timers",
+                post(|| async { "created" }).get(|| async { "listed" }),
+            )
+            .layer(axum::middleware::from_fn_with_state(limiter, rate_limit))
+    }
+
+    async fn send(app: &Router, method: Method, ip: [u8; 4]) -> StatusCode {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/timers")
+            .body(Body::empty())
+            .unwrap();
+        let addr = SocketAddr::from((ip, 40000));
+        request.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_request_after_the_limit_is_refused_until_the_bucket_refills() {
+        let app = limited(3);
+        let client = [192, 168, 1, 20];
+        for _ in 0..3 {
+            assert_eq!(send(&app, Method::POST, client).await, StatusCode::OK);
+        }
+        assert_eq!(
+            send(&app, Method::POST, client).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // Reads and other clients aren't affected
+        assert_eq!(send(&app, Method::GET, client).await, StatusCode::OK);
+        assert_eq!(
+            send(&app, Method::POST, [192, 168, 1, 21]).await,
+            StatusCode::OK
+        );
+
+        // Three a minute is one every 20 seconds
+        tokio::time::advance(std::time::Duration::from_secs(20)).await;
+        assert_eq!(send(&app, Method::POST, client).await, StatusCode::OK);
+        assert_eq!(
+            send(&app, Method::POST, client).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Structured logging of every request, including the id of the timer it touched if any
+use axum::{body::Body, extract::Request, http::Response};
+use std::time::Duration;
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::TraceLayer,
+};
+use tracing::{field::Empty, info, Span};
+use uuid::Uuid;
+
+/// The id of the timer a request is about, taken from the first segment of its path which is
+/// a UUID, e.g. `/timer/:id`
+pub fn timer_id(path: &str) -> Option<Uuid> {
+    path.split('/')
+        .find_map(|segment| Uuid::parse_str(segment).ok())
+}
+
+/// Open a span for a request with its method, path and timer id, leaving the status and latency
+/// to be recorded once it has been handled
+pub fn request_span(request: &Request) -> Span {
+    let path = request.uri().path();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %path,
+        timer_id = Empty,
+        status = Empty,
+        latency_ms = Empty,
+    );
+    if let Some(id) = timer_id(path) {
+        span.record("timer_id", tracing::field::display(id));
+    }
+    span
+}
+
+pub fn record_response(response: &Response<Body>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    info!("Handled request");
+}
+
+pub type RequestLogLayer = TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    fn(&Request) -> Span,
+    (),
+    fn(&Response<Body>, Duration, &Span),
+>;
+
+/// A layer logging each request's method, path, timer id, status and latency
+pub fn layer() -> RequestLogLayer {
+    TraceLayer::new_for_http()
+        .make_span_with(request_span as fn(&Request) -> Span)
+        .on_request(())
+        .on_response(record_response as fn(&Response<Body>, Duration, &Span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    /// Collects the fields recorded on every span, by name
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{:?}", value);
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_owned(), value);
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Fields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    fn logged(uri: &str, status: u16) -> HashMap<String, String> {
+        let fields = Fields::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let request = Request::post(uri).body(Body::empty()).unwrap();
+            let span = request_span(&request);
+            let response = Response::builder()
+                .status(status)
+                .body(Body::empty())
+                .unwrap();
+            record_response(&response, Duration::from_millis(12), &span);
+        });
+        let fields = fields.0.lock().unwrap().clone();
+        fields
+    }
+
+    #[test]
+    fn the_span_records_the_timer_a_request_touched() {
+        let id = Uuid::new_v4();
+        let fields = logged(&format!("/timer/{}/enable?x=1", id), 303);
+        assert_eq!(fields["timer_id"], id.to_string());
+        assert_eq!(fields["method"], "POST");
+        assert_eq!(fields["path"], format!("/timer/{}/enable", id));
+        assert_eq!(fields["status"], "303");
+        assert_eq!(fields["latency_ms"], "12");
+    }
+
+    #[test]
+    fn requests_about_no_timer_leave_the_id_empty() {
+        let fields = logged("/all_timers", 200);
+        assert!(!fields.contains_key("timer_id"));
+        assert_eq!(timer_id("/timer/not-a-uuid"), None);
+    }
+}
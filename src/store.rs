@@ -0,0 +1,170 @@
+//! Where timers are kept, behind a trait so that code which only needs to store and look up
+//! timers can work with something other than sled, e.g. a map in memory.
+//!
+//! The handlers don't go through it. They take `State<AppState>` and use its sled storage
+//! directly, since they rely on the indexes, trash and atomic run counts which the trait
+//! doesn't cover.
+use crate::{
+    util::{read_timer, AppState},
+    Error, IntervalTimer,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+pub trait TimerStore: Send + Sync {
+    /// Store a timer, returning the one it replaced if there was one
+    fn insert(&self, timer: &IntervalTimer) -> Result<Option<IntervalTimer>, Error>;
+    fn get(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error>;
+    /// Every stored timer, in no particular order
+    fn all(&self) -> Result<Vec<IntervalTimer>, Error>;
+    /// Remove a timer, returning it if it was stored
+    fn remove(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error>;
+}
+
+/// Timers stored as JSON in a sled tree keyed by id, the way `AppState` stores them but without
+/// its indexes, trash or run counts
+#[derive(Clone)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+impl SledStore {
+    pub fn new(tree: sled::Tree) -> SledStore {
+        SledStore { tree }
+    }
+
+    /// Use the database's default tree
+    pub fn from_db(db: &Arc<sled::Db>) -> SledStore {
+        SledStore::new((***db).clone())
+    }
+}
+
+impl TimerStore for SledStore {
+    fn insert(&self, timer: &IntervalTimer) -> Result<Option<IntervalTimer>, Error> {
+        let prev = self
+            .tree
+            .insert(timer.get_id().as_bytes(), timer.to_json_vec()?)?;
+        prev.map(IntervalTimer::from_json_slice).transpose()
+    }
+
+    fn get(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        let value = self.tree.get(id.as_bytes())?;
+        value.map(IntervalTimer::from_json_slice).transpose()
+    }
+
+    /// Records which can't be read are logged and skipped
+    fn all(&self) -> Result<Vec<IntervalTimer>, Error> {
+        self.tree
+            .iter()
+            .filter_map(|entry| match entry {
+                Ok((key, value)) => read_timer(&key, &value).map(Ok),
+                Err(e) => Some(Err(e.into())),
+            })
+            .collect()
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        let prev = self.tree.remove(id.as_bytes())?;
+        prev.map(IntervalTimer::from_json_slice).transpose()
+    }
+}
+
+/// Timers kept in memory and lost when dropped, e.g. for trying out code which stores timers
+/// without a database
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    timers: Mutex<HashMap<Uuid, IntervalTimer>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl TimerStore for MemoryStore {
+    fn insert(&self, timer: &IntervalTimer) -> Result<Option<IntervalTimer>, Error> {
+        let mut timers = self.timers.lock().unwrap();
+        Ok(timers.insert(timer.get_id(), timer.clone()))
+    }
+
+    fn get(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        Ok(self.timers.lock().unwrap().get(&id).cloned())
+    }
+
+    fn all(&self) -> Result<Vec<IntervalTimer>, Error> {
+        Ok(self.timers.lock().unwrap().values().cloned().collect())
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        Ok(self.timers.lock().unwrap().remove(&id))
+    }
+}
+
+/// The app's own storage, keeping its indexes up to date. Removed timers go to the trash.
+impl TimerStore for AppState {
+    fn insert(&self, timer: &IntervalTimer) -> Result<Option<IntervalTimer>, Error> {
+        self.insert_interval_timer(timer)
+    }
+
+    fn get(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        self.get_interval_timer(id)
+    }
+
+    fn all(&self) -> Result<Vec<IntervalTimer>, Error> {
+        self.get_all_interval_timers()
+    }
+
+    fn remove(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        self.delete_interval_timer(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Put a store through inserting, replacing, listing and removing two timers
+    fn exercise(store: &dyn TimerStore) {
+        let mut a = IntervalTimer::new(Some("a".to_owned()), None, Default::default());
+        let b = IntervalTimer::new(Some("b".to_owned()), None, Default::default());
+        assert!(store.insert(&a).unwrap().is_none());
+        assert!(store.insert(&b).unwrap().is_none());
+        a.name = Some("a2".to_owned());
+        let replaced = store.insert(&a).unwrap().unwrap();
+        assert_eq!(replaced.name.as_deref(), Some("a"));
+        assert_eq!(
+            store.get(a.get_id()).unwrap().unwrap().name.as_deref(),
+            Some("a2")
+        );
+
+        let mut names: Vec<_> = store
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|timer| timer.name.unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a2", "b"]);
+
+        let removed = store.remove(b.get_id()).unwrap().unwrap();
+        assert_eq!(removed.get_id(), b.get_id());
+        assert!(store.remove(b.get_id()).unwrap().is_none());
+        assert!(store.get(b.get_id()).unwrap().is_none());
+        assert_eq!(store.all().unwrap().len(), 1);
+    }
+
+    fn db() -> Arc<sled::Db> {
+        Arc::new(sled::Config::new().temporary(true).open().unwrap())
+    }
+
+    #[test]
+    fn every_store_behaves_alike() {
+        exercise(&MemoryStore::new());
+        exercise(&SledStore::from_db(&db()));
+        exercise(&AppState::new(db(), tokio::sync::mpsc::channel(1).0));
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The Julian date of 2000-01-01 12:00 UTC
+const J2000: f64 = 2451545.0;
+/// The Julian date of the Unix epoch
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+/// Compute the times of sunrise and sunset on the given date at the given latitude and
+/// longitude (in degrees, north and east positive), using the sunrise equation. Returns `None`
+/// if the sun doesn't rise or set that day, i.e. during polar day or night.
+pub fn sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let days = (date - epoch).num_days() as f64;
+    // Mean solar noon
+    let mean_noon = days - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.0200 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let rise = julian_to_utc(transit - hour_angle / 360.0)?;
+    let set = julian_to_utc(transit + hour_angle / 360.0)?;
+    Some((rise, set))
+}
+
+fn julian_to_utc(julian: f64) -> Option<DateTime<Utc>> {
+    let millis = ((julian - UNIX_EPOCH_JD) * 86_400_000.0).round() as i64;
+    Utc.timestamp_millis_opt(millis).single()
+}
+
+/// The time of the given event on the given local date, if it happens that day
+pub fn event_on(
+    date: NaiveDate,
+    event: SunEvent,
+    latitude: f64,
+    longitude: f64,
+) -> Option<DateTime<Local>> {
+    let (rise, set) = sun_times(date, latitude, longitude)?;
+    let time = match event {
+        SunEvent::Sunrise => rise,
+        SunEvent::Sunset => set,
+    };
+    Some(time.with_timezone(&Local))
+}
+
+/// The next occurrence of the given event, shifted by `offset`, strictly after `now`. Looks up
+/// to a year ahead to skip over polar day or night.
+pub fn next_event(
+    now: DateTime<Local>,
+    event: SunEvent,
+    latitude: f64,
+    longitude: f64,
+    offset: chrono::Duration,
+) -> Option<DateTime<Local>> {
+    let today = now.date_naive();
+    // Start from yesterday in case a negative offset or timezone shift moves an event into today
+    (0..=367)
+        .filter_map(|i| {
+            today
+                .checked_sub_days(Days::new(1))?
+                .checked_add_days(Days::new(i))
+        })
+        .filter_map(|date| event_on(date, event, latitude, longitude))
+        .map(|time| time + offset)
+        .find(|time| *time > now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: (f64, f64) = (51.5, -0.13);
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    /// Whether two times are within a few minutes, which is as close as the equation gets
+    fn near(a: DateTime<Utc>, b: DateTime<Utc>) -> bool {
+        (a - b).num_minutes().abs() <= 3
+    }
+
+    #[test]
+    fn sun_times_match_the_almanac() {
+        let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (rise, set) = sun_times(midsummer, LONDON.0, LONDON.1).unwrap();
+        assert!(near(rise, utc(2024, 6, 21, 3, 43)), "sunrise at {}", rise);
+        assert!(near(set, utc(2024, 6, 21, 20, 21)), "sunset at {}", set);
+    }
+
+    #[test]
+    fn no_sun_times_during_polar_night() {
+        let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        assert_eq!(sun_times(midwinter, 69.65, 18.96), None);
+    }
+
+    #[test]
+    fn next_event_is_strictly_later_and_shifted_by_the_offset() {
+        let noon = utc(2024, 6, 21, 12, 0).with_timezone(&Local);
+        let early = chrono::Duration::minutes(-30);
+        let (lat, long) = LONDON;
+        let sunset = next_event(noon, SunEvent::Sunset, lat, long, early).unwrap();
+        assert!(near(sunset.with_timezone(&Utc), utc(2024, 6, 21, 19, 51)));
+        // The morning's sunrise has passed, so the next one is tomorrow's
+        let none = chrono::Duration::zero();
+        let sunrise = next_event(noon, SunEvent::Sunrise, lat, long, none).unwrap();
+        assert!(near(sunrise.with_timezone(&Utc), utc(2024, 6, 22, 3, 43)));
+    }
+}
@@ -1,28 +1,133 @@
-use crate::IntervalTimer;
+use crate::{
+    events::{timer_label, SchedulerEventKind, SchedulerEvents, SCHEDULER_EVENTS_CAPACITY},
+    handlers::{DurationUnit, NewDaily, OutputRef, TimerFilter},
+    history::FireOutcome,
+    metrics::Metrics,
+    pins::{GpioBackend, InputPin, MockBackend, OutputPin, SysFsBackend},
+    sun::{self, SunEvent},
+    webhook::{SkipQuery, Webhook},
+    Edge, IntervalTimer, Schedule,
+};
 use axum::{
+    extract::rejection::{JsonRejection, PathRejection},
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
-use chrono::{DateTime, Duration, Local, NaiveTime};
-use gpio::{
-    sysfs::{SysFsGpioInput, SysFsGpioOutput},
-    GpioOut,
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+    Utc, Weekday,
 };
+use chrono_tz::Tz;
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     pin::Pin,
-    sync::{Arc, Mutex},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
     task::{Context, Poll, Waker},
 };
-use tokio::{sync::mpsc, task::JoinHandle, time::sleep};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, OwnedSemaphorePermit, Semaphore},
+    task::{AbortHandle, JoinHandle},
+    time::sleep,
+};
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Called by a timer's task each time it turns its output on, with how long it's on for
+pub type OnFire = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// Asked by a timer's task before each run, which is skipped if it resolves to `true`
+pub type SkipCheck = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// What a timer's task checks and calls around each of its runs, shared by every kind of timer
+#[derive(Clone)]
+pub struct RunHooks {
+    /// Checked before each run, so that the timer can be disabled without cancelling it
+    pub enabled: Arc<AtomicBool>,
+    /// Called each time the timer turns its output on
+    pub on_fire: Option<OnFire>,
+    /// Asked before each run whether to skip it
+    pub skip_check: Option<SkipCheck>,
+}
+
+impl Default for RunHooks {
+    fn default() -> RunHooks {
+        RunHooks {
+            enabled: Arc::new(AtomicBool::new(true)),
+            on_fire: None,
+            skip_check: None,
+        }
+    }
+}
+
+impl RunHooks {
+    /// Share the flag checked before each run, so that the timer can be disabled from elsewhere
+    pub fn with_enabled(mut self, enabled: Arc<AtomicBool>) -> RunHooks {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Call `on_fire` each time the timer turns its output on, e.g. to count its runs
+    pub fn with_on_fire(mut self, on_fire: OnFire) -> RunHooks {
+        self.on_fire = Some(on_fire);
+        self
+    }
+
+    /// Ask `skip_check`, if any, before each run whether to skip it, e.g. because it has rained
+    pub fn with_skip_check(mut self, skip_check: Option<SkipCheck>) -> RunHooks {
+        self.skip_check = skip_check;
+        self
+    }
+
+    /// Whether the timer is currently enabled
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Whether the skip check, if any, asks to skip this run
+    async fn skips(&self) -> bool {
+        match &self.skip_check {
+            Some(skip_check) if skip_check().await => {
+                info!("Skip check asked to skip this run, skipping.");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Let the `on_fire` hook, if any, know that the output was turned on for `duration`
+    fn fired(&self, duration: Duration) {
+        if let Some(on_fire) = &self.on_fire {
+            on_fire(duration);
+        }
+    }
+}
 
 pub struct DailyTimer {
     pub time: NaiveTime,
     pub msg: GpioOutMessage,
     pub duration: Duration,
     pub tx: mpsc::Sender<GpioMessage>,
+    /// Days of the week on which the timer fires, or every day if `None`
+    pub days: Option<Vec<Weekday>>,
+    /// Timezone of the start time, or the system's local timezone if `None`
+    pub tz: Option<Tz>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
+    /// Whether to keep firing every day, or stop after the first run
+    pub repeat: bool,
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
 }
 
 impl DailyTimer {
@@ -37,268 +142,3080 @@ impl DailyTimer {
             msg,
             duration,
             tx,
+            days: None,
+            tz: None,
+            hooks: RunHooks::default(),
+            repeat: true,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// A timer which fires once at the next occurrence of `time` and then stops, e.g. for a
+    /// one-off deep soak
+    pub fn once_at(
+        time: NaiveTime,
+        msg: GpioOutMessage,
+        duration: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> DailyTimer {
+        DailyTimer {
+            repeat: false,
+            ..DailyTimer::new(time, msg, duration, tx)
+        }
+    }
+
+    /// A timer which only fires on the given days of the week
+    pub fn weekly(
+        time: NaiveTime,
+        days: Vec<Weekday>,
+        msg: GpioOutMessage,
+        duration: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> DailyTimer {
+        DailyTimer {
+            days: Some(days),
+            ..DailyTimer::new(time, msg, duration, tx)
         }
     }
 
+    /// Interpret the start time in the given timezone rather than the system's
+    pub fn with_timezone(mut self, tz: Option<Tz>) -> DailyTimer {
+        self.tz = tz;
+        self
+    }
+
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> DailyTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> DailyTimer {
+        self.clock = clock;
+        self
+    }
+
     pub fn run(&self) -> JoinHandle<()> {
         let msg = self.msg;
         let off_msg = GpioOutMessage {
-            output: self.msg.output,
             value: !self.msg.value,
+            ..self.msg
         };
         let start_time = self.time;
-        let stop_time = self.time + self.duration;
+        let duration = self.duration;
         let tx = self.tx.clone();
-        let f = tokio::spawn(async move {
+        let days = self.days.clone();
+        let tz = self.tz;
+        let hooks = self.hooks.clone();
+        let repeat = self.repeat;
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
             info!("Spawned task to run new daily timer.");
             loop {
                 info!("Waiting until {:?}", &start_time);
-                TimeFuture::new(start_time).await;
-                let _ = tx.send(msg.into()).await.map_err(|e| error!("{}", e));
-                info!("Waiting until {:?}", &stop_time);
-                TimeFuture::new(stop_time).await;
-                let _ = tx.send(off_msg.into()).await.map_err(|e| error!("{}", e));
+                TimeFuture::new_with_clock(start_time, tz, &*clock).await;
+                // The run ends `duration` after it was due to start rather than at the next
+                // time the clock reads the stop time, which is a day late if waiting for the
+                // skip check or another run took us past it, e.g. for a window spanning midnight
+                let stop = clock.now() + duration;
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, skipping.");
+                    continue;
+                }
+                if let Some(days) = &days {
+                    let today = local_datetime_with(tz, &*clock).weekday();
+                    if !days.contains(&today) {
+                        info!("Not scheduled to run on {}, skipping.", today);
+                        continue;
+                    }
+                }
+                if hooks.skips().await {
+                    continue;
+                }
+                let slot = OutputSlot::acquire(&tx, msg).await;
+                let on = GpioMessage::OnFor {
+                    msg,
+                    duration,
+                    scheduled: true,
+                };
+                let _ = tx.send(on).await.map_err(|e| error!("{}", e));
+                hooks.fired(duration);
+                info!("Waiting until {:?}", &stop);
+                TimeFuture::at_with_clock(stop, &*clock).await;
+                sleep(slot.waited).await;
+                let _ = tx
+                    .send(GpioMessage::Rest(off_msg))
+                    .await
+                    .map_err(|e| error!("{}", e));
+                drop(slot);
+                if !repeat {
+                    info!("One-shot timer finished.");
+                    break;
+                }
             }
-        });
-        f
+        })
     }
 }
 
-pub fn naive_now() -> NaiveTime {
-    let dt = Local::now();
-    dt.time()
+/// A daily window of time during which an output should be on
+#[derive(Debug, Copy, Clone)]
+pub struct Daily {
+    time: NaiveTime,
+    duration: Duration,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("Duration cannot be zero")]
-    InvalidDuration,
-    #[error("JSON serialization/deserialization error: {0}")]
-    Json(#[from] serde_json::Error),
-    #[error("Database error: {0}")]
-    Db(#[from] sled::Error),
-    #[error("Failed to parse time from hh:mm format: {0}")]
-    TimeParsing(#[from] chrono::ParseError),
-    #[error("Other error: {0}")]
-    Anyhow(#[from] anyhow::Error),
-    #[error("Not found: {0}")]
-    NotFound(String),
-    #[error("Unknown error")]
-    Unknown,
-}
+impl Daily {
+    pub fn new(time: NaiveTime, duration: Duration) -> Daily {
+        Daily { time, duration }
+    }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        match self {
-            Error::NotFound(s) => (StatusCode::NOT_FOUND, s).into_response(),
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response(),
-        }
+    /// Build a [DailyTimer] which sends `msg` at the start of the window every day and its
+    /// opposite at the end
+    pub fn timer(self, msg: GpioOutMessage, tx: mpsc::Sender<GpioMessage>) -> DailyTimer {
+        DailyTimer::new(self.time, msg, self.duration, tx)
     }
-}
-#[derive(Debug, Copy, Clone)]
-pub struct GpioOutMessage {
-    pub output: u16,
-    pub value: bool,
-}
 
-#[derive(Debug, Clone)]
-pub enum GpioMessage {
-    In(u16),
-    Out(GpioOutMessage),
+    /// Spawn a task turning `output` on for the window every day
+    pub fn schedule(self, output: GpioOutMessage, tx: mpsc::Sender<GpioMessage>) -> JoinHandle<()> {
+        self.timer(output, tx).run()
+    }
 }
 
-pub async fn run_timer(
-    tx: mpsc::Sender<GpioMessage>,
-    output: u16,
-    value: bool,
-    time: NaiveTime,
-    duration: Duration,
-) -> Result<(), Error> {
-    let mut outmsg = GpioOutMessage { output, value };
-    let _ = TimeFuture::new(time).await;
-    tx.send(outmsg.clone().into())
-        .await
-        .map_err(|e| Error::Anyhow(e.into()))?;
-    info!(
-        "Sent message to set output {} to value {} for duration {}.",
-        output, value, &duration
-    );
-    tokio::time::sleep(duration.to_std().map_err(|e| Error::Anyhow(e.into()))?).await;
-    outmsg.value = !value;
-    tx.send(outmsg.into())
-        .await
-        .map_err(|e| Error::Anyhow(e.into()))?;
-    info!(
-        "Sent message to set output {} back to value {}.",
-        &output, !value
-    );
-    Ok(())
+/// A timer which turns an output on for several windows throughout each day. The windows
+/// must not overlap, which is checked by [crate::IntervalSettings::multi_window].
+pub struct MultiWindowTimer {
+    pub windows: Vec<(NaiveTime, Duration)>,
+    pub msg: GpioOutMessage,
+    pub tx: mpsc::Sender<GpioMessage>,
+    /// Timezone of the window start times, or the system's local timezone if `None`
+    pub tz: Option<Tz>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
 }
 
-impl From<GpioOutMessage> for GpioMessage {
-    fn from(other: GpioOutMessage) -> GpioMessage {
-        GpioMessage::Out(other)
+impl MultiWindowTimer {
+    pub fn new(
+        mut windows: Vec<(NaiveTime, Duration)>,
+        msg: GpioOutMessage,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> MultiWindowTimer {
+        windows.sort_by_key(|w| w.0);
+        MultiWindowTimer {
+            windows,
+            msg,
+            tx,
+            tz: None,
+            hooks: RunHooks::default(),
+            clock: Arc::new(SystemClock),
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct GpioManager {
-    inputs: HashMap<u16, SysFsGpioInput>,
-    outputs: HashMap<u16, SysFsGpioOutput>,
-    rx: mpsc::Receiver<GpioMessage>,
-}
-impl GpioManager {
-    pub fn new() -> Result<(GpioManager, mpsc::Sender<GpioMessage>), Error> {
-        let (tx, rx) = mpsc::channel(32);
-        let (inputs, outputs) = (HashMap::new(), HashMap::new());
-        let man = GpioManager {
-            inputs,
-            outputs,
-            rx,
-        };
-        Ok((man, tx))
+    /// Interpret the window start times in the given timezone rather than the system's
+    pub fn with_timezone(mut self, tz: Option<Tz>) -> MultiWindowTimer {
+        self.tz = tz;
+        self
+    }
+
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> MultiWindowTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> MultiWindowTimer {
+        self.clock = clock;
+        self
     }
-    pub fn run(self) -> Result<(), Error> {
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let msg = self.msg;
+        let off_msg = GpioOutMessage {
+            value: !self.msg.value,
+            ..self.msg
+        };
+        let windows = self.windows.clone();
+        let tx = self.tx.clone();
+        let tz = self.tz;
+        let hooks = self.hooks.clone();
+        let clock = self.clock.clone();
         tokio::spawn(async move {
-            let mut rx = self.rx;
-            debug!("Spawned GPIO manager thread");
-            while let Some(message) = rx.recv().await {
-                info!("Received GPIO message: {:?}", &message);
-                match message {
-                    GpioMessage::In(num) => {
-                        let pin = SysFsGpioInput::open(num).map_err(|e| {
-                            error!("{}", e);
-                        });
-                        info!("Opened GPIO port {} for reading", &num);
-                        warn!("GPIO in not yet implemented");
-                    }
-                    GpioMessage::Out(outmsg) => {
-                        if let Ok(mut pin) = SysFsGpioOutput::open(outmsg.output) {
-                            info!("Opened GPIO output {} for writing", &outmsg.output);
-                            if let Ok(_) = pin.set_value(outmsg.value).map_err(|e| error!("{}", e))
-                            {
-                                info!("Write to pin {} successful.", &outmsg.output);
-                            }
-                        }
+            info!("Spawned task to run new multi-window timer.");
+            if windows.is_empty() {
+                warn!("Multi-window timer has no windows, stopping.");
+                return;
+            }
+            let n = windows.len();
+            // Start with whichever window comes up next
+            let mut i = (0..n)
+                .min_by_key(|&i| time_until_with(windows[i].0, tz, &*clock))
+                .unwrap_or_default();
+            loop {
+                let (start_time, duration) = windows[i];
+                info!("Waiting until {:?}", &start_time);
+                TimeFuture::new_with_clock(start_time, tz, &*clock).await;
+                // As for daily timers, the run ends a fixed time after it was due to start
+                let started = clock.now();
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, skipping.");
+                    i = (i + 1) % n;
+                    continue;
+                }
+                if hooks.skips().await {
+                    i = (i + 1) % n;
+                    continue;
+                }
+                let slot = OutputSlot::acquire(&tx, msg).await;
+                // Windows which begin right as the previous one ends keep the output on
+                // rather than toggling it off and back on
+                let mut stop_time = start_time + duration;
+                for _ in 1..n {
+                    let (next_start, next_duration) = windows[(i + 1) % n];
+                    if next_start != stop_time {
+                        break;
                     }
+                    i = (i + 1) % n;
+                    stop_time = next_start + next_duration;
                 }
+                let duration = wrapping_time_until(start_time, stop_time);
+                let on = GpioMessage::OnFor {
+                    msg,
+                    duration,
+                    scheduled: true,
+                };
+                let _ = tx.send(on).await.map_err(|e| error!("{}", e));
+                hooks.fired(duration);
+                info!("Waiting until {:?}", &stop_time);
+                TimeFuture::at_with_clock(started + duration, &*clock).await;
+                sleep(slot.waited).await;
+                let _ = tx
+                    .send(GpioMessage::Rest(off_msg))
+                    .await
+                    .map_err(|e| error!("{}", e));
+                drop(slot);
+                i = (i + 1) % n;
             }
-        });
-        Ok(())
+        })
     }
 }
 
-pub fn local_time() -> NaiveTime {
-    let dt: DateTime<Local> = Local::now();
-    dt.time()
+/// A timer which turns an output on for a fixed duration at each time matching a cron
+/// expression, e.g. `0 6 * * 1-5` for 06:00 on weekdays
+pub struct CronTimer {
+    pub cron: Cron,
+    pub msg: GpioOutMessage,
+    pub duration: Duration,
+    pub tx: mpsc::Sender<GpioMessage>,
+    /// Timezone the expression is evaluated in, or the system's local timezone if `None`
+    pub tz: Option<Tz>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
 }
-pub fn time_until(target: NaiveTime) -> Duration {
-    let now = local_time();
-    let diff = target - now;
-    if diff < Duration::zero() {
-        // Target time is later in the day than now, add (negative) difference to 24h to get
-        // positive time until target
-        Duration::new(86400, 0).unwrap() + diff
-    } else {
-        diff
+
+impl CronTimer {
+    pub fn new(
+        cron: Cron,
+        msg: GpioOutMessage,
+        duration: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> CronTimer {
+        CronTimer {
+            cron,
+            msg,
+            duration,
+            tx,
+            tz: None,
+            hooks: RunHooks::default(),
+            clock: Arc::new(SystemClock),
+        }
     }
-}
 
-pub struct TimeSharedState {
-    completed: bool,
-    waker: Option<Waker>,
-}
+    /// Evaluate the expression in the given timezone rather than the system's
+    pub fn with_timezone(mut self, tz: Option<Tz>) -> CronTimer {
+        self.tz = tz;
+        self
+    }
 
-/// A future that resolves at a given time
-pub struct TimeFuture {
-    shared_state: Arc<Mutex<TimeSharedState>>,
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> CronTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> CronTimer {
+        self.clock = clock;
+        self
+    }
+
+    /// The next time matching the expression strictly after the time `clock` reads
+    fn next_occurrence(
+        cron: &Cron,
+        tz: Option<Tz>,
+        clock: &dyn Clock,
+    ) -> Result<DateTime<Utc>, Error> {
+        let now = clock.now();
+        let next = match tz {
+            Some(tz) => cron
+                .find_next_occurrence(&now.with_timezone(&tz), false)?
+                .with_timezone(&Utc),
+            None => cron
+                .find_next_occurrence(&now.with_timezone(&Local), false)?
+                .with_timezone(&Utc),
+        };
+        Ok(next)
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let msg = self.msg;
+        let off_msg = GpioOutMessage {
+            value: !self.msg.value,
+            ..self.msg
+        };
+        let cron = self.cron.clone();
+        let duration = self.duration;
+        let tx = self.tx.clone();
+        let tz = self.tz;
+        let hooks = self.hooks.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            info!("Spawned task to run new cron timer {}.", cron.pattern);
+            loop {
+                let start = match CronTimer::next_occurrence(&cron, tz, &*clock) {
+                    Ok(start) => start,
+                    Err(e) => {
+                        error!("No next occurrence of {}: {}", cron.pattern, e);
+                        return;
+                    }
+                };
+                info!("Waiting until {}", &start);
+                TimeFuture::at_with_clock(start, &*clock).await;
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, skipping.");
+                    continue;
+                }
+                if hooks.skips().await {
+                    continue;
+                }
+                let slot = OutputSlot::acquire(&tx, msg).await;
+                let on = GpioMessage::OnFor {
+                    msg,
+                    duration,
+                    scheduled: true,
+                };
+                let _ = tx.send(on).await.map_err(|e| error!("{}", e));
+                hooks.fired(duration);
+                let stop = start + duration;
+                info!("Waiting until {}", &stop);
+                TimeFuture::at_with_clock(stop, &*clock).await;
+                sleep(slot.waited).await;
+                let _ = tx
+                    .send(GpioMessage::Rest(off_msg))
+                    .await
+                    .map_err(|e| error!("{}", e));
+                drop(slot);
+            }
+        })
+    }
 }
-pub struct Daily {
-    time: NaiveTime,
-    duration: Duration,
+
+/// A timer which turns an output on for `on`, then off for `off`, over and over, independent
+/// of the wall clock
+pub struct CyclingTimer {
+    pub msg: GpioOutMessage,
+    pub on: Duration,
+    pub off: Duration,
+    pub tx: mpsc::Sender<GpioMessage>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
 }
-impl Future for TimeFuture {
-    type Output = ();
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut shared_state = self.shared_state.lock().unwrap();
-        if shared_state.completed {
-            Poll::Ready(())
-        } else {
-            shared_state.waker = Some(cx.waker().clone());
-            Poll::Pending
+
+impl CyclingTimer {
+    pub fn new(
+        msg: GpioOutMessage,
+        on: Duration,
+        off: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> CyclingTimer {
+        CyclingTimer {
+            msg,
+            on,
+            off,
+            tx,
+            hooks: RunHooks::default(),
         }
     }
-}
-impl TimeFuture {
-    /// Returns a future which will resolve at the next occurrence of `time` in the local timezone
-    pub fn new(time: NaiveTime) -> Self {
-        let shared_state = Arc::new(Mutex::new(TimeSharedState {
-            completed: false,
-            waker: None,
-        }));
-        let thread_shared_state = shared_state.clone();
+
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> CyclingTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let msg = self.msg;
+        let off_msg = GpioOutMessage {
+            value: !self.msg.value,
+            ..self.msg
+        };
+        let on = self.on.to_std().unwrap_or_default();
+        let off = self.off.to_std().unwrap_or_default();
+        let tx = self.tx.clone();
+        let hooks = self.hooks.clone();
         tokio::spawn(async move {
-            let sleep_time = time_until(time);
-            sleep(sleep_time.to_std().unwrap()).await;
-            let mut shared_state = thread_shared_state.lock().unwrap();
-            shared_state.completed = true;
-            if let Some(waker) = shared_state.waker.take() {
-                waker.wake()
+            info!("Spawned task to run new cycling timer.");
+            loop {
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, skipping.");
+                    sleep(on).await;
+                } else if hooks.skips().await {
+                    sleep(on).await;
+                } else {
+                    let slot = OutputSlot::acquire(&tx, msg).await;
+                    let on_for = Duration::from_std(on).unwrap_or_default();
+                    let on_msg = GpioMessage::OnFor {
+                        msg,
+                        duration: on_for,
+                        scheduled: true,
+                    };
+                    let _ = tx.send(on_msg).await.map_err(|e| error!("{}", e));
+                    hooks.fired(on_for);
+                    sleep(on).await;
+                    let _ = tx
+                        .send(GpioMessage::Rest(off_msg))
+                        .await
+                        .map_err(|e| error!("{}", e));
+                    drop(slot);
+                }
+                sleep(off).await;
             }
-        });
-        TimeFuture { shared_state }
+        })
     }
 }
 
-pub struct Periodic {
-    pulse_width: Duration,
-    duty: f32,
-    period: Duration,
-}
+/// How often timers triggered by an input read it
+const INPUT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 
-#[derive(Clone)]
+/// A timer which turns an output on for `duration` whenever an input changes in the direction
+/// of `edge`, once the new value has held for `debounce` so that a bouncing contact or noisy
+/// sensor triggers it only once. The input is polled through the GPIO manager.
+pub struct InputTimer {
+    pub input: u16,
+    pub edge: Edge,
+    pub debounce: std::time::Duration,
+    pub msg: GpioOutMessage,
+    pub duration: Duration,
+    pub tx: mpsc::Sender<GpioMessage>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
+}
+
+impl InputTimer {
+    pub fn new(
+        input: u16,
+        edge: Edge,
+        debounce: std::time::Duration,
+        msg: GpioOutMessage,
+        duration: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> InputTimer {
+        InputTimer {
+            input,
+            edge,
+            debounce,
+            msg,
+            duration,
+            tx,
+            hooks: RunHooks::default(),
+        }
+    }
+
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> InputTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let (input, edge, debounce) = (self.input, self.edge, self.debounce);
+        let msg = self.msg;
+        let off_msg = GpioOutMessage {
+            value: !self.msg.value,
+            ..self.msg
+        };
+        let duration = self.duration.to_std().unwrap_or_default();
+        let tx = self.tx.clone();
+        let hooks = self.hooks.clone();
+        tokio::spawn(async move {
+            info!(
+                "Spawned task to run new timer triggered by input {}.",
+                input
+            );
+            // The last value which held for the debounce window. The first reading only sets
+            // it, so that starting up doesn't count as an edge.
+            let mut settled: Option<bool> = None;
+            loop {
+                sleep(INPUT_POLL_INTERVAL).await;
+                let value = match read_input(&tx, input).await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        warn!("Failed to read input {}: {}", input, e);
+                        continue;
+                    }
+                };
+                if settled == Some(value) {
+                    continue;
+                }
+                if !debounce.is_zero() {
+                    sleep(debounce).await;
+                    if !matches!(read_input(&tx, input).await, Ok(v) if v == value) {
+                        continue;
+                    }
+                }
+                let previous = settled.replace(value);
+                if previous.is_none() || value != (edge == Edge::Rising) {
+                    continue;
+                }
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, ignoring input {}.", input);
+                    continue;
+                }
+                if hooks.skips().await {
+                    continue;
+                }
+                info!("Input {} triggered, turning on for {:?}.", input, duration);
+                let slot = OutputSlot::acquire(&tx, msg).await;
+                let on_for = Duration::from_std(duration).unwrap_or_default();
+                let on = GpioMessage::OnFor {
+                    msg,
+                    duration: on_for,
+                    scheduled: true,
+                };
+                let _ = tx.send(on).await.map_err(|e| error!("{}", e));
+                hooks.fired(on_for);
+                sleep(duration).await;
+                let _ = tx
+                    .send(GpioMessage::Rest(off_msg))
+                    .await
+                    .map_err(|e| error!("{}", e));
+                drop(slot);
+            }
+        })
+    }
+}
+
+/// A timer which turns an output on for a fixed duration at sunrise or sunset, offset by a
+/// fixed amount. The time of the event is recomputed every day.
+pub struct SunTimer {
+    pub event: SunEvent,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub offset: Duration,
+    pub msg: GpioOutMessage,
+    pub duration: Duration,
+    pub tx: mpsc::Sender<GpioMessage>,
+    /// Checked and called around each run
+    pub hooks: RunHooks,
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
+}
+
+impl SunTimer {
+    pub fn new(
+        event: SunEvent,
+        latitude: f64,
+        longitude: f64,
+        offset: Duration,
+        msg: GpioOutMessage,
+        duration: Duration,
+        tx: mpsc::Sender<GpioMessage>,
+    ) -> SunTimer {
+        SunTimer {
+            event,
+            latitude,
+            longitude,
+            offset,
+            msg,
+            duration,
+            tx,
+            hooks: RunHooks::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Check and call `hooks` around each run
+    pub fn with_hooks(mut self, hooks: RunHooks) -> SunTimer {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> SunTimer {
+        self.clock = clock;
+        self
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let msg = self.msg;
+        let off_msg = GpioOutMessage {
+            value: !self.msg.value,
+            ..self.msg
+        };
+        let (event, latitude, longitude, offset) =
+            (self.event, self.latitude, self.longitude, self.offset);
+        let duration = self.duration;
+        let tx = self.tx.clone();
+        let hooks = self.hooks.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            info!("Spawned task to run new {:?} timer.", event);
+            loop {
+                let now = clock.now().with_timezone(&Local);
+                let Some(start) = sun::next_event(now, event, latitude, longitude, offset) else {
+                    error!(
+                        "No {:?} within the next year at {}, {}, stopping.",
+                        event, latitude, longitude
+                    );
+                    return;
+                };
+                info!("Waiting until {}", &start);
+                TimeFuture::at_with_clock(start, &*clock).await;
+                if !hooks.is_enabled() {
+                    info!("Timer is disabled, skipping.");
+                    continue;
+                }
+                if hooks.skips().await {
+                    continue;
+                }
+                let slot = OutputSlot::acquire(&tx, msg).await;
+                let on = GpioMessage::OnFor {
+                    msg,
+                    duration,
+                    scheduled: true,
+                };
+                let _ = tx.send(on).await.map_err(|e| error!("{}", e));
+                hooks.fired(duration);
+                let stop = start + duration;
+                info!("Waiting until {}", &stop);
+                TimeFuture::at_with_clock(stop, &*clock).await;
+                sleep(slot.waited).await;
+                let _ = tx
+                    .send(GpioMessage::Rest(off_msg))
+                    .await
+                    .map_err(|e| error!("{}", e));
+                drop(slot);
+            }
+        })
+    }
+}
+
+pub fn naive_now(tz: Option<Tz>) -> NaiveTime {
+    local_time(tz)
+}
+
+/// Parse a time of day in `%H:%M:%S` or `%H:%M` format
+pub fn parse_time_of_day(time: &str) -> Result<NaiveTime, chrono::ParseError> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+}
+
+/// Format a duration for people to read, e.g. "1 h 30 min" or "45 s", leaving out parts
+/// which are zero and any fraction of a second
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    let mut parts = Vec::new();
+    if hours > 0 {
+        parts.push(format!("{} h", hours));
+    }
+    if mins > 0 {
+        parts.push(format!("{} min", mins));
+    }
+    if secs > 0 || parts.is_empty() {
+        parts.push(format!("{} s", secs));
+    }
+    parts.join(" ")
+}
+
+/// Format a time of day as `%H:%M`, or `%H:%M:%S` if it doesn't fall on a whole minute
+pub fn format_time_of_day(time: NaiveTime) -> String {
+    if time.second() == 0 {
+        time.format("%H:%M").to_string()
+    } else {
+        time.format("%H:%M:%S").to_string()
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Duration cannot be zero")]
+    InvalidDuration,
+    #[error("At least one day of the week must be selected")]
+    NoDays,
+    #[error("Timer windows must not overlap")]
+    OverlappingWindows,
+    #[error("Latitude must be within ±90° and longitude within ±180°")]
+    InvalidCoordinates,
+    #[error("Failed to parse cron expression: {0}")]
+    CronParse(#[from] croner::errors::CronError),
+    #[error("JSON serialization/deserialization error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Database error: {0}")]
+    Db(#[from] sled::Error),
+    /// The database path names something other than a directory, e.g. a file passed by mistake
+    #[error(
+        "Database path {0:?} is not a directory. The database is stored as a directory of files, \
+         so pass a path to one which exists or can be created, e.g. ./sploosh.db"
+    )]
+    DbNotDirectory(PathBuf),
+    #[error("Duty cycle must be between 0 and 1, got {0}")]
+    InvalidDuty(f32),
+    #[error("Output pin {0} is not allowed")]
+    InvalidPin(u16),
+    #[error("Unknown timezone: {0}")]
+    InvalidTimezone(String),
+    #[error("A timer named {0:?} already exists")]
+    DuplicateName(String),
+    #[error("Unsupported backup version {0}")]
+    UnsupportedVersion(u32),
+    /// One message per invalid field of a submitted timer
+    #[error("Invalid timer: {}", .0.join("; "))]
+    Validation(Vec<String>),
+    #[error("Failed to parse time from hh:mm format: {0}")]
+    TimeParsing(#[from] chrono::ParseError),
+    #[error("Other error: {0}")]
+    Anyhow(#[from] anyhow::Error),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// The request couldn't be parsed, e.g. a malformed JSON body or id
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Too many requests, try again later")]
+    RateLimited,
+    #[error("Missing or wrong credentials")]
+    Unauthorized,
+    /// The GPIO manager's queue is full, so a manual run would have to wait for room
+    #[error("The GPIO manager is busy, try again later")]
+    Busy,
+    #[error("Unknown error")]
+    Unknown,
+}
+
+impl Error {
+    /// A stable, machine-readable name for the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidDuration => "InvalidDuration",
+            Error::NoDays => "NoDays",
+            Error::OverlappingWindows => "OverlappingWindows",
+            Error::InvalidCoordinates => "InvalidCoordinates",
+            Error::CronParse(_) => "CronParse",
+            Error::Json(_) => "Json",
+            Error::Db(_) => "Db",
+            Error::DbNotDirectory(_) => "DbNotDirectory",
+            Error::InvalidDuty(_) => "InvalidDuty",
+            Error::InvalidPin(_) => "InvalidPin",
+            Error::InvalidTimezone(_) => "InvalidTimezone",
+            Error::DuplicateName(_) => "DuplicateName",
+            Error::UnsupportedVersion(_) => "UnsupportedVersion",
+            Error::Validation(_) => "Validation",
+            Error::TimeParsing(_) => "TimeParsing",
+            Error::Anyhow(_) => "Anyhow",
+            Error::NotFound(_) => "NotFound",
+            Error::BadRequest(_) => "BadRequest",
+            Error::RateLimited => "RateLimited",
+            Error::Unauthorized => "Unauthorized",
+            Error::Busy => "Busy",
+            Error::Unknown => "Unknown",
+        }
+    }
+
+    /// The HTTP status to respond with: 400 for invalid input, 401 for missing credentials, 404
+    /// for missing timers, 409 for duplicate names, 429 for rate limited clients, 503 when the
+    /// GPIO manager is busy and 500 for everything else
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::InvalidDuration
+            | Error::NoDays
+            | Error::OverlappingWindows
+            | Error::InvalidCoordinates
+            | Error::CronParse(_)
+            | Error::InvalidDuty(_)
+            | Error::InvalidPin(_)
+            | Error::InvalidTimezone(_)
+            | Error::UnsupportedVersion(_)
+            | Error::Validation(_)
+            | Error::TimeParsing(_)
+            | Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::DuplicateName(_) => StatusCode::CONFLICT,
+            Error::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Json(_)
+            | Error::Db(_)
+            | Error::DbNotDirectory(_)
+            | Error::Anyhow(_)
+            | Error::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(e: JsonRejection) -> Self {
+        Error::BadRequest(e.body_text())
+    }
+}
+
+impl From<PathRejection> for Error {
+    fn from(e: PathRejection) -> Self {
+        Error::BadRequest(e.body_text())
+    }
+}
+
+/// The JSON body of an error response
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+    /// The problem with each invalid field, for validation errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let fields = match &self {
+            Error::Validation(fields) => fields.clone(),
+            _ => Vec::new(),
+        };
+        let body = ErrorBody {
+            error: self.code().to_owned(),
+            message: self.to_string(),
+            fields,
+        };
+        (self.status(), Json(body)).into_response()
+    }
+}
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct GpioOutMessage {
+    pub output: u16,
+    /// The logical value of the output, i.e. `true` turns the load on
+    pub value: bool,
+    /// Whether the load is turned on by driving the pin low, as on many relay boards
+    pub active_low: bool,
+}
+
+impl GpioOutMessage {
+    /// The value to physically write to the pin, accounting for its polarity
+    pub fn pin_value(&self) -> bool {
+        self.value != self.active_low
+    }
+}
+
+#[derive(Debug)]
+pub enum GpioMessage {
+    /// Read an input and log its value
+    In(u16),
+    /// Read an input and send its value back to the caller
+    ReadIn {
+        pin: u16,
+        resp: oneshot::Sender<bool>,
+    },
+    Out(GpioOutMessage),
+    /// Like `Out`, for returning an output to the state it rests in between runs. Outputs
+    /// resting on, as for normally-on timers, aren't forced off after the maximum on duration.
+    Rest(GpioOutMessage),
+    /// Like `Out` turning an output on, noting that the timer will turn it off again after
+    /// `duration` so that the run can be resumed if the controller restarts in the meantime
+    OnFor {
+        msg: GpioOutMessage,
+        duration: Duration,
+        /// Whether this is one of a timer's runs rather than e.g. a test fire, only those
+        /// being counted as fires
+        scheduled: bool,
+    },
+    /// Reply once every message sent before this one has been handled
+    Flush(oneshot::Sender<()>),
+    /// Reply once the output may be turned on, with a permit to hold while it is on if the
+    /// number of outputs on at once is capped
+    Slot {
+        output: u16,
+        resp: oneshot::Sender<Option<OwnedSemaphorePermit>>,
+    },
+    /// Turn an output on for a manual run, unless it's already on, and keep it on until
+    /// released. Scheduled messages for the output are not written in the meantime.
+    Hold {
+        msg: GpioOutMessage,
+        until: tokio::time::Instant,
+    },
+    /// End the manual run of an output which was held until `until`. The output is turned off
+    /// unless a later manual run holds it or its scheduled run is still going.
+    Release {
+        output: u16,
+        until: tokio::time::Instant,
+    },
+    /// Forget all manual runs so that following off messages are written, e.g. when shutting
+    /// down
+    ClearHolds,
+}
+
+/// Name of the sled tree holding the last value written to each output, keyed by big-endian
+/// pin number
+pub const OUTPUT_STATE: &str = "output_state";
+
+/// The last value written to an output, as persisted across restarts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PersistedOutput {
+    pub msg: GpioOutMessage,
+    /// When the run which turned the output on is due to turn it off, if known
+    pub off_at: Option<DateTime<Utc>>,
+}
+
+/// The last message successfully written to each output, keyed by pin
+pub type OutputStates = Arc<Mutex<HashMap<u16, GpioOutMessage>>>;
+
+/// A write to an output, as streamed to websocket clients and sent to webhooks
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct OutputEvent {
+    pub output: u16,
+    /// The logical value written, i.e. `true` means the load was turned on
+    pub value: bool,
+    pub at: DateTime<Local>,
+    /// Whether the write landed. Failed writes are only sent to webhooks.
+    pub ok: bool,
+}
+
+/// Broadcasts every output write to each subscriber
+pub type OutputEvents = broadcast::Sender<OutputEvent>;
+
+/// How many events a slow subscriber may fall behind by before it starts missing them
+const OUTPUT_EVENTS_CAPACITY: usize = 64;
+
+/// How long to wait before trying again to force off an output whose cutoff write failed
+const CUTOFF_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Read the current value of an input through the GPIO manager
+pub async fn read_input(tx: &mpsc::Sender<GpioMessage>, pin: u16) -> Result<bool, Error> {
+    let (resp, rx) = oneshot::channel();
+    tx.send(GpioMessage::ReadIn { pin, resp })
+        .await
+        .map_err(|e| Error::Anyhow(e.into()))?;
+    rx.await
+        .map_err(|_| Error::Anyhow(anyhow::anyhow!("GPIO manager failed to read input {}", pin)))
+}
+
+pub async fn run_timer(
+    tx: mpsc::Sender<GpioMessage>,
+    output: u16,
+    value: bool,
+    active_low: bool,
+    time: NaiveTime,
+    duration: Duration,
+) -> Result<(), Error> {
+    TimeFuture::new(time).await;
+    run_output(tx, output, value, active_low, duration).await
+}
+
+/// Immediately set an output to `value` for the given duration, then set it back
+pub async fn run_output(
+    tx: mpsc::Sender<GpioMessage>,
+    output: u16,
+    value: bool,
+    active_low: bool,
+    duration: Duration,
+) -> Result<(), Error> {
+    let mut outmsg = GpioOutMessage {
+        output,
+        value,
+        active_low,
+    };
+    let slot = OutputSlot::acquire(&tx, outmsg).await;
+    let on = match value {
+        true => GpioMessage::OnFor {
+            msg: outmsg,
+            duration,
+            scheduled: false,
+        },
+        false => outmsg.into(),
+    };
+    tx.send(on).await.map_err(|e| Error::Anyhow(e.into()))?;
+    info!(
+        "Sent message to set output {} to value {} for duration {}.",
+        output, value, &duration
+    );
+    tokio::time::sleep(duration.to_std().map_err(|e| Error::Anyhow(e.into()))?).await;
+    outmsg.value = !value;
+    tx.send(GpioMessage::Rest(outmsg))
+        .await
+        .map_err(|e| Error::Anyhow(e.into()))?;
+    drop(slot);
+    info!(
+        "Sent message to set output {} back to value {}.",
+        &output, !value
+    );
+    Ok(())
+}
+
+/// Permission to turn an output on while the number of outputs on at once is capped. The
+/// output counts as on until this is dropped.
+#[derive(Debug)]
+pub struct OutputSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+    /// How long it took for a slot to free up, which timers add to their run so that the
+    /// output still gets its full duration
+    pub waited: std::time::Duration,
+}
+
+impl OutputSlot {
+    /// Wait for a free slot before sending `msg`, if it turns an output on and the GPIO
+    /// manager caps how many may be on at once
+    pub async fn acquire(tx: &mpsc::Sender<GpioMessage>, msg: GpioOutMessage) -> OutputSlot {
+        let started = tokio::time::Instant::now();
+        let mut permit = None;
+        if msg.value {
+            let (resp, rx) = oneshot::channel();
+            let request = GpioMessage::Slot {
+                output: msg.output,
+                resp,
+            };
+            if tx.send(request).await.is_ok() {
+                // A closed channel means the manager stopped, so there is nothing to wait for
+                permit = rx.await.ok().flatten();
+            }
+        }
+        OutputSlot {
+            _permit: permit,
+            waited: started.elapsed(),
+        }
+    }
+}
+
+impl From<GpioOutMessage> for GpioMessage {
+    fn from(other: GpioOutMessage) -> GpioMessage {
+        GpioMessage::Out(other)
+    }
+}
+
+/// Messages queued for the GPIO manager by default before senders wait for room
+pub const DEFAULT_GPIO_CHANNEL_CAPACITY: usize = 32;
+
+/// A failed write to an output waiting to be tried again
+#[derive(Debug, Copy, Clone)]
+struct PendingWrite {
+    msg: GpioOutMessage,
+    /// How many attempts have been made so far
+    attempts: u32,
+    /// When to try again
+    due: tokio::time::Instant,
+    /// Whether the write is one of a timer's runs, to be counted as a fire once it lands
+    fire: bool,
+}
+
+#[derive(Debug)]
+pub struct GpioManager {
+    inputs: HashMap<u16, Box<dyn InputPin>>,
+    outputs: HashMap<u16, Box<dyn OutputPin>>,
+    backend: Box<dyn GpioBackend>,
+    /// Writes to pins outside this range are refused without opening the pin
+    allowed_pins: RangeInclusive<u16>,
+    states: OutputStates,
+    metrics: Arc<Metrics>,
+    events: OutputEvents,
+    /// One permit per output which may be on at once, if that is capped
+    slots: Option<Arc<Semaphore>>,
+    /// Times a failed write is retried before giving up
+    write_retries: u32,
+    /// Delay before the first retry, doubling for each one after
+    retry_delay: std::time::Duration,
+    /// Outputs whose scheduled runs last turned them on rather than off
+    scheduled_on: HashSet<u16>,
+    /// Outputs which were last turned on to rest on between runs
+    resting_on: HashSet<u16>,
+    /// Outputs held on by manual runs, with the message which turned each on and when the
+    /// latest run holding it ends
+    held: HashMap<u16, (GpioOutMessage, tokio::time::Instant)>,
+    /// The longest an output may stay on before it is forced off, if that is capped
+    max_on: Option<std::time::Duration>,
+    /// When each output which is on will be forced off
+    cutoffs: HashMap<u16, tokio::time::Instant>,
+    /// When each output which is on is due to be turned off, where that is known
+    off_at: HashMap<u16, DateTime<Utc>>,
+    /// The failed write to each output waiting to be retried, if any
+    retries: HashMap<u16, PendingWrite>,
+    /// Where the last value written to each output is persisted, if anywhere
+    persisted: Option<sled::Tree>,
+    rx: mpsc::Receiver<GpioMessage>,
+}
+impl GpioManager {
+    /// Use sysfs pins, or in-memory ones which log writes if `simulate` is set, e.g. for
+    /// development off the Pi. Up to `capacity` messages are queued for the manager, after
+    /// which senders wait for room.
+    pub fn new(
+        simulate: bool,
+        capacity: usize,
+    ) -> Result<(GpioManager, mpsc::Sender<GpioMessage>), Error> {
+        if simulate {
+            GpioManager::with_backend(Box::new(MockBackend::new()), capacity)
+        } else {
+            GpioManager::with_backend(Box::new(SysFsBackend), capacity)
+        }
+    }
+
+    pub fn with_backend(
+        backend: Box<dyn GpioBackend>,
+        capacity: usize,
+    ) -> Result<(GpioManager, mpsc::Sender<GpioMessage>), Error> {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let (inputs, outputs) = (HashMap::new(), HashMap::new());
+        let man = GpioManager {
+            inputs,
+            outputs,
+            backend,
+            allowed_pins: 1..=u16::MAX,
+            states: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
+            events: broadcast::channel(OUTPUT_EVENTS_CAPACITY).0,
+            slots: None,
+            write_retries: 3,
+            retry_delay: std::time::Duration::from_millis(100),
+            scheduled_on: HashSet::new(),
+            resting_on: HashSet::new(),
+            held: HashMap::new(),
+            max_on: None,
+            cutoffs: HashMap::new(),
+            off_at: HashMap::new(),
+            retries: HashMap::new(),
+            persisted: None,
+            rx,
+        };
+        Ok((man, tx))
+    }
+
+    /// Only write to pins in the given range, e.g. the ones wired to relays
+    pub fn with_allowed_pins(mut self, allowed_pins: RangeInclusive<u16>) -> GpioManager {
+        self.allowed_pins = allowed_pins;
+        self
+    }
+
+    /// Retry failed writes up to `retries` times, waiting `delay` before the first retry and
+    /// twice as long before each one after
+    pub fn with_write_retries(mut self, retries: u32, delay: std::time::Duration) -> GpioManager {
+        self.write_retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Allow at most `max` outputs on at once, making timers which would turn on another wait
+    /// until one turns off. Periodic pulse trains aren't counted.
+    pub fn with_max_concurrent_outputs(mut self, max: Option<usize>) -> GpioManager {
+        self.slots = max.map(|max| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Force any output which stays on for longer than `max_on` off, in case whatever turned
+    /// it on never turns it off, e.g. a stuck timer leaving a valve open. Manual runs are cut
+    /// off too, but outputs resting on between the runs of a normally-on timer aren't.
+    pub fn with_max_on_duration(mut self, max_on: Option<std::time::Duration>) -> GpioManager {
+        self.max_on = max_on;
+        self
+    }
+
+    /// Persist the last value written to each output and when it's due to turn off to the
+    /// given tree, so that it can be restored after a restart
+    pub fn with_persisted_state(mut self, tree: sled::Tree) -> GpioManager {
+        self.persisted = Some(tree);
+        self
+    }
+
+    /// The state of every output the manager has written, kept up to date as it writes them
+    pub fn output_states(&self) -> OutputStates {
+        self.states.clone()
+    }
+
+    /// The counters which the manager updates as it writes outputs
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// The channel on which the manager broadcasts each output it writes
+    pub fn output_events(&self) -> OutputEvents {
+        self.events.clone()
+    }
+    /// Read the value of an input, opening the pin the first time it is read
+    fn read_input(&mut self, num: u16) -> Option<bool> {
+        let pin = match self.inputs.entry(num) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let pin = self
+                    .backend
+                    .open_input(num)
+                    .map_err(|e| error!("{}", e))
+                    .ok()?;
+                info!("Opened GPIO port {} for reading", &num);
+                entry.insert(pin)
+            }
+        };
+        match pin.get() {
+            Ok(value) => {
+                debug!("Read value {} from pin {}.", value, &num);
+                Some(value)
+            }
+            Err(e) => {
+                error!("{}", e);
+                // Reopen the pin on the next read in case the handle went bad
+                self.inputs.remove(&num);
+                None
+            }
+        }
+    }
+
+    /// Try once to write the value of an output, opening the pin only the first time it is
+    /// written so that it stays exported between writes
+    fn try_write_output(&mut self, outmsg: GpioOutMessage) -> Result<(), Error> {
+        let pin = match self.outputs.entry(outmsg.output) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let pin = self.backend.open_output(outmsg.output)?;
+                info!("Opened GPIO output {} for writing", &outmsg.output);
+                entry.insert(pin)
+            }
+        };
+        if let Err(e) = pin.set(outmsg.pin_value()) {
+            // Reopen the pin on the next attempt in case the handle went bad
+            self.outputs.remove(&outmsg.output);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Make attempt number `attempts + 1` at writing the value of an output. If opening or
+    /// setting the pin fails, e.g. because sysfs is briefly busy, the write is retried with
+    /// exponential backoff from the run loop, so that writes to other outputs aren't held
+    /// up meanwhile. Returns whether the write landed on this attempt.
+    fn write_output(&mut self, outmsg: GpioOutMessage, attempts: u32, fire: bool) -> bool {
+        match self.try_write_output(outmsg) {
+            Ok(()) => {
+                info!(
+                    "Wrote value {} to pin {}.",
+                    outmsg.pin_value(),
+                    &outmsg.output
+                );
+                self.states.lock().unwrap().insert(outmsg.output, outmsg);
+                match (outmsg.value, self.max_on) {
+                    (true, _) if self.resting_on.contains(&outmsg.output) => {}
+                    // Turning an output on again doesn't put off its cutoff
+                    (true, Some(max_on)) => {
+                        self.cutoffs
+                            .entry(outmsg.output)
+                            .or_insert_with(|| tokio::time::Instant::now() + max_on);
+                    }
+                    (true, None) => {}
+                    (false, _) => {
+                        self.cutoffs.remove(&outmsg.output);
+                        self.resting_on.remove(&outmsg.output);
+                    }
+                }
+                if !outmsg.value {
+                    self.off_at.remove(&outmsg.output);
+                }
+                self.persist(outmsg);
+                self.metrics.gpio_writes.fetch_add(1, Ordering::Relaxed);
+                if fire {
+                    self.metrics.timer_fires.fetch_add(1, Ordering::Relaxed);
+                }
+                // Sending only fails when nobody is subscribed
+                let _ = self.events.send(OutputEvent {
+                    output: outmsg.output,
+                    value: outmsg.value,
+                    at: Local::now(),
+                    ok: true,
+                });
+                true
+            }
+            Err(e) if attempts < self.write_retries => {
+                let delay = self.retry_delay.saturating_mul(1 << attempts.min(31));
+                warn!(
+                    "Failed to write to pin {}, retrying in {:?}: {}",
+                    outmsg.output, delay, e
+                );
+                let retry = PendingWrite {
+                    msg: outmsg,
+                    attempts: attempts + 1,
+                    due: tokio::time::Instant::now() + delay,
+                    fire,
+                };
+                self.retries.insert(outmsg.output, retry);
+                false
+            }
+            Err(e) => {
+                error!(
+                    "Failed to write to pin {} after {} attempts: {}",
+                    outmsg.output,
+                    attempts + 1,
+                    e
+                );
+                self.metrics
+                    .gpio_write_failures
+                    .fetch_add(1, Ordering::Relaxed);
+                let _ = self.events.send(OutputEvent {
+                    output: outmsg.output,
+                    value: outmsg.value,
+                    at: Local::now(),
+                    ok: false,
+                });
+                false
+            }
+        }
+    }
+
+    /// Retry every failed write which is due
+    fn retry_due_writes(&mut self) {
+        let now = tokio::time::Instant::now();
+        let due: Vec<PendingWrite> = self
+            .retries
+            .values()
+            .filter(|retry| retry.due <= now)
+            .copied()
+            .collect();
+        for retry in due {
+            self.retries.remove(&retry.msg.output);
+            self.write_output(retry.msg, retry.attempts, retry.fire);
+        }
+    }
+
+    /// Record the last value written to an output and when it's due to turn off
+    fn persist(&self, outmsg: GpioOutMessage) {
+        let Some(tree) = &self.persisted else {
+            return;
+        };
+        let record = PersistedOutput {
+            msg: outmsg,
+            off_at: self.off_at.get(&outmsg.output).copied(),
+        };
+        let result = serde_json::to_vec(&record)
+            .map_err(Error::from)
+            .and_then(|bytes| Ok(tree.insert(outmsg.output.to_be_bytes(), bytes)?));
+        if let Err(e) = result {
+            error!(
+                "Failed to persist the state of output {}: {}",
+                outmsg.output, e
+            );
+        }
+    }
+
+    /// Write an output for a scheduled run, unless a manual run is holding it on. If `fire` is
+    /// set the write is counted as one of a timer's runs once it lands.
+    fn write_scheduled(&mut self, outmsg: GpioOutMessage, fire: bool) {
+        if outmsg.value {
+            self.scheduled_on.insert(outmsg.output);
+        } else {
+            self.scheduled_on.remove(&outmsg.output);
+        }
+        if self.held.contains_key(&outmsg.output) {
+            info!(
+                "Output {} is held on by a manual run, not setting it to {}",
+                outmsg.output, outmsg.value
+            );
+            return;
+        }
+        self.write_counted(outmsg, fire);
+    }
+
+    /// Whether the last value written to an output turned it on
+    fn is_on(&self, output: u16) -> bool {
+        self.states
+            .lock()
+            .unwrap()
+            .get(&output)
+            .is_some_and(|msg| msg.value)
+    }
+
+    /// Write an output if it's in the allowed range, updating the metrics. Any retry of an
+    /// earlier write to the same output is dropped, as this one supersedes it.
+    fn write_counted(&mut self, outmsg: GpioOutMessage, fire: bool) {
+        if !self.allowed_pins.contains(&outmsg.output) {
+            error!(
+                "Refusing to write to pin {} outside the allowed range {:?}",
+                outmsg.output, self.allowed_pins
+            );
+            self.metrics
+                .gpio_writes_rejected
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.retries.remove(&outmsg.output);
+        self.write_output(outmsg, 0, fire);
+    }
+
+    /// Force off every output which has been on for longer than the maximum
+    fn cut_off_expired(&mut self) {
+        let now = tokio::time::Instant::now();
+        let expired: Vec<u16> = self
+            .cutoffs
+            .iter()
+            .filter(|(_, cutoff)| **cutoff <= now)
+            .map(|(output, _)| *output)
+            .collect();
+        for output in expired {
+            self.cutoffs.remove(&output);
+            let Some(msg) = self.states.lock().unwrap().get(&output).copied() else {
+                continue;
+            };
+            error!(
+                "Output {} has been on for longer than the maximum of {:?}, forcing it off!",
+                output,
+                self.max_on.unwrap_or_default()
+            );
+            self.held.remove(&output);
+            self.scheduled_on.remove(&output);
+            self.metrics.forced_offs.fetch_add(1, Ordering::Relaxed);
+            self.write_counted(
+                GpioOutMessage {
+                    value: false,
+                    ..msg
+                },
+                false,
+            );
+            if self.is_on(output) {
+                // The write failed, so keep trying
+                self.cutoffs.insert(output, now + CUTOFF_RETRY_INTERVAL);
+            }
+        }
+    }
+
+    pub fn run(mut self) -> Result<(), Error> {
+        tokio::spawn(async move {
+            debug!("Spawned GPIO manager thread");
+            loop {
+                let cutoff = self.cutoffs.values().min().copied();
+                let retry = self.retries.values().map(|retry| retry.due).min();
+                let message = tokio::select! {
+                    message = self.rx.recv() => message,
+                    _ = tokio::time::sleep_until(cutoff.unwrap_or_else(tokio::time::Instant::now)),
+                        if cutoff.is_some() =>
+                    {
+                        self.cut_off_expired();
+                        continue;
+                    }
+                    _ = tokio::time::sleep_until(retry.unwrap_or_else(tokio::time::Instant::now)),
+                        if retry.is_some() =>
+                    {
+                        self.retry_due_writes();
+                        continue;
+                    }
+                };
+                let Some(message) = message else {
+                    break;
+                };
+                debug!("Received GPIO message: {:?}", &message);
+                match message {
+                    GpioMessage::In(num) => {
+                        self.read_input(num);
+                    }
+                    GpioMessage::ReadIn { pin, resp } => {
+                        // Dropping the sender on failure lets the caller know the read failed
+                        if let Some(value) = self.read_input(pin) {
+                            let _ = resp.send(value);
+                        }
+                    }
+                    GpioMessage::Out(outmsg) => self.write_scheduled(outmsg, false),
+                    GpioMessage::Rest(outmsg) => {
+                        if outmsg.value {
+                            self.resting_on.insert(outmsg.output);
+                            self.cutoffs.remove(&outmsg.output);
+                        }
+                        self.write_scheduled(outmsg, false);
+                    }
+                    GpioMessage::OnFor {
+                        msg,
+                        duration,
+                        scheduled,
+                    } => {
+                        self.off_at.insert(msg.output, Utc::now() + duration);
+                        self.write_scheduled(msg, scheduled);
+                    }
+                    GpioMessage::Hold { msg, until } => {
+                        let on = self.is_on(msg.output);
+                        let hold = self.held.entry(msg.output).or_insert((msg, until));
+                        hold.1 = hold.1.max(until);
+                        let remaining = hold.1 - tokio::time::Instant::now();
+                        let off_at = Utc::now() + Duration::from_std(remaining).unwrap_or_default();
+                        self.off_at.insert(msg.output, off_at);
+                        if on {
+                            // Don't let a retried write turn it off while it's held
+                            self.retries.remove(&msg.output);
+                            self.persist(msg);
+                        } else {
+                            self.write_counted(msg, false);
+                        }
+                    }
+                    GpioMessage::Release { output, until } => {
+                        let Some(&(msg, held_until)) = self.held.get(&output) else {
+                            continue;
+                        };
+                        if held_until > until {
+                            // A later manual run of the same output is still going
+                            continue;
+                        }
+                        self.held.remove(&output);
+                        if self.scheduled_on.contains(&output) {
+                            info!("Leaving output {} on for its scheduled run", output);
+                        } else {
+                            self.write_counted(
+                                GpioOutMessage {
+                                    value: false,
+                                    ..msg
+                                },
+                                false,
+                            );
+                        }
+                    }
+                    GpioMessage::ClearHolds => self.held.clear(),
+                    GpioMessage::Flush(resp) => {
+                        let _ = resp.send(());
+                    }
+                    GpioMessage::Slot { output, resp } => {
+                        let Some(slots) = self.slots.clone() else {
+                            let _ = resp.send(None);
+                            continue;
+                        };
+                        if slots.available_permits() == 0 {
+                            warn!(
+                                "Output {} is waiting for another output to turn off",
+                                output
+                            );
+                        }
+                        // Wait in a separate task so that other messages keep being handled.
+                        // If the caller has gone the permit is dropped along with the reply.
+                        tokio::spawn(async move {
+                            if let Ok(permit) = slots.acquire_owned().await {
+                                let _ = resp.send(Some(permit));
+                            }
+                        });
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Where the scheduler gets the current time from, so that tests can control it
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock which starts at a fixed time and then advances along with tokio's clock, so that
+/// pausing and advancing tokio's time in a test moves it too
+#[derive(Debug, Copy, Clone)]
+pub struct MockClock {
+    start: DateTime<Utc>,
+    started: tokio::time::Instant,
+}
+
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> MockClock {
+        MockClock {
+            start,
+            started: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = Duration::from_std(self.started.elapsed()).unwrap_or_default();
+        self.start + elapsed
+    }
+}
+
+/// Open the database at `path`, creating it and any missing parent directories. Fails with a
+/// clear error if `path` is a file rather than sled's opaque one.
+pub fn open_db(path: &Path) -> Result<sled::Db, Error> {
+    if path.exists() && !path.is_dir() {
+        return Err(Error::DbNotDirectory(path.to_owned()));
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        if parent.exists() && !parent.is_dir() {
+            return Err(Error::DbNotDirectory(parent.to_owned()));
+        }
+        std::fs::create_dir_all(parent).map_err(|e| {
+            Error::Anyhow(anyhow::anyhow!(
+                "Failed to create the database's parent directory {:?}: {}",
+                parent.display(),
+                e
+            ))
+        })?;
+    }
+    Ok(sled::open(path)?)
+}
+
+/// Deserialize a stored timer, logging and skipping records which can't be read
+pub(crate) fn read_timer(key: &[u8], val: &[u8]) -> Option<IntervalTimer> {
+    match IntervalTimer::from_json_slice(val) {
+        Ok(timer) => Some(timer),
+        Err(e) => {
+            warn!(
+                "Skipping unreadable timer record {}: {}",
+                record_key_label(key),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// A timer record's key for logs, as a UUID if it is one and as raw bytes otherwise
+fn record_key_label(key: &[u8]) -> String {
+    Uuid::from_slice(key).map_or_else(|_| format!("{:?}", key), |id| id.to_string())
+}
+
+/// The current wall-clock date and time in the given timezone, or the system's local timezone
+/// if `None`
+pub fn local_datetime(tz: Option<Tz>) -> NaiveDateTime {
+    local_datetime_with(tz, &SystemClock)
+}
+
+/// Like `local_datetime`, but reading the time from `clock`
+pub fn local_datetime_with(tz: Option<Tz>, clock: &dyn Clock) -> NaiveDateTime {
+    match tz {
+        Some(tz) => clock.now().with_timezone(&tz).naive_local(),
+        None => clock.now().with_timezone(&Local).naive_local(),
+    }
+}
+
+pub fn local_time(tz: Option<Tz>) -> NaiveTime {
+    local_datetime(tz).time()
+}
+
+/// The next time after `now` at which the wall clock of its timezone reads `time`. A time
+/// skipped by a daylight saving transition occurs that much later instead, e.g. 02:30 becomes
+/// 03:30 when the clocks go forward at 02:00, and a time repeated by one occurs the first time.
+pub fn next_occurrence<T: TimeZone>(now: &DateTime<T>, time: NaiveTime) -> DateTime<T> {
+    let tz = now.timezone();
+    let mut date = now.date_naive();
+    loop {
+        match wall_clock_instant(&tz, date.and_time(time)) {
+            Some(occurrence) if occurrence > *now => return occurrence,
+            _ => {}
+        }
+        date = match date.succ_opt() {
+            Some(next) => next,
+            None => return now.clone(),
+        };
+    }
+}
+
+/// When the wall clock of `tz` reads `naive`, or an hour later if a daylight saving transition
+/// skips it. A time repeated by a transition occurs the first time.
+fn wall_clock_instant<T: TimeZone>(tz: &T, naive: NaiveDateTime) -> Option<DateTime<T>> {
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(dt) => Some(dt),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => tz
+            .from_local_datetime(&(naive + Duration::hours(1)))
+            .earliest(),
+    }
+}
+
+/// Time until the wall clock of the given timezone, or the system's local timezone if `None`,
+/// reads `target`, which may be on a later date. Negative if it already has.
+pub fn time_until_datetime(target: NaiveDateTime, tz: Option<Tz>) -> Option<Duration> {
+    time_until_datetime_with(target, tz, &SystemClock)
+}
+
+/// Like `time_until_datetime`, but reading the time from `clock`
+pub fn time_until_datetime_with(
+    target: NaiveDateTime,
+    tz: Option<Tz>,
+    clock: &dyn Clock,
+) -> Option<Duration> {
+    let target = match tz {
+        Some(tz) => wall_clock_instant(&tz, target)?.with_timezone(&Utc),
+        None => wall_clock_instant(&Local, target)?.with_timezone(&Utc),
+    };
+    Some(target - clock.now())
+}
+
+/// Time from `now` until the clock next reads `target`, ignoring daylight saving. A target
+/// equal to `now` is a full day away.
+pub fn wrapping_time_until(now: NaiveTime, target: NaiveTime) -> Duration {
+    if target > now {
+        target - now
+    } else {
+        Duration::days(1) - (now - target)
+    }
+}
+
+/// Time until the wall clock of the given timezone, or the system's local timezone if `None`,
+/// next reads `target`. That's `target - now` if the target is later today, and otherwise
+/// `24h - (now - target)` until tomorrow's, give or take a daylight saving change. A target
+/// equal to now is a full day away, so a timer which has just fired doesn't fire again.
+pub fn time_until(target: NaiveTime, tz: Option<Tz>) -> Duration {
+    time_until_with(target, tz, &SystemClock)
+}
+
+/// Like `time_until`, but reading the time from `clock`
+pub fn time_until_with(target: NaiveTime, tz: Option<Tz>, clock: &dyn Clock) -> Duration {
+    match tz {
+        Some(tz) => {
+            let now = clock.now().with_timezone(&tz);
+            next_occurrence(&now, target) - now
+        }
+        None => {
+            let now = clock.now().with_timezone(&Local);
+            next_occurrence(&now, target) - now
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeSharedState {
+    completed: bool,
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+impl TimeSharedState {
+    fn complete(&mut self) {
+        self.completed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake()
+        }
+    }
+}
+
+/// A future that resolves at a given time. Dropping it stops the task sleeping until then.
+pub struct TimeFuture {
+    shared_state: Arc<Mutex<TimeSharedState>>,
+    task: JoinHandle<()>,
+}
+impl Drop for TimeFuture {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+impl Future for TimeFuture {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared_state = self.shared_state.lock().unwrap();
+        if shared_state.completed {
+            Poll::Ready(())
+        } else {
+            shared_state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+impl TimeFuture {
+    /// Returns a future which will resolve at the next occurrence of `time` in the local timezone
+    pub fn new(time: NaiveTime) -> Self {
+        TimeFuture::new_in(time, None)
+    }
+
+    /// Returns a future which will resolve at the next occurrence of `time` in the given
+    /// timezone, or the local timezone if `None`
+    pub fn new_in(time: NaiveTime, tz: Option<Tz>) -> Self {
+        TimeFuture::after(time_until(time, tz))
+    }
+
+    /// Like `new_in`, but reading the current time from `clock`
+    pub fn new_with_clock(time: NaiveTime, tz: Option<Tz>, clock: &dyn Clock) -> Self {
+        TimeFuture::after(time_until_with(time, tz, clock))
+    }
+
+    /// Returns a future which will resolve at the given date and time, or immediately if it
+    /// has already passed
+    pub fn at<T: TimeZone>(datetime: DateTime<T>) -> Self {
+        TimeFuture::at_with_clock(datetime, &SystemClock)
+    }
+
+    /// Like `at`, but reading the current time from `clock`
+    pub fn at_with_clock<T: TimeZone>(datetime: DateTime<T>, clock: &dyn Clock) -> Self {
+        TimeFuture::after(datetime.with_timezone(&Utc) - clock.now())
+    }
+
+    /// Like `new`, but also returns a handle which resolves the future early when it is
+    /// cancelled or dropped, stopping the task sleeping until `time`
+    pub fn new_cancellable(time: NaiveTime) -> (Self, CancelHandle) {
+        let future = TimeFuture::new(time);
+        let handle = CancelHandle {
+            task: future.task.abort_handle(),
+            shared_state: Some(future.shared_state.clone()),
+        };
+        (future, handle)
+    }
+
+    /// Whether the future resolved because it was cancelled rather than because its time came
+    pub fn is_cancelled(&self) -> bool {
+        self.shared_state.lock().unwrap().cancelled
+    }
+
+    fn after(sleep_time: Duration) -> Self {
+        let shared_state = Arc::new(Mutex::new(TimeSharedState {
+            completed: false,
+            cancelled: false,
+            waker: None,
+        }));
+        let thread_shared_state = shared_state.clone();
+        let task = tokio::spawn(async move {
+            sleep(sleep_time.to_std().unwrap_or_default()).await;
+            thread_shared_state.lock().unwrap().complete();
+        });
+        TimeFuture { shared_state, task }
+    }
+}
+
+/// Cancels a task, either explicitly or when dropped. A handle from
+/// [TimeFuture::new_cancellable] stops the future's sleep and resolves it early. One from
+/// [CancelHandle::new] stops a timer's whole task, and the `TimeFuture` it is waiting on is
+/// dropped along with it.
+#[derive(Debug)]
+pub struct CancelHandle {
+    task: AbortHandle,
+    /// The state of the future to resolve on cancelling, if the handle is for a `TimeFuture`
+    shared_state: Option<Arc<Mutex<TimeSharedState>>>,
+}
+impl CancelHandle {
+    pub fn new(task: &JoinHandle<()>) -> CancelHandle {
+        CancelHandle {
+            task: task.abort_handle(),
+            shared_state: None,
+        }
+    }
+
+    /// Stop the task, if it hasn't finished already
+    pub fn cancel(self) {
+        // Dropping the handle does the cancelling
+        drop(self)
+    }
+
+    /// Whether the task has finished, e.g. a one-off timer which has run
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+impl Drop for CancelHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(shared_state) = &self.shared_state {
+            let mut shared_state = shared_state.lock().unwrap();
+            if !shared_state.completed {
+                shared_state.cancelled = true;
+                shared_state.complete();
+            }
+        }
+    }
+}
+
+/// A PWM-style timer which turns an output on for `pulse_width` at the start of every
+/// `period`, where `pulse_width` is the `duty` fraction of the period
+#[derive(Debug, Copy, Clone)]
+pub struct Periodic {
+    pulse_width: Duration,
+    duty: f32,
+    period: Duration,
+    active_low: bool,
+}
+
+impl Periodic {
+    pub fn new(pulse_width: Duration, duty: f32, period: Duration) -> Result<Periodic, Error> {
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::InvalidDuty(duty));
+        }
+        if period <= Duration::zero() || pulse_width < Duration::zero() || pulse_width > period {
+            return Err(Error::InvalidDuration);
+        }
+        Ok(Periodic {
+            pulse_width,
+            duty,
+            period,
+            active_low: false,
+        })
+    }
+
+    /// Turn on for the `duty` fraction of every `period`
+    pub fn from_duty(duty: f32, period: Duration) -> Result<Periodic, Error> {
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::InvalidDuty(duty));
+        }
+        let pulse_width = Duration::milliseconds(
+            (period.num_milliseconds() as f64 * f64::from(duty)).round() as i64,
+        );
+        Periodic::new(pulse_width, duty, period)
+    }
+
+    /// Turn on for `pulse_width` at the start of every `period`
+    pub fn from_pulse_width(pulse_width: Duration, period: Duration) -> Result<Periodic, Error> {
+        if period <= Duration::zero() {
+            return Err(Error::InvalidDuration);
+        }
+        let duty =
+            (pulse_width.num_milliseconds() as f64 / period.num_milliseconds() as f64) as f32;
+        Periodic::new(pulse_width, duty, period)
+    }
+
+    /// Drive the output low to turn it on, as for many relay boards
+    pub fn with_active_low(mut self, active_low: bool) -> Periodic {
+        self.active_low = active_low;
+        self
+    }
+
+    pub fn pulse_width(&self) -> Duration {
+        self.pulse_width
+    }
+    pub fn duty(&self) -> f32 {
+        self.duty
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn run(&self, tx: mpsc::Sender<GpioMessage>, output: u16) -> JoinHandle<()> {
+        let on_msg = GpioOutMessage {
+            output,
+            value: true,
+            active_low: self.active_low,
+        };
+        let off_msg = GpioOutMessage {
+            value: false,
+            ..on_msg
+        };
+        let on_time = self.pulse_width.to_std().unwrap_or_default();
+        let off_time = (self.period - self.pulse_width)
+            .to_std()
+            .unwrap_or_default();
+        tokio::spawn(async move {
+            info!(
+                "Spawned task to run new periodic timer on output {}.",
+                output
+            );
+            loop {
+                if !on_time.is_zero() {
+                    let _ = tx.send(on_msg.into()).await.map_err(|e| error!("{}", e));
+                    sleep(on_time).await;
+                }
+                if !off_time.is_zero() {
+                    let _ = tx.send(off_msg.into()).await.map_err(|e| error!("{}", e));
+                    sleep(off_time).await;
+                }
+            }
+        })
+    }
+}
+
+/// The task running a scheduled timer, along with the flag it checks before each run
+pub struct RunningTimer {
+    pub cancel: CancelHandle,
+    pub enabled: Arc<AtomicBool>,
+}
+
+/// Name of the sled tree indexing timers by start time
+const START_TIME_INDEX: &str = "by_start_time";
+
+/// Name of the sled tree holding deleted timers until they are purged
+const TRASH: &str = "_trash";
+
+/// How often the trash is checked for timers older than the retention
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// A deleted timer in the trash, along with when it was deleted
+#[derive(Debug, Serialize, Deserialize)]
+struct TrashedTimer<T> {
+    deleted_at: DateTime<Utc>,
+    timer: T,
+}
+
+/// Name of the sled tree mapping timer names to ids
+const NAME_INDEX: &str = "by_name";
+
+/// The key of a timer in the name index, if it has a non-empty name
+fn name_key(timer: &IntervalTimer) -> Option<&str> {
+    timer
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// The key of a timer in the start time index, if it has a start time
+fn start_time_key(timer: &IntervalTimer) -> Option<[u8; 20]> {
+    let start_time = timer.settings.start_time?;
+    let mut key = [0; 20];
+    key[..4].copy_from_slice(&start_time.num_seconds_from_midnight().to_be_bytes());
+    key[4..].copy_from_slice(timer.get_id().as_bytes());
+    Some(key)
+}
+
+#[derive(Clone)]
 pub struct AppState {
     pub db: Arc<sled::Db>,
     pub gpio_tx: mpsc::Sender<GpioMessage>,
+    /// Handles to the running task of each scheduled timer, keyed by timer id
+    pub tasks: Arc<Mutex<HashMap<Uuid, RunningTimer>>>,
+    /// Output pins which timers are allowed to control
+    pub allowed_pins: RangeInclusive<u16>,
+    /// The last value written to each output, as tracked by the GPIO manager
+    pub output_states: OutputStates,
+    pub metrics: Arc<Metrics>,
+    /// Timezone for new timers which don't specify one, or the server's local timezone if `None`
+    pub default_tz: Option<Tz>,
+    /// Flush the database to disk after every write instead of leaving it to sled
+    pub durable: bool,
+    /// Output writes broadcast by the GPIO manager
+    pub output_events: OutputEvents,
+    /// Refuse to store a timer with the same name as another
+    pub unique_names: bool,
+    /// Clamp submitted durations into the allowed range instead of rejecting them
+    pub clamp_durations: bool,
+    /// Names which may be given for output pins instead of their numbers
+    pub pin_aliases: HashMap<String, u16>,
+    /// The unit durations are given and shown in as plain numbers
+    pub duration_unit: DurationUnit,
+    /// Asked before each scheduled run whether to skip it, e.g. because it has rained
+    pub skip_check: Option<Webhook>,
+    /// How long deleted timers stay in the trash before they are purged
+    pub trash_retention: Duration,
+    /// Where the current time comes from
+    pub clock: Arc<dyn Clock>,
+    /// The most entries kept in the audit log
+    pub audit_max: usize,
+    /// How many entries the audit log holds, counted when it's first written to since sled
+    /// counts a tree's entries by walking it
+    pub audit_len: Arc<OnceLock<AtomicUsize>>,
+    /// The most runs kept in each timer's history
+    pub history_max: usize,
+    /// Timer runs and changes, streamed to `GET /events`
+    pub scheduler_events: SchedulerEvents,
+    /// Timers with a fixed start time start up to this long after it, so that timers sharing
+    /// a start time don't all turn on at once
+    pub start_stagger: std::time::Duration,
+    /// When this state was created, i.e. when the controller started
+    pub started: std::time::Instant,
 }
 impl AppState {
+    pub fn new(db: Arc<sled::Db>, gpio_tx: mpsc::Sender<GpioMessage>) -> AppState {
+        AppState {
+            db,
+            gpio_tx,
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            allowed_pins: 1..=u16::MAX,
+            output_states: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
+            default_tz: None,
+            durable: false,
+            output_events: broadcast::channel(OUTPUT_EVENTS_CAPACITY).0,
+            unique_names: false,
+            clamp_durations: false,
+            pin_aliases: HashMap::new(),
+            duration_unit: DurationUnit::Minutes,
+            skip_check: None,
+            trash_retention: Duration::days(7),
+            clock: Arc::new(SystemClock),
+            audit_max: 1000,
+            audit_len: Arc::new(OnceLock::new()),
+            history_max: 100,
+            scheduler_events: broadcast::channel(SCHEDULER_EVENTS_CAPACITY).0,
+            start_stagger: std::time::Duration::ZERO,
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// Spread the starts of timers sharing a start time over up to `start_stagger`
+    pub fn with_start_stagger(mut self, start_stagger: std::time::Duration) -> AppState {
+        self.start_stagger = start_stagger;
+        self
+    }
+
+    /// Keep at most this many entries in the audit log, dropping the oldest
+    pub fn with_audit_max(mut self, audit_max: usize) -> AppState {
+        self.audit_max = audit_max;
+        self
+    }
+
+    /// Take and show durations given as plain numbers in `duration_unit`
+    pub fn with_duration_unit(mut self, duration_unit: DurationUnit) -> AppState {
+        self.duration_unit = duration_unit;
+        self
+    }
+
+    /// Keep at most this many runs in each timer's history, dropping the oldest
+    pub fn with_history_max(mut self, history_max: usize) -> AppState {
+        self.history_max = history_max;
+        self
+    }
+
+    /// Keep deleted timers in the trash for the given time before purging them
+    pub fn with_trash_retention(mut self, trash_retention: Duration) -> AppState {
+        self.trash_retention = trash_retention;
+        self
+    }
+
+    /// Read the current time from `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> AppState {
+        self.clock = clock;
+        self
+    }
+
+    /// Refuse to store a timer with the same name as a different timer
+    pub fn with_unique_names(mut self, unique_names: bool) -> AppState {
+        self.unique_names = unique_names;
+        self
+    }
+
+    /// Clamp submitted durations into the allowed range with a warning instead of rejecting them
+    pub fn with_clamp_durations(mut self, clamp_durations: bool) -> AppState {
+        self.clamp_durations = clamp_durations;
+        self
+    }
+
+    /// Let outputs be given by the names in `pin_aliases` as well as by number
+    pub fn with_pin_aliases(mut self, pin_aliases: HashMap<String, u16>) -> AppState {
+        self.pin_aliases = pin_aliases;
+        self
+    }
+
+    /// Ask `skip_check` before each scheduled run whether to skip it
+    pub fn with_skip_check(mut self, skip_check: Option<Webhook>) -> AppState {
+        self.skip_check = skip_check;
+        self
+    }
+
+    /// Replace an output given by one of the configured aliases with its pin, returning the
+    /// alias. Pins and unknown aliases are left as they are.
+    pub fn resolve_output(&self, output: &mut OutputRef) -> Option<String> {
+        let OutputRef::Alias(alias) = output else {
+            return None;
+        };
+        let alias = alias.trim().to_owned();
+        let pin = *self.pin_aliases.get(&alias)?;
+        *output = OutputRef::Pin(pin);
+        Some(alias)
+    }
+
+    /// Fill in the server's defaults for a submitted timer, resolve its output if it was given
+    /// by alias, and clamp its duration if lenient. Unknown aliases are left for validation to
+    /// reject.
+    pub fn fill_new_daily(&self, n: &mut NewDaily) {
+        n.or_timezone(self.default_tz);
+        n.duration_unit = self.duration_unit;
+        if let Some(alias) = self.resolve_output(&mut n.output) {
+            n.output_alias = Some(alias);
+        }
+        if self.clamp_durations {
+            n.clamp_duration();
+        }
+    }
+
+    /// Share the output events broadcast by a `GpioManager`
+    pub fn with_output_events(mut self, output_events: OutputEvents) -> AppState {
+        self.output_events = output_events;
+        self
+    }
+
+    /// Flush the database to disk after every write, trading write latency for not losing
+    /// recent changes on power loss
+    pub fn with_durable(mut self, durable: bool) -> AppState {
+        self.durable = durable;
+        self
+    }
+
+    /// Use the given timezone for new timers which don't specify one
+    pub fn with_default_timezone(mut self, default_tz: Option<Tz>) -> AppState {
+        self.default_tz = default_tz;
+        self
+    }
+
+    /// Only allow timers to control pins in the given range
+    pub fn with_allowed_pins(mut self, allowed_pins: RangeInclusive<u16>) -> AppState {
+        self.allowed_pins = allowed_pins;
+        self
+    }
+
+    /// Share the output states tracked by a `GpioManager`
+    pub fn with_output_states(mut self, output_states: OutputStates) -> AppState {
+        self.output_states = output_states;
+        self
+    }
+
+    /// The outputs which are currently turned on, along with the message which turned each on
+    pub fn active_outputs(&self) -> Vec<GpioOutMessage> {
+        self.output_states
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|msg| msg.value)
+            .copied()
+            .collect()
+    }
+
+    /// Check that the database responds and the GPIO manager is still receiving messages,
+    /// returning the reason if not
+    pub fn check_health(&self) -> Result<(), String> {
+        if self.gpio_tx.is_closed() {
+            return Err("GPIO manager channel is closed".to_owned());
+        }
+        self.db
+            .first()
+            .map_err(|e| format!("Database error: {}", e))?;
+        Ok(())
+    }
+
+    /// Share the counters updated by a `GpioManager`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> AppState {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Stop every timer and turn off every output which is on, waiting until the GPIO manager
+    /// has written them so that nothing is left energized on exit
+    pub async fn shutdown(&self) -> Result<(), Error> {
+        // Stop the timers first so that none of them turn an output back on
+        for (_, running) in self.tasks.lock().unwrap().drain() {
+            running.cancel.cancel();
+        }
+        self.gpio_tx
+            .send(GpioMessage::ClearHolds)
+            .await
+            .map_err(|e| Error::Anyhow(e.into()))?;
+        for msg in self.active_outputs() {
+            info!("Turning off output {} before shutting down", msg.output);
+            let off = GpioOutMessage {
+                value: false,
+                ..msg
+            };
+            self.gpio_tx
+                .send(off.into())
+                .await
+                .map_err(|e| Error::Anyhow(e.into()))?;
+        }
+        let (resp, rx) = oneshot::channel();
+        self.gpio_tx
+            .send(GpioMessage::Flush(resp))
+            .await
+            .map_err(|e| Error::Anyhow(e.into()))?;
+        rx.await
+            .map_err(|_| Error::Anyhow(anyhow::anyhow!("GPIO manager stopped before flushing")))
+    }
+
+    /// Check that a timer is allowed to control the given output pin
+    pub fn check_pin(&self, pin: u16) -> Result<(), Error> {
+        if pin != 0 && self.allowed_pins.contains(&pin) {
+            Ok(())
+        } else {
+            Err(Error::InvalidPin(pin))
+        }
+    }
+
+    /// Refuse a manual run with `Error::Busy` if the GPIO manager's queue is full, rather than
+    /// leaving the request waiting for room. Scheduled runs wait instead.
+    pub fn check_gpio_capacity(&self) -> Result<(), Error> {
+        match self.gpio_tx.capacity() {
+            0 => Err(Error::Busy),
+            _ => Ok(()),
+        }
+    }
+
+    /// Store a timer, keeping the runs already stored for it, which may have been recorded
+    /// since `interval` was read. Returns the timer it replaced, if any.
     pub fn insert_interval_timer(
         &self,
         interval: &IntervalTimer,
+    ) -> Result<Option<IntervalTimer>, Error> {
+        self.store_interval_timer(interval, true)
+    }
+
+    /// Store a timer exactly as given, e.g. when restoring it, discarding any runs stored for
+    /// it. Returns the timer it replaced, if any.
+    pub fn replace_interval_timer(
+        &self,
+        interval: &IntervalTimer,
+    ) -> Result<Option<IntervalTimer>, Error> {
+        self.store_interval_timer(interval, false)
+    }
+
+    fn store_interval_timer(
+        &self,
+        interval: &IntervalTimer,
+        keep_runs: bool,
     ) -> Result<Option<IntervalTimer>, Error> {
         let id = interval.get_id();
+        let names = self.name_index()?;
+        let name = name_key(interval);
         let bytes = interval.to_json_vec()?;
-        let prev = self.db.insert(id.as_bytes(), bytes)?;
+        if let (Some(name), true) = (name, self.unique_names) {
+            // Claim the name before writing the timer, so that two timers given the same name
+            // at once can't both find it free
+            let claimed =
+                names.compare_and_swap(name, None as Option<&[u8]>, Some(id.as_bytes()))?;
+            if claimed.is_err_and(|e| e.current.as_deref() != Some(id.as_bytes())) {
+                return Err(Error::DuplicateName(name.to_owned()));
+            }
+        }
+        let prev = self.db.fetch_and_update(id.as_bytes(), |old| {
+            match old.and_then(|old| IntervalTimer::from_json_slice(old).ok()) {
+                Some(prev)
+                    if keep_runs
+                        && (prev.fire_count, prev.last_fired_at)
+                            != (interval.fire_count, interval.last_fired_at) =>
+                {
+                    let mut timer = interval.clone();
+                    timer.fire_count = prev.fire_count;
+                    timer.last_fired_at = prev.last_fired_at;
+                    timer.to_json_vec().ok().or_else(|| Some(bytes.clone()))
+                }
+                _ => Some(bytes.clone()),
+            }
+        })?;
         let prev = match prev {
             Some(ivec) => Some(IntervalTimer::from_json_slice(ivec.as_ref())?),
             _ => None,
         };
+        let index = self.start_time_index()?;
+        if let Some(prev) = &prev {
+            if let Some(key) = start_time_key(prev) {
+                index.remove(key)?;
+            }
+        }
+        if let Some(key) = start_time_key(interval) {
+            index.insert(key, &[])?;
+        }
+        if let Some(old) = prev
+            .as_ref()
+            .and_then(name_key)
+            .filter(|&old| Some(old) != name)
+        {
+            // Only drop the entry if it's ours, since names may be shared without unique names
+            let _ = names.compare_and_swap(old, Some(id.as_bytes()), None as Option<&[u8]>)?;
+        }
+        if let Some(name) = name {
+            names.insert(name, id.as_bytes())?;
+        }
+        self.flush_if_durable()?;
         Ok(prev)
     }
 
-    pub fn get_interval_timer(&self, id: impl AsRef<[u8]>) -> Result<Option<IntervalTimer>, Error> {
-        match self.db.get(id.as_ref())? {
+    /// Insert new timers all at once, so that either all of them are stored or none are
+    pub fn insert_interval_timers(&self, timers: &[IntervalTimer]) -> Result<(), Error> {
+        let entries = timers
+            .iter()
+            .map(|timer| {
+                Ok((
+                    timer.get_id(),
+                    timer.to_json_vec()?,
+                    start_time_key(timer),
+                    name_key(timer),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (index, names) = (self.start_time_index()?, self.name_index()?);
+        (&**self.db, &index, &names)
+            .transaction(|(db, index, names)| {
+                for (id, bytes, key, name) in &entries {
+                    db.insert(id.as_bytes(), bytes.as_slice())?;
+                    if let Some(key) = key {
+                        index.insert(key, &[])?;
+                    }
+                    if let Some(name) = name {
+                        // Earlier timers in the batch are visible here, so duplicates within
+                        // the batch are caught too
+                        if names.insert(*name, id.as_bytes())?.is_some() && self.unique_names {
+                            return Err(ConflictableTransactionError::Abort(name.to_string()));
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e: TransactionError<String>| match e {
+                TransactionError::Storage(e) => Error::Db(e),
+                TransactionError::Abort(name) => Error::DuplicateName(name),
+            })?;
+        self.flush_if_durable()
+    }
+
+    /// Flush the database to disk if running in durable mode
+    fn flush_if_durable(&self) -> Result<(), Error> {
+        if self.durable {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The tree indexing timers by start time, keyed by the start time's seconds from midnight
+    /// followed by the timer's id
+    fn start_time_index(&self) -> Result<sled::Tree, Error> {
+        Ok(self.db.open_tree(START_TIME_INDEX)?)
+    }
+
+    /// The tree mapping each timer's name to its id
+    fn name_index(&self) -> Result<sled::Tree, Error> {
+        Ok(self.db.open_tree(NAME_INDEX)?)
+    }
+
+    /// The keys of stored timer records which can't be read, e.g. after a bad write, which
+    /// are otherwise left out of every listing
+    pub fn corrupt_keys(&self) -> Vec<String> {
+        self.db
+            .iter()
+            .filter_map(|r| r.ok())
+            .filter(|(_, val)| IntervalTimer::from_json_slice(val).is_err())
+            .map(|(key, _)| record_key_label(&key))
+            .collect()
+    }
+
+    /// Rewrite stored timers in the current layout, so that upgrades made when reading older
+    /// records stick. Returns how many were rewritten.
+    pub fn migrate_records(&self) -> Result<usize, Error> {
+        let mut migrated = 0;
+        for (key, val) in self.db.iter().filter_map(|r| r.ok()) {
+            let Some(timer) = read_timer(&key, &val) else {
+                continue;
+            };
+            let bytes = timer.to_json_vec()?;
+            if bytes[..] != val[..] {
+                // Leave the record alone if it changed since it was read
+                let _ = self.db.compare_and_swap(&key, Some(val), Some(bytes))?;
+                migrated += 1;
+            }
+        }
+        self.flush_if_durable()?;
+        Ok(migrated)
+    }
+
+    /// Rebuild the start time and name indexes from the stored timers, e.g. for databases
+    /// created before the indexes existed
+    pub fn reindex(&self) -> Result<(), Error> {
+        let (index, names) = (self.start_time_index()?, self.name_index()?);
+        index.clear()?;
+        names.clear()?;
+        for timer in self.get_all_interval_timers()? {
+            if let Some(key) = start_time_key(&timer) {
+                index.insert(key, &[])?;
+            }
+            if let Some(name) = name_key(&timer) {
+                names.insert(name, timer.get_id().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the timers whose start time is in `from..to`. If `to` is before `from` the range
+    /// wraps past midnight.
+    pub fn timers_starting_between(
+        &self,
+        from: NaiveTime,
+        to: NaiveTime,
+    ) -> Result<Vec<IntervalTimer>, Error> {
+        let index = self.start_time_index()?;
+        let (from, to) = (
+            from.num_seconds_from_midnight().to_be_bytes(),
+            to.num_seconds_from_midnight().to_be_bytes(),
+        );
+        let keys: Vec<_> = if from <= to {
+            index.range(from..to).collect()
+        } else {
+            index.range(from..).chain(index.range(..to)).collect()
+        };
+        let mut timers = Vec::new();
+        for entry in keys {
+            let (key, _) = entry?;
+            // Keys are the start time followed by the timer's id
+            let Ok(id) = Uuid::from_slice(&key[4..]) else {
+                warn!("Skipping malformed start time index key {:?}", key);
+                continue;
+            };
+            if let Some(timer) = self.get_interval_timer(id)? {
+                timers.push(timer);
+            }
+        }
+        Ok(timers)
+    }
+
+    pub fn get_interval_timer(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        match self.db.get(id.as_bytes())? {
+            Some(value) => {
+                let timer = IntervalTimer::from_json_slice(value.as_ref())?;
+                Ok(Some(timer))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Move a timer from the database to the trash, where it can be restored until it is
+    /// purged, returning the removed timer if it existed
+    pub fn delete_interval_timer(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        let id = id.as_bytes();
+        match self.db.get(id)? {
             Some(value) => {
                 let timer = IntervalTimer::from_json_slice(value.as_ref())?;
+                let trashed = serde_json::to_vec(&TrashedTimer {
+                    deleted_at: self.clock.now(),
+                    timer: &timer,
+                })?;
+                let trash = self.trash()?;
+                (&**self.db, &trash)
+                    .transaction(|(db, trash)| {
+                        db.remove(id)?;
+                        trash.insert(id, trashed.as_slice())?;
+                        Ok(())
+                    })
+                    .map_err(|e: TransactionError<()>| match e {
+                        TransactionError::Storage(e) => Error::Db(e),
+                        TransactionError::Abort(()) => Error::Unknown,
+                    })?;
+                if let Some(key) = start_time_key(&timer) {
+                    self.start_time_index()?.remove(key)?;
+                }
+                if let Some(name) = name_key(&timer) {
+                    let id = timer.get_id();
+                    let _ = self.name_index()?.compare_and_swap(
+                        name,
+                        Some(id.as_bytes()),
+                        None as Option<&[u8]>,
+                    )?;
+                }
+                self.flush_if_durable()?;
                 Ok(Some(timer))
             }
             _ => Ok(None),
         }
     }
 
+    /// The tree holding deleted timers until they are purged, keyed by id
+    fn trash(&self) -> Result<sled::Tree, Error> {
+        Ok(self.db.open_tree(TRASH)?)
+    }
+
+    /// Move a timer from the trash back into the database, returning it if it was in the
+    /// trash. Its schedule isn't spawned.
+    pub fn restore_interval_timer(&self, id: Uuid) -> Result<Option<IntervalTimer>, Error> {
+        let trash = self.trash()?;
+        let Some(value) = trash.get(id.as_bytes())? else {
+            return Ok(None);
+        };
+        let trashed: TrashedTimer<IntervalTimer> = serde_json::from_slice(&value)?;
+        self.insert_interval_timer(&trashed.timer)?;
+        trash.remove(id.as_bytes())?;
+        self.flush_if_durable()?;
+        Ok(Some(trashed.timer))
+    }
+
+    /// Permanently remove timers which have been in the trash for longer than the retention,
+    /// returning how many were removed
+    pub fn purge_trash(&self) -> Result<usize, Error> {
+        let trash = self.trash()?;
+        let cutoff = self.clock.now() - self.trash_retention;
+        let mut purged = 0;
+        for entry in trash.iter() {
+            let (key, value) = entry?;
+            let trashed: TrashedTimer<IntervalTimer> = match serde_json::from_slice(&value) {
+                Ok(trashed) => trashed,
+                Err(e) => {
+                    warn!(
+                        "Skipping unreadable trashed timer record {}: {}",
+                        record_key_label(&key),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if trashed.deleted_at <= cutoff {
+                trash.remove(key)?;
+                self.clear_history(trashed.timer.get_id())?;
+                purged += 1;
+            }
+        }
+        if purged > 0 {
+            info!("Purged {} timers from the trash.", purged);
+            self.flush_if_durable()?;
+        }
+        Ok(purged)
+    }
+
+    /// Spawn a task which purges old timers from the trash every `TRASH_PURGE_INTERVAL`
+    pub fn spawn_trash_purger(&self) -> JoinHandle<()> {
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = state.purge_trash() {
+                    error!("Failed to purge the trash: {}", e);
+                }
+                sleep(TRASH_PURGE_INTERVAL).await;
+            }
+        })
+    }
+
+    /// Whether the timers controlling `output` drive it low to turn it on. Outputs without
+    /// timers are taken to be active-high.
+    pub fn output_active_low(&self, output: u16) -> Result<bool, Error> {
+        Ok(self
+            .get_all_interval_timers()?
+            .iter()
+            .any(|timer| timer.output == output && timer.active_low))
+    }
+
+    /// Turn a timer's output on for `duration` right away, leaving its schedule as it is. If a
+    /// scheduled run overlaps, the output stays on until both are over and is only turned off
+    /// once.
+    pub fn run_now(&self, timer: &IntervalTimer, duration: std::time::Duration) -> JoinHandle<()> {
+        let msg = GpioOutMessage {
+            output: timer.output,
+            value: true,
+            active_low: timer.active_low,
+        };
+        self.hold_output(msg, duration)
+    }
+
+    /// Hold an output on for `duration` right away, alongside any scheduled run of it
+    fn hold_output(&self, msg: GpioOutMessage, duration: std::time::Duration) -> JoinHandle<()> {
+        // An output which is already on doesn't take up another slot
+        let already_on = self
+            .output_states
+            .lock()
+            .unwrap()
+            .get(&msg.output)
+            .is_some_and(|state| state.value);
+        let tx = self.gpio_tx.clone();
+        tokio::spawn(async move {
+            let slot = match already_on {
+                true => None,
+                false => Some(OutputSlot::acquire(&tx, msg).await),
+            };
+            let until = tokio::time::Instant::now() + duration;
+            if let Err(e) = tx.send(GpioMessage::Hold { msg, until }).await {
+                error!("{}", e);
+                return;
+            }
+            tokio::time::sleep_until(until).await;
+            let release = GpioMessage::Release {
+                output: msg.output,
+                until,
+            };
+            let _ = tx.send(release).await.map_err(|e| error!("{}", e));
+            drop(slot);
+        })
+    }
+
+    /// Bring outputs back to a safe state after a restart. Every output persisted by the GPIO
+    /// manager is turned off, except that with `resume` set, outputs whose run was cut short
+    /// are held on for the rest of it. Outputs of enabled normally-on timers are left for
+    /// their timers to turn back on when spawned. Returns how many runs were resumed.
+    pub async fn restore_outputs(&self, resume: bool) -> Result<usize, Error> {
+        let now = self.clock.now();
+        let resting_on: HashSet<u16> = self
+            .get_all_interval_timers()?
+            .iter()
+            .filter(|timer| timer.enabled && timer.normally_on)
+            .map(|timer| timer.output)
+            .collect();
+        let mut resumed = 0;
+        for entry in self.db.open_tree(OUTPUT_STATE)?.iter() {
+            let record: PersistedOutput = serde_json::from_slice(&entry?.1)?;
+            let msg = record.msg;
+            if resting_on.contains(&msg.output) {
+                continue;
+            }
+            let remaining = record
+                .off_at
+                .and_then(|off_at| (off_at - now).to_std().ok())
+                .filter(|remaining| !remaining.is_zero());
+            match remaining {
+                Some(remaining) if resume && msg.value => {
+                    info!(
+                        "Resuming the run of output {} for another {:?}",
+                        msg.output, remaining
+                    );
+                    self.hold_output(msg, remaining);
+                    resumed += 1;
+                }
+                _ => {
+                    if msg.value {
+                        warn!(
+                            "Turning off output {}, left on before restarting",
+                            msg.output
+                        );
+                    }
+                    let off = GpioOutMessage {
+                        value: false,
+                        ..msg
+                    };
+                    self.gpio_tx
+                        .send(off.into())
+                        .await
+                        .map_err(|e| Error::Anyhow(e.into()))?;
+                }
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Add one to the number of times the timer with the given id has fired and note when,
+    /// returning the new count, or `None` if the timer no longer exists. The record is updated
+    /// atomically, so concurrent runs and edits don't lose runs.
+    pub fn record_fire(&self, id: Uuid) -> Result<Option<u64>, Error> {
+        let now = self.clock.now().with_timezone(&Local);
+        let mut count = None;
+        self.db.update_and_fetch(id.as_bytes(), |old| {
+            let old = old?;
+            let Ok(mut timer) = IntervalTimer::from_json_slice(old) else {
+                return Some(old.to_vec());
+            };
+            timer.fire_count += 1;
+            timer.last_fired_at = Some(now);
+            match timer.to_json_vec() {
+                Ok(bytes) => {
+                    count = Some(timer.fire_count);
+                    Some(bytes)
+                }
+                Err(_) => Some(old.to_vec()),
+            }
+        })?;
+        self.flush_if_durable()?;
+        Ok(count)
+    }
+
+    /// A hook asking the skip check, if there is one, whether to skip a run of `timer`,
+    /// and noting skipped runs in its history
+    fn skip_check_for(&self, timer: &IntervalTimer) -> Option<SkipCheck> {
+        let webhook = self.skip_check.clone()?;
+        let id = timer.get_id();
+        let query = SkipQuery {
+            timer_id: id,
+            name: timer.name.clone(),
+            output: timer.output,
+        };
+        let label = timer_label(timer);
+        let state = self.clone();
+        Some(Arc::new(move || {
+            let (state, webhook, query) = (state.clone(), webhook.clone(), query.clone());
+            let label = label.clone();
+            Box::pin(async move {
+                let due = state.clock.now();
+                let skip = webhook.should_skip(query).await;
+                if skip {
+                    state.record_history(id, due, None, FireOutcome::Skipped);
+                    let what = "was skipped by the skip check";
+                    state.publish(SchedulerEventKind::Skipped, id, &label, what);
+                }
+                skip
+            })
+        }))
+    }
+
+    /// A hook recording each run of `timer` with `record_fire`, in its history and as an event
+    fn fire_counter(&self, timer: &IntervalTimer) -> OnFire {
+        let id = timer.get_id();
+        let label = timer_label(timer);
+        let state = self.clone();
+        Arc::new(move |duration| {
+            if let Err(e) = state.record_fire(id) {
+                warn!("Failed to count a run of timer {}: {}", id, e);
+            }
+            let on_at = state.clock.now();
+            state.record_history(id, on_at, Some(on_at + duration), FireOutcome::Fired);
+            let what = format!(
+                "turned on for {}",
+                format_duration(duration.to_std().unwrap_or_default())
+            );
+            state.publish(SchedulerEventKind::Fired, id, &label, &what);
+        })
+    }
+
+    /// Spawn the task running a timer's schedule and keep track of it, replacing any task
+    /// already running for the same timer. Timers without a start time are not scheduled.
+    pub fn spawn_interval_timer(&self, timer: &IntervalTimer) -> Option<JoinHandle<()>> {
+        // A normally-on timer's runs turn the output off, and it's turned back on after each
+        let msg = GpioOutMessage {
+            output: timer.output,
+            value: !timer.normally_on,
+            active_low: timer.active_low,
+        };
+        if !timer.enabled {
+            info!("Timer {} is disabled, not scheduling it.", timer.get_id());
+            self.cancel_timer(&timer.get_id());
+            return None;
+        }
+        if timer.normally_on {
+            let resting = GpioOutMessage { value: true, ..msg };
+            if let Err(e) = self.gpio_tx.try_send(GpioMessage::Rest(resting)) {
+                warn!(
+                    "Failed to turn on output {} of normally-on timer {}: {}",
+                    timer.output,
+                    timer.get_id(),
+                    e
+                );
+            }
+        }
+        let enabled = Arc::new(AtomicBool::new(true));
+        let hooks = RunHooks::default()
+            .with_enabled(enabled.clone())
+            .with_on_fire(self.fire_counter(timer))
+            .with_skip_check(self.skip_check_for(timer));
+        let tx = self.gpio_tx.clone();
+        let tz = timer.settings.tz;
+        let Ok(duration) = Duration::from_std(timer.settings.duration_on) else {
+            warn!(
+                "Timer {} has an out of range duration, not scheduling it.",
+                timer.get_id()
+            );
+            return None;
+        };
+        let handle = match &timer.settings.schedule {
+            Schedule::Windows(windows) => {
+                let offset =
+                    Duration::from_std(timer.start_offset(self.start_stagger)).unwrap_or_default();
+                let windows = match windows
+                    .iter()
+                    .map(|(time, duration)| Ok((*time + offset, Duration::from_std(*duration)?)))
+                    .collect::<Result<Vec<_>, chrono::OutOfRangeError>>()
+                {
+                    Ok(windows) => windows,
+                    Err(e) => {
+                        warn!(
+                            "Timer {} has an out of range window, not scheduling it: {}",
+                            timer.get_id(),
+                            e
+                        );
+                        return None;
+                    }
+                };
+                MultiWindowTimer::new(windows, msg, tx)
+                    .with_timezone(tz)
+                    .with_hooks(hooks)
+                    .with_clock(self.clock.clone())
+                    .run()
+            }
+            Schedule::Cron(expr) => {
+                let cron = match Cron::from_str(expr) {
+                    Ok(cron) => cron,
+                    Err(e) => {
+                        warn!(
+                            "Timer {} has an invalid cron expression, not scheduling it: {}",
+                            timer.get_id(),
+                            e
+                        );
+                        return None;
+                    }
+                };
+                CronTimer::new(cron, msg, duration, tx)
+                    .with_timezone(tz)
+                    .with_hooks(hooks)
+                    .with_clock(self.clock.clone())
+                    .run()
+            }
+            Schedule::Sun {
+                event,
+                latitude,
+                longitude,
+                offset_secs,
+            } => {
+                let offset = Duration::seconds(*offset_secs);
+                SunTimer::new(*event, *latitude, *longitude, offset, msg, duration, tx)
+                    .with_hooks(hooks)
+                    .with_clock(self.clock.clone())
+                    .run()
+            }
+            Schedule::Cycling => {
+                let Ok(off) = Duration::from_std(timer.settings.duration_off) else {
+                    warn!(
+                        "Timer {} has an out of range off duration, not scheduling it.",
+                        timer.get_id()
+                    );
+                    return None;
+                };
+                CyclingTimer::new(msg, duration, off, tx)
+                    .with_hooks(hooks)
+                    .run()
+            }
+            Schedule::Input {
+                pin,
+                edge,
+                debounce_ms,
+            } => {
+                let debounce = std::time::Duration::from_millis(*debounce_ms);
+                InputTimer::new(*pin, *edge, debounce, msg, duration, tx)
+                    .with_hooks(hooks)
+                    .run()
+            }
+            Schedule::Daily | Schedule::Weekly(_) => {
+                let Some(start_time) = timer.effective_start_time(self.start_stagger) else {
+                    warn!(
+                        "Timer {} has no start time set, not scheduling it.",
+                        timer.get_id()
+                    );
+                    return None;
+                };
+                let daily = match &timer.settings.schedule {
+                    Schedule::Weekly(days) => {
+                        DailyTimer::weekly(start_time, days.clone(), msg, duration, tx)
+                    }
+                    _ => DailyTimer::new(start_time, msg, duration, tx),
+                };
+                daily
+                    .with_timezone(tz)
+                    .with_hooks(hooks)
+                    .with_clock(self.clock.clone())
+                    .run()
+            }
+        };
+        self.track_timer(timer.get_id(), &handle, enabled);
+        Some(handle)
+    }
+
+    /// Spawn the tasks for every timer stored in the database, e.g. when the server starts
+    pub fn spawn_all_timers(&self) -> Result<Vec<JoinHandle<()>>, Error> {
+        let handles: Vec<_> = self
+            .get_all_interval_timers()?
+            .iter()
+            .filter_map(|timer| self.spawn_interval_timer(timer))
+            .collect();
+        info!("Spawned {} timers from the database.", handles.len());
+        Ok(handles)
+    }
+
+    /// The ids of timers whose tasks are running, forgetting any tasks which have finished,
+    /// e.g. one-off timers which have already run
+    pub fn running_timer_ids(&self) -> Vec<Uuid> {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, running| !running.cancel.is_finished());
+        let mut ids: Vec<_> = tasks.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Cancel every running timer task and spawn them again from the database, e.g. so that
+    /// they pick up a changed clock or timezone. Returns how many were spawned.
+    pub fn reschedule_all(&self) -> Result<usize, Error> {
+        for (_, running) in self.tasks.lock().unwrap().drain() {
+            running.cancel.cancel();
+        }
+        Ok(self.spawn_all_timers()?.len())
+    }
+
+    /// Keep track of the task running a timer so that it can be cancelled later. Any task
+    /// previously tracked for the same id is aborted.
+    pub fn track_timer(&self, id: Uuid, handle: &JoinHandle<()>, enabled: Arc<AtomicBool>) {
+        let running = RunningTimer {
+            cancel: CancelHandle::new(handle),
+            enabled,
+        };
+        if let Some(prev) = self.tasks.lock().unwrap().insert(id, running) {
+            prev.cancel.cancel();
+        }
+    }
+
+    /// Enable or disable the timer with the given id without deleting it. A running task just
+    /// skips its runs while disabled, and one is started if the timer is enabled without one.
+    pub fn set_timer_enabled(&self, id: Uuid, enabled: bool) -> Result<IntervalTimer, Error> {
+        let mut timer = self
+            .get_interval_timer(id)?
+            .ok_or_else(|| Error::NotFound(format!("Timer with ID {}", &id)))?;
+        timer.enabled = enabled;
+        timer.updated_at = Local::now();
+        self.insert_interval_timer(&timer)?;
+        let running = self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|running| running.enabled.store(enabled, Ordering::SeqCst))
+            .is_some();
+        if enabled && !running {
+            self.spawn_interval_timer(&timer);
+        }
+        info!(
+            "{} timer {}",
+            if enabled { "Enabled" } else { "Disabled" },
+            id
+        );
+        Ok(timer)
+    }
+
+    /// Abort the running task of the timer with the given id, returning whether one was running
+    pub fn cancel_timer(&self, id: &Uuid) -> bool {
+        match self.tasks.lock().unwrap().remove(id) {
+            Some(running) => {
+                running.cancel.cancel();
+                info!("Cancelled running task for timer {}", id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every stored timer. Records which can't be read are logged and skipped, so that one
+    /// corrupt record doesn't stop the rest from running.
     pub fn get_all_interval_timers(&self) -> Result<Vec<IntervalTimer>, Error> {
-        let result: Result<Vec<_>, _> = self
+        Ok(self
             .db
             .iter()
             .filter_map(|r| r.ok())
-            .collect::<Vec<_>>()
+            .filter_map(|(key, val)| read_timer(&key, &val))
+            .collect())
+    }
+
+    /// The number of timers in the database
+    pub fn count_interval_timers(&self) -> usize {
+        self.db.len()
+    }
+
+    /// Get up to `limit` timers, skipping the first `offset`, without deserializing the rest
+    pub fn get_interval_timers(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<IntervalTimer>, Error> {
+        Ok(self
+            .db
             .iter()
-            .map(|x| {
-                let val = &x.1;
-                IntervalTimer::from_json_slice(&val)
-            })
+            .filter_map(|r| r.ok())
+            .skip(offset)
+            .take(limit)
+            .filter_map(|(key, val)| read_timer(&key, &val))
+            .collect())
+    }
+
+    /// Get up to `limit` of the timers matching `filter`, skipping the first `offset`, along
+    /// with how many match in total
+    pub fn find_interval_timers(
+        &self,
+        filter: &TimerFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<IntervalTimer>, usize), Error> {
+        if filter.is_empty() {
+            let timers = self.get_interval_timers(offset, limit)?;
+            return Ok((timers, self.count_interval_timers()));
+        }
+        let matching: Vec<_> = self
+            .get_all_interval_timers()?
+            .into_iter()
+            .filter(|timer| filter.matches(timer))
             .collect();
-        result
+        let total = matching.len();
+        Ok((
+            matching.into_iter().skip(offset).take(limit).collect(),
+            total,
+        ))
     }
 }
 
@@ -311,15 +3228,13 @@ markup::define! {
         html {
             head {
                 @head
+                link[rel = "stylesheet", href = "/static/css/normalize.css"];
+                link[rel = "stylesheet", href = "/static/css/skeleton.css"];
                 style {
                     "nav{ background: #FFAAAA text-align: center }"
                     "body { background: #ECFFE6 }"
                     "columns { border-style: solid }"
                     "column { border-style: solid }"
-
-
-                    @markup::raw(include_str!("../static/css/normalize.css"))
-                    @markup::raw(include_str!("../static/css/skeleton.css"))
                     @markup::raw(
                         r#"
                         <link href="fonts.googleapis.com/css?family=Raleway:400,300,600" rel="stylesheet" type="text/css">
@@ -384,3 +3299,1390 @@ pub mod skeleton {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: u16 = 5;
+
+    /// A manager writing to simulated pins, forcing outputs off after a second on
+    fn capped_manager() -> (MockBackend, mpsc::Sender<GpioMessage>) {
+        let backend = MockBackend::new();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        man.with_max_on_duration(Some(std::time::Duration::from_secs(1)))
+            .run()
+            .unwrap();
+        (backend, tx)
+    }
+
+    async fn flush(tx: &mpsc::Sender<GpioMessage>) {
+        let (resp, rx) = oneshot::channel();
+        tx.send(GpioMessage::Flush(resp)).await.unwrap();
+        rx.await.unwrap();
+    }
+
+    fn on() -> GpioOutMessage {
+        GpioOutMessage {
+            output: PIN,
+            value: true,
+            active_low: false,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_stops_a_task_waiting_on_a_time_future() {
+        let task = tokio::spawn(TimeFuture::at(Utc::now() + Duration::hours(1)));
+        let cancel = CancelHandle::new(&task);
+        assert!(!cancel.is_finished());
+        cancel.cancel();
+        assert!(task.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_a_time_future_resolves_it_early_and_ends_its_sleep() {
+        let in_an_hour = (Local::now() + Duration::hours(1)).time();
+        let (mut future, cancel) = TimeFuture::new_cancellable(in_an_hour);
+        let sleeping = future.task.abort_handle();
+        let minute = std::time::Duration::from_secs(60);
+        assert!(tokio::time::timeout(minute, &mut future).await.is_err());
+        assert!(!sleeping.is_finished());
+
+        cancel.cancel();
+        let begun = tokio::time::Instant::now();
+        (&mut future).await;
+        assert_eq!(begun.elapsed(), std::time::Duration::ZERO);
+        assert!(future.is_cancelled());
+        // The task sleeping until then has ended, though the future is still around
+        tokio::task::yield_now().await;
+        assert!(sleeping.is_finished());
+
+        // Dropping the handle cancels too, and a future left to run out isn't cancelled
+        let (future, cancel) = TimeFuture::new_cancellable(in_an_hour);
+        drop(cancel);
+        future.await;
+        let soon = (Local::now() + Duration::minutes(1)).time();
+        let (mut future, _cancel) = TimeFuture::new_cancellable(soon);
+        (&mut future).await;
+        assert!(!future.is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_the_cancel_handle_stops_the_task() {
+        let task = tokio::spawn(TimeFuture::at(Utc::now() + Duration::hours(1)));
+        drop(CancelHandle::new(&task));
+        assert!(task.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_on_duration_forces_a_run_off() {
+        let (backend, tx) = capped_manager();
+        tx.send(on().into()).await.unwrap();
+        sleep(std::time::Duration::from_secs(5)).await;
+        flush(&tx).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, true), (PIN, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_on_duration_leaves_a_normally_on_output_resting_on() {
+        let (backend, tx) = capped_manager();
+        // The window of a normally-on timer turns it off, then it goes back to resting on
+        let off = GpioOutMessage {
+            value: false,
+            ..on()
+        };
+        tx.send(GpioMessage::Rest(on())).await.unwrap();
+        tx.send(off.into()).await.unwrap();
+        tx.send(GpioMessage::Rest(on())).await.unwrap();
+        sleep(std::time::Duration::from_secs(5)).await;
+        flush(&tx).await;
+        assert_eq!(
+            *backend.writes.lock().unwrap(),
+            [(PIN, true), (PIN, false), (PIN, true)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn multi_window_timer_waits_by_its_clock() {
+        let (tx, mut rx) = mpsc::channel(8);
+        // A minute before the first window, whatever the system's time is
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 59, 0).unwrap());
+        let at = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        let windows = vec![
+            (at(12), Duration::minutes(5)),
+            (at(6), Duration::minutes(10)),
+        ];
+        let _task = MultiWindowTimer::new(windows, on(), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_clock(Arc::new(clock))
+            .run();
+
+        let begun = tokio::time::Instant::now();
+        assert!(matches!(rx.recv().await, Some(GpioMessage::Slot { .. })));
+        assert_eq!(begun.elapsed().as_secs(), 60);
+        match rx.recv().await {
+            Some(GpioMessage::OnFor { msg, duration, .. }) => {
+                assert_eq!((msg.output, msg.value), (PIN, true));
+                assert_eq!(duration, Duration::minutes(10));
+            }
+            other => panic!("expected the 06:00 window to start, got {:?}", other),
+        }
+        match rx.recv().await {
+            Some(GpioMessage::Rest(msg)) => assert!(!msg.value),
+            other => panic!("expected the 06:00 window to end, got {:?}", other),
+        }
+        assert_eq!(begun.elapsed().as_secs(), 11 * 60);
+    }
+
+    /// A clock stopped at the given time
+    #[derive(Debug)]
+    struct Stopped(DateTime<Utc>);
+
+    impl Clock for Stopped {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    fn hm(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn cron_timers_start_at_the_next_matching_time() {
+        use std::str::FromStr;
+
+        let next = |expr: &str, tz: Tz, now: DateTime<Utc>| {
+            let cron = Cron::from_str(expr).unwrap();
+            CronTimer::next_occurrence(&cron, Some(tz), &Stopped(now)).unwrap()
+        };
+        // 2024-05-03 is a Friday
+        let may = |d: u32, h: u32, m: u32| Utc.with_ymd_and_hms(2024, 5, d, h, m, 0).unwrap();
+        let utc = chrono_tz::UTC;
+
+        // Weekday ranges skip the weekend, and a time which has just passed is a day away
+        assert_eq!(next("0 6 * * 1-5", utc, may(3, 5, 59)), may(3, 6, 0));
+        assert_eq!(next("0 6 * * 1-5", utc, may(3, 6, 0)), may(6, 6, 0));
+        assert_eq!(next("0 6 * * 6,0", utc, may(3, 6, 0)), may(4, 6, 0));
+        // Step values
+        assert_eq!(next("*/15 * * * *", utc, may(3, 6, 1)), may(3, 6, 15));
+        assert_eq!(next("*/15 * * * *", utc, may(3, 6, 45)), may(3, 7, 0));
+        assert_eq!(next("0 */8 * * *", utc, may(3, 9, 0)), may(3, 16, 0));
+        // Rolling over past midnight, into the next week too
+        assert_eq!(next("*/15 * * * *", utc, may(3, 23, 50)), may(4, 0, 0));
+        assert_eq!(next("30 23 * * *", utc, may(3, 23, 30)), may(4, 23, 30));
+        assert_eq!(next("0 0 * * 1-5", utc, may(3, 23, 50)), may(6, 0, 0));
+        // Evaluated in the timer's timezone, where 06:00 is 04:00 UTC in summer
+        let berlin = chrono_tz::Europe::Berlin;
+        assert_eq!(next("0 6 * * *", berlin, may(3, 3, 0)), may(3, 4, 0));
+        assert_eq!(next("0 6 * * *", berlin, may(3, 4, 0)), may(4, 4, 0));
+    }
+
+    #[test]
+    fn time_until_a_target_later_today() {
+        let clock = Stopped(Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap());
+        let until = time_until_with(hm(9, 30), Some(chrono_tz::UTC), &clock);
+        assert_eq!(until, Duration::minutes(90));
+    }
+
+    #[test]
+    fn time_until_a_passed_target_rolls_to_tomorrow() {
+        let clock = Stopped(Utc.with_ymd_and_hms(2024, 6, 1, 8, 0, 0).unwrap());
+        let until = time_until_with(hm(7, 0), Some(chrono_tz::UTC), &clock);
+        assert_eq!(until, Duration::hours(23));
+        // A timer which has just fired waits a full day rather than firing again
+        let until = time_until_with(hm(8, 0), Some(chrono_tz::UTC), &clock);
+        assert_eq!(until, Duration::hours(24));
+    }
+
+    #[test]
+    fn time_until_across_daylight_saving_changes() {
+        let berlin = Some(chrono_tz::Europe::Berlin);
+        // 22:00 the evening before the clocks go forward an hour at 02:00
+        let clock = Stopped(Utc.with_ymd_and_hms(2024, 3, 30, 21, 0, 0).unwrap());
+        assert_eq!(
+            time_until_with(hm(6, 0), berlin, &clock),
+            Duration::hours(7)
+        );
+        // 02:30 doesn't happen that night, so it's taken as an hour later
+        let until = time_until_with(hm(2, 30), berlin, &clock);
+        assert_eq!(until, Duration::minutes(4 * 60 + 30));
+        // 22:00 the evening before the clocks go back an hour at 03:00
+        let clock = Stopped(Utc.with_ymd_and_hms(2024, 10, 26, 20, 0, 0).unwrap());
+        assert_eq!(
+            time_until_with(hm(6, 0), berlin, &clock),
+            Duration::hours(9)
+        );
+    }
+
+    /// Simulated pins which remember each pin they open
+    #[derive(Debug, Default, Clone)]
+    struct CountingBackend {
+        mock: MockBackend,
+        opened: Arc<Mutex<Vec<(&'static str, u16)>>>,
+    }
+
+    impl GpioBackend for CountingBackend {
+        fn open_output(&mut self, pin: u16) -> Result<Box<dyn OutputPin>, Error> {
+            self.opened.lock().unwrap().push(("out", pin));
+            self.mock.open_output(pin)
+        }
+
+        fn open_input(&mut self, pin: u16) -> Result<Box<dyn InputPin>, Error> {
+            self.opened.lock().unwrap().push(("in", pin));
+            self.mock.open_input(pin)
+        }
+    }
+
+    #[tokio::test]
+    async fn pins_are_opened_once_and_kept_open() {
+        let backend = CountingBackend::default();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        man.run().unwrap();
+        let other = GpioOutMessage { output: 6, ..on() };
+        for msg in [on(), other, on(), on()] {
+            tx.send(msg.into()).await.unwrap();
+        }
+        for _ in 0..2 {
+            let (resp, rx) = oneshot::channel();
+            tx.send(GpioMessage::ReadIn { pin: 7, resp }).await.unwrap();
+            rx.await.unwrap();
+        }
+        flush(&tx).await;
+        assert_eq!(
+            *backend.opened.lock().unwrap(),
+            [("out", PIN), ("out", 6), ("in", 7)]
+        );
+        assert_eq!(backend.mock.writes.lock().unwrap().len(), 4);
+    }
+
+    /// An app state on a temporary database whose GPIO messages go to a simulated manager
+    fn simulated() -> (AppState, MockBackend) {
+        let backend = MockBackend::new();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        let states = man.output_states();
+        man.run().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tx).with_output_states(states);
+        (state, backend)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_active_low_output_is_driven_low_to_turn_on() {
+        let (state, backend) = simulated();
+        let mut timer = IntervalTimer::new(None, None, crate::IntervalSettings::default());
+        timer.output = PIN;
+        timer.active_low = true;
+        state.insert_interval_timer(&timer).unwrap();
+        assert!(state.output_active_low(PIN).unwrap());
+        assert!(!state.output_active_low(PIN + 1).unwrap());
+
+        state
+            .run_now(&timer, std::time::Duration::from_secs(30))
+            .await
+            .unwrap();
+        flush(&state.gpio_tx).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, false), (PIN, true)]);
+        let output = state.output_states.lock().unwrap()[&PIN];
+        assert!(!output.value && output.pin_value());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_disabled_daily_timer_skips_its_runs() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 0, 0).unwrap());
+        let enabled = Arc::new(AtomicBool::new(false));
+        let six = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let _task = DailyTimer::new(six, on(), Duration::minutes(1), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_hooks(RunHooks::default().with_enabled(enabled.clone()))
+            .with_clock(Arc::new(clock))
+            .run();
+
+        sleep(std::time::Duration::from_secs(2 * 60 * 60)).await;
+        assert!(rx.try_recv().is_err());
+        enabled.store(true, Ordering::SeqCst);
+        let begun = tokio::time::Instant::now();
+        assert!(matches!(rx.recv().await, Some(GpioMessage::Slot { .. })));
+        // The next run is at 06:00 the following day
+        assert_eq!(begun.elapsed().as_secs(), 23 * 60 * 60);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_time_future_resolves_when_its_time_comes() {
+        let begun = tokio::time::Instant::now();
+        TimeFuture::at(Utc::now() + Duration::minutes(5)).await;
+        assert_eq!(begun.elapsed().as_secs(), 5 * 60);
+        // A time which has passed resolves right away
+        TimeFuture::at(Utc::now() - Duration::minutes(5)).await;
+        assert_eq!(begun.elapsed().as_secs(), 5 * 60);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_a_time_future_stops_its_sleeping_task() {
+        let future = TimeFuture::at(Utc::now() + Duration::hours(1));
+        let shared = Arc::downgrade(&future.shared_state);
+        drop(future);
+        for _ in 0..3 {
+            tokio::task::yield_now().await;
+        }
+        // Only the task was left holding the shared state
+        assert!(shared.upgrade().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutting_down_stops_timers_and_turns_outputs_off() {
+        let (state, backend) = simulated();
+        let six = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let mut timer =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(60), six).unwrap();
+        timer.output = PIN;
+        state.insert_interval_timer(&timer).unwrap();
+        state.spawn_interval_timer(&timer);
+        // A manual run holds the output on for an hour
+        state.run_now(&timer, std::time::Duration::from_secs(60 * 60));
+        sleep(std::time::Duration::from_secs(1)).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, true)]);
+
+        state.shutdown().await.unwrap();
+        assert!(state.running_timer_ids().is_empty());
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, true), (PIN, false)]);
+        assert!(state.active_outputs().is_empty());
+    }
+
+    async fn error_json(e: Error) -> (StatusCode, serde_json::Value) {
+        let response = e.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_respond_with_their_code_and_message_as_json() {
+        let (status, body) = error_json(Error::InvalidDuration).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "InvalidDuration");
+        assert_eq!(body["message"], "Duration cannot be zero");
+        // Only validation errors list fields
+        assert!(body.get("fields").is_none());
+
+        let (status, body) = error_json(Error::NotFound("Timer with ID 1".to_owned())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "NotFound");
+
+        let fields = vec!["name must not be empty".to_owned()];
+        let (status, body) = error_json(Error::Validation(fields)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["fields"],
+            serde_json::json!(["name must not be empty"])
+        );
+
+        let (status, body) = error_json(Error::Unknown).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"], "Unknown");
+    }
+
+    #[test]
+    fn timers_starting_between_follows_inserts_updates_and_deletes() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0);
+        let minute = std::time::Duration::from_secs(60);
+        let add = |h, m| {
+            let timer = IntervalTimer::once_daily(None, None, minute, hm(h, m)).unwrap();
+            state.insert_interval_timer(&timer).unwrap();
+            timer
+        };
+        let ids = |from, to| -> Vec<Uuid> {
+            let timers = state.timers_starting_between(from, to).unwrap();
+            timers.iter().map(IntervalTimer::get_id).collect()
+        };
+        let (early, mut morning, late, after_midnight) =
+            (add(5, 0), add(6, 30), add(23, 30), add(0, 30));
+
+        assert_eq!(ids(hm(6, 0), hm(7, 0)), [morning.get_id()]);
+        // The end of the window is excluded
+        assert_eq!(ids(hm(5, 0), hm(6, 30)), [early.get_id()]);
+        // A window ending before it starts wraps past midnight
+        assert_eq!(
+            ids(hm(23, 0), hm(1, 0)),
+            [late.get_id(), after_midnight.get_id()]
+        );
+
+        morning.settings = crate::IntervalSettings::once_daily(minute, hm(8, 0)).unwrap();
+        state.replace_interval_timer(&morning).unwrap();
+        assert!(ids(hm(6, 0), hm(7, 0)).is_empty());
+        assert_eq!(ids(hm(7, 0), hm(9, 0)), [morning.get_id()]);
+
+        state.delete_interval_timer(early.get_id()).unwrap();
+        assert!(ids(hm(4, 0), hm(6, 0)).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_one_shot_timer_stops_after_turning_its_output_back_off() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 59, 0).unwrap());
+        let task = DailyTimer::once_at(hm(6, 0), on(), Duration::minutes(2), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_clock(Arc::new(clock))
+            .run();
+
+        let mut received = Vec::new();
+        while let Some(message) = rx.recv().await {
+            received.push(match message {
+                GpioMessage::Slot { resp, .. } => {
+                    let _ = resp.send(None);
+                    "slot"
+                }
+                GpioMessage::OnFor { msg, .. } if msg.value => "on",
+                GpioMessage::Rest(msg) if !msg.value => "off",
+                other => panic!("unexpected {:?}", other),
+            });
+        }
+        // The sender is only dropped once the task has ended
+        task.await.unwrap();
+        assert_eq!(received, ["slot", "on", "off"]);
+    }
+
+    #[tokio::test]
+    async fn a_simulated_manager_tracks_writes_without_sysfs() {
+        let (man, tx) = GpioManager::new(true, 8).unwrap();
+        let states = man.output_states();
+        man.run().unwrap();
+        let pin = 4093;
+        tx.send(GpioMessage::Out(GpioOutMessage {
+            output: pin,
+            ..on()
+        }))
+        .await
+        .unwrap();
+        flush(&tx).await;
+        assert!(states.lock().unwrap()[&pin].value);
+        let exported = format!("/sys/class/gpio/gpio{}", pin);
+        assert!(!std::path::Path::new(&exported).exists());
+    }
+
+    #[tokio::test]
+    async fn the_manager_writes_and_reads_through_its_backend() {
+        let backend = MockBackend::new();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        man.run().unwrap();
+        let read = || async {
+            let (resp, rx) = oneshot::channel();
+            tx.send(GpioMessage::ReadIn { pin: PIN, resp })
+                .await
+                .unwrap();
+            rx.await.unwrap()
+        };
+
+        tx.send(on().into()).await.unwrap();
+        assert!(read().await);
+        let off = GpioOutMessage {
+            value: false,
+            ..on()
+        };
+        tx.send(off.into()).await.unwrap();
+        assert!(!read().await);
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, true), (PIN, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_cycling_timer_alternates_on_and_off() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let _task = CyclingTimer::new(on(), Duration::minutes(5), Duration::minutes(25), tx).run();
+        let begun = tokio::time::Instant::now();
+
+        let mut changes = Vec::new();
+        while changes.len() < 5 {
+            let value = match rx.recv().await.unwrap() {
+                GpioMessage::Slot { resp, .. } => {
+                    let _ = resp.send(None);
+                    continue;
+                }
+                GpioMessage::OnFor { msg, .. } | GpioMessage::Rest(msg) => msg.value,
+                other => panic!("unexpected {:?}", other),
+            };
+            changes.push((begun.elapsed().as_secs() / 60, value));
+        }
+        assert_eq!(
+            changes,
+            [(0, true), (5, false), (30, true), (35, false), (60, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_outside_the_allowed_pins_are_refused_and_counted() {
+        let backend = MockBackend::new();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        let man = man.with_allowed_pins(2..=6);
+        let metrics = man.metrics();
+        man.run().unwrap();
+        for output in [1, PIN, 7] {
+            tx.send(GpioOutMessage { output, ..on() }.into())
+                .await
+                .unwrap();
+        }
+        flush(&tx).await;
+        assert_eq!(*backend.writes.lock().unwrap(), [(PIN, true)]);
+        assert_eq!(metrics.gpio_writes_rejected.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn timers_may_only_control_the_allowed_pins() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0).with_allowed_pins(2..=6);
+        assert!(state.check_pin(2).is_ok());
+        assert!(state.check_pin(6).is_ok());
+        for pin in [0, 1, 7] {
+            assert!(matches!(state.check_pin(pin), Err(Error::InvalidPin(p)) if p == pin));
+        }
+    }
+
+    #[test]
+    fn durable_writes_survive_reopening_the_database() {
+        let path = std::env::temp_dir().join(format!("sploosh-durable-{}", Uuid::new_v4()));
+        let open = || {
+            // sled's flusher thread lets go of the previous handle's file lock shortly after
+            // it is dropped
+            let db = (0..100)
+                .find_map(|_| {
+                    let db = sled::open(&path).ok();
+                    if db.is_none() {
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                    db
+                })
+                .unwrap();
+            AppState::new(Arc::new(db), mpsc::channel(1).0).with_durable(true)
+        };
+        let kept =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(60), hm(6, 0))
+                .unwrap();
+        let deleted =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(60), hm(7, 0))
+                .unwrap();
+
+        let state = open();
+        state.insert_interval_timer(&kept).unwrap();
+        state.insert_interval_timer(&deleted).unwrap();
+        state.delete_interval_timer(deleted.get_id()).unwrap();
+        drop(state);
+
+        let state = open();
+        let ids: Vec<_> = state
+            .get_all_interval_timers()
+            .unwrap()
+            .iter()
+            .map(IntervalTimer::get_id)
+            .collect();
+        drop(state);
+        let _ = std::fs::remove_dir_all(&path);
+        assert_eq!(ids, [kept.get_id()]);
+    }
+
+    #[test]
+    fn unique_names_refuse_only_another_timers_name() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0).with_unique_names(true);
+        let named = |name: &str| {
+            let minute = std::time::Duration::from_secs(60);
+            IntervalTimer::once_daily(Some(name.to_owned()), None, minute, hm(6, 0)).unwrap()
+        };
+        let mut lawn = named("Front Lawn");
+        state.insert_interval_timer(&lawn).unwrap();
+
+        let err = state
+            .insert_interval_timer(&named(" Front Lawn "))
+            .unwrap_err();
+        assert!(matches!(&err, Error::DuplicateName(name) if name == "Front Lawn"));
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 1);
+
+        // Saving a timer under its own name is fine, and renaming it frees the old name
+        lawn.description = Some("by the road".to_owned());
+        state.insert_interval_timer(&lawn).unwrap();
+        lawn.name = Some("Back Lawn".to_owned());
+        state.insert_interval_timer(&lawn).unwrap();
+        state.insert_interval_timer(&named("Front Lawn")).unwrap();
+        assert!(state.insert_interval_timer(&named("Back Lawn")).is_err());
+
+        // Deleting a timer frees its name too
+        state.delete_interval_timer(lawn.get_id()).unwrap();
+        state.insert_interval_timer(&named("Back Lawn")).unwrap();
+    }
+
+    #[test]
+    fn only_one_of_several_timers_created_at_once_gets_a_unique_name() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0).with_unique_names(true);
+        let minute = std::time::Duration::from_secs(60);
+        for round in 0..50 {
+            let name = format!("Beds {}", round);
+            let barrier = std::sync::Barrier::new(8);
+            let created = std::thread::scope(|scope| {
+                let threads: Vec<_> = (0..8)
+                    .map(|_| {
+                        let timer =
+                            IntervalTimer::once_daily(Some(name.clone()), None, minute, hm(6, 0))
+                                .unwrap();
+                        let (state, barrier) = (&state, &barrier);
+                        scope.spawn(move || {
+                            barrier.wait();
+                            state.insert_interval_timer(&timer).is_ok()
+                        })
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .filter(|&created| created)
+                    .count()
+            });
+            assert_eq!(created, 1, "{}", name);
+        }
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 50);
+    }
+
+    #[test]
+    fn names_may_repeat_without_unique_names() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0);
+        for _ in 0..2 {
+            let minute = std::time::Duration::from_secs(60);
+            let timer =
+                IntervalTimer::once_daily(Some("Beds".to_owned()), None, minute, hm(6, 0)).unwrap();
+            state.insert_interval_timer(&timer).unwrap();
+        }
+        assert_eq!(state.get_all_interval_timers().unwrap().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_trash_is_purged_once_the_retention_has_passed() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap());
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0)
+            .with_clock(Arc::new(clock))
+            .with_trash_retention(Duration::hours(2));
+        let minute = std::time::Duration::from_secs(60);
+        let (kept, purged) = (
+            IntervalTimer::once_daily(None, None, minute, hm(6, 0)).unwrap(),
+            IntervalTimer::once_daily(None, None, minute, hm(7, 0)).unwrap(),
+        );
+        for timer in [&kept, &purged] {
+            state.insert_interval_timer(timer).unwrap();
+            state.delete_interval_timer(timer.get_id()).unwrap();
+        }
+        let _purger = state.spawn_trash_purger();
+
+        sleep(std::time::Duration::from_secs(90 * 60)).await;
+        assert!(state
+            .restore_interval_timer(kept.get_id())
+            .unwrap()
+            .is_some());
+        // The purger runs hourly, so the other is gone by three hours after it was deleted
+        sleep(std::time::Duration::from_secs(90 * 60)).await;
+        assert!(state
+            .restore_interval_timer(purged.get_id())
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            state.get_all_interval_timers().unwrap()[0].get_id(),
+            kept.get_id()
+        );
+    }
+
+    #[test]
+    fn an_unreadable_record_in_the_trash_does_not_stop_the_purge() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state =
+            AppState::new(Arc::new(db), mpsc::channel(1).0).with_trash_retention(Duration::zero());
+        let timer =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(60), hm(6, 0))
+                .unwrap();
+        state.insert_interval_timer(&timer).unwrap();
+        state.delete_interval_timer(timer.get_id()).unwrap();
+        // Sorts before the timer's record, so the purge meets it first
+        state
+            .trash()
+            .unwrap()
+            .insert([0u8], b"not json".to_vec())
+            .unwrap();
+
+        assert_eq!(state.purge_trash().unwrap(), 1);
+        assert!(state
+            .restore_interval_timer(timer.get_id())
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overlapping_runs_take_turns_when_only_one_output_may_be_on() {
+        let backend = MockBackend::new();
+        let (man, tx) = GpioManager::with_backend(Box::new(backend.clone()), 8).unwrap();
+        man.with_max_concurrent_outputs(Some(1)).run().unwrap();
+        let begun = tokio::time::Instant::now();
+        let run = |output| {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                run_output(tx, output, true, false, Duration::minutes(10))
+                    .await
+                    .unwrap();
+                begun.elapsed().as_secs() / 60
+            })
+        };
+
+        let (first, second) = (run(3), run(4));
+        assert_eq!(first.await.unwrap(), 10);
+        // The second waits for the first and still runs for its full duration
+        assert_eq!(second.await.unwrap(), 20);
+        flush(&tx).await;
+        assert_eq!(
+            *backend.writes.lock().unwrap(),
+            [(3, true), (3, false), (4, true), (4, false)]
+        );
+    }
+
+    /// Fails to open `PIN` until it has been asked `failures` times
+    #[derive(Debug)]
+    struct FlakyBackend {
+        mock: MockBackend,
+        failures: u32,
+    }
+
+    impl GpioBackend for FlakyBackend {
+        fn open_output(&mut self, pin: u16) -> Result<Box<dyn OutputPin>, Error> {
+            if pin == PIN && self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::Anyhow(anyhow::anyhow!("device busy")));
+            }
+            self.mock.open_output(pin)
+        }
+
+        fn open_input(&mut self, pin: u16) -> Result<Box<dyn InputPin>, Error> {
+            self.mock.open_input(pin)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_writes_are_retried_with_backoff() {
+        let write_after = |failures| async move {
+            let mock = MockBackend::new();
+            let backend = FlakyBackend {
+                mock: mock.clone(),
+                failures,
+            };
+            let (man, tx) = GpioManager::with_backend(Box::new(backend), 8).unwrap();
+            let man = man.with_write_retries(3, std::time::Duration::from_millis(100));
+            let metrics = man.metrics();
+            let mut events = man.output_events().subscribe();
+            man.run().unwrap();
+            let begun = tokio::time::Instant::now();
+            tx.send(on().into()).await.unwrap();
+            events.recv().await.unwrap();
+            let failed = metrics.gpio_write_failures.load(Ordering::Relaxed);
+            let writes = mock.writes.lock().unwrap().clone();
+            (writes, failed, begun.elapsed().as_millis())
+        };
+
+        // Waiting 100ms then 200ms before the third attempt lands
+        assert_eq!(write_after(2).await, (vec![(PIN, true)], 0, 300));
+        // Four failed attempts give up and count as one failed write
+        assert_eq!(write_after(4).await, (vec![], 1, 700));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retrying_a_write_does_not_hold_up_other_outputs() {
+        let mock = MockBackend::new();
+        let backend = FlakyBackend {
+            mock: mock.clone(),
+            failures: 3,
+        };
+        let (man, tx) = GpioManager::with_backend(Box::new(backend), 8).unwrap();
+        let man = man.with_write_retries(3, std::time::Duration::from_millis(100));
+        let mut events = man.output_events().subscribe();
+        man.run().unwrap();
+        let begun = tokio::time::Instant::now();
+        let other = GpioOutMessage { output: 6, ..on() };
+        for msg in [on(), other] {
+            tx.send(msg.into()).await.unwrap();
+        }
+
+        // The other output is written straight away while PIN waits to be retried
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.output, event.ok), (6, true));
+        assert_eq!(begun.elapsed().as_millis(), 0);
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.output, event.ok), (PIN, true));
+        assert_eq!(begun.elapsed().as_millis(), 700);
+        assert_eq!(*mock.writes.lock().unwrap(), [(6, true), (PIN, true)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_newer_write_drops_the_retry_of_an_older_one() {
+        let mock = MockBackend::new();
+        let backend = FlakyBackend {
+            mock: mock.clone(),
+            failures: 1,
+        };
+        let (man, tx) = GpioManager::with_backend(Box::new(backend), 8).unwrap();
+        let man = man.with_write_retries(3, std::time::Duration::from_millis(100));
+        man.run().unwrap();
+        tx.send(on().into()).await.unwrap();
+        let off = GpioOutMessage {
+            value: false,
+            ..on()
+        };
+        tx.send(off.into()).await.unwrap();
+        sleep(std::time::Duration::from_secs(1)).await;
+        flush(&tx).await;
+        assert_eq!(*mock.writes.lock().unwrap(), [(PIN, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_manual_run_during_a_scheduled_one_turns_the_output_off_once() {
+        let (state, backend) = simulated();
+        let mut timer =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(600), hm(6, 0))
+                .unwrap();
+        timer.output = PIN;
+        let minutes = |m: u64| std::time::Duration::from_secs(m * 60);
+        let writes = || backend.writes.lock().unwrap().clone();
+
+        // A ten minute scheduled run, extended by a manual one from minute 2 to 17
+        let scheduled = tokio::spawn(run_output(
+            state.gpio_tx.clone(),
+            PIN,
+            true,
+            false,
+            Duration::minutes(10),
+        ));
+        sleep(minutes(2)).await;
+        let manual = state.run_now(&timer, minutes(15));
+        sleep(minutes(9)).await;
+        scheduled.await.unwrap().unwrap();
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, true)], "still on after the scheduled end");
+        manual.await.unwrap();
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, true), (PIN, false)]);
+
+        // A manual run ending within a scheduled one leaves the output on until it ends
+        backend.writes.lock().unwrap().clear();
+        let scheduled = tokio::spawn(run_output(
+            state.gpio_tx.clone(),
+            PIN,
+            true,
+            false,
+            Duration::minutes(10),
+        ));
+        sleep(minutes(2)).await;
+        state.run_now(&timer, minutes(3)).await.unwrap();
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, true)]);
+        scheduled.await.unwrap().unwrap();
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, true), (PIN, false)]);
+    }
+
+    #[test]
+    fn durations_are_formatted_for_people() {
+        let secs = std::time::Duration::from_secs;
+        assert_eq!(format_duration(secs(45)), "45 s");
+        assert_eq!(format_duration(secs(600)), "10 min");
+        assert_eq!(format_duration(secs(5400)), "1 h 30 min");
+        assert_eq!(format_duration(secs(7200)), "2 h");
+        assert_eq!(format_duration(secs(3661)), "1 h 1 min 1 s");
+        assert_eq!(format_duration(secs(0)), "0 s");
+        // Fractions of a second are dropped
+        assert_eq!(
+            format_duration(std::time::Duration::from_millis(90_500)),
+            "1 min 30 s"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spawned_timers_are_tracked_until_cancelled() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(8).0);
+        let minute = std::time::Duration::from_secs(60);
+        let a = IntervalTimer::once_daily(None, None, minute, hm(6, 0)).unwrap();
+        let mut b = IntervalTimer::once_daily(None, None, minute, hm(7, 0)).unwrap();
+        let mut ids = vec![a.get_id(), b.get_id()];
+        ids.sort();
+
+        let first = state.spawn_interval_timer(&a).unwrap();
+        state.spawn_interval_timer(&b).unwrap();
+        assert_eq!(state.running_timer_ids(), ids);
+        // Spawning a timer again replaces its task
+        let second = state.spawn_interval_timer(&a).unwrap();
+        assert!(first.await.unwrap_err().is_cancelled());
+        assert_eq!(state.running_timer_ids(), ids);
+
+        assert!(state.cancel_timer(&a.get_id()));
+        assert!(second.await.unwrap_err().is_cancelled());
+        assert_eq!(state.running_timer_ids(), [b.get_id()]);
+        assert!(!state.cancel_timer(&a.get_id()));
+
+        // Spawning a disabled timer stops its task instead
+        b.enabled = false;
+        assert!(state.spawn_interval_timer(&b).is_none());
+        assert!(state.running_timer_ids().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_input_timer_fires_on_each_debounced_rising_edge() {
+        const INPUT: u16 = 23;
+        let (tx, mut rx) = mpsc::channel(8);
+        let level = Arc::new(AtomicBool::new(false));
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let begun = tokio::time::Instant::now();
+        // Stand in for the GPIO manager, reading the input from `level`
+        let manager = {
+            let (level, fired) = (level.clone(), fired.clone());
+            tokio::spawn(async move {
+                while let Some(message) = rx.recv().await {
+                    match message {
+                        GpioMessage::ReadIn { pin: INPUT, resp } => {
+                            let _ = resp.send(level.load(Ordering::SeqCst));
+                        }
+                        GpioMessage::Slot { resp, .. } => {
+                            let _ = resp.send(None);
+                        }
+                        GpioMessage::OnFor { msg, .. } => {
+                            assert_eq!(msg.output, PIN);
+                            fired
+                                .lock()
+                                .unwrap()
+                                .push(begun.elapsed().as_millis() / 1000);
+                        }
+                        GpioMessage::Rest(_) => {}
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+            })
+        };
+        let debounce = std::time::Duration::from_millis(50);
+        let timer = InputTimer::new(
+            INPUT,
+            Edge::Rising,
+            debounce,
+            on(),
+            Duration::seconds(1),
+            tx,
+        )
+        .run();
+        let set = |high, at_ms| {
+            let level = level.clone();
+            async move {
+                tokio::time::sleep_until(begun + std::time::Duration::from_millis(at_ms)).await;
+                level.store(high, Ordering::SeqCst);
+            }
+        };
+
+        // A 20ms glitch is shorter than the debounce
+        set(true, 1000).await;
+        set(false, 1020).await;
+        // Held high, it fires once however long it stays high
+        set(true, 2000).await;
+        set(false, 5000).await;
+        // A falling edge doesn't fire, but the next rising one does
+        set(true, 6000).await;
+        sleep(std::time::Duration::from_secs(3)).await;
+        timer.abort();
+        manager.abort();
+        assert_eq!(*fired.lock().unwrap(), [2, 6]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_off_before_the_cutoff_cancels_it() {
+        let (backend, tx) = capped_manager();
+        let off = GpioOutMessage {
+            value: false,
+            ..on()
+        };
+        let ms = std::time::Duration::from_millis;
+        tx.send(on().into()).await.unwrap();
+        sleep(ms(500)).await;
+        tx.send(off.into()).await.unwrap();
+        sleep(ms(1000)).await;
+        // The first run's cutoff would have been at 1s, so this one runs its own full second
+        tx.send(on().into()).await.unwrap();
+        sleep(ms(900)).await;
+        flush(&tx).await;
+        assert_eq!(backend.writes.lock().unwrap().last(), Some(&(PIN, true)));
+        sleep(ms(200)).await;
+        flush(&tx).await;
+        assert_eq!(
+            *backend.writes.lock().unwrap(),
+            [(PIN, true), (PIN, false), (PIN, true), (PIN, false)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_run_cut_short_by_a_reboot_is_resumed_only_if_asked() {
+        let rebooted_at = Utc.with_ymd_and_hms(2024, 6, 1, 6, 4, 0).unwrap();
+        let minutes = |m: u64| std::time::Duration::from_secs(m * 60);
+        // Before the reboot, output 5 was six minutes into a ten minute run and output 6 was
+        // on for a run which should already have ended
+        let reboot = |resume: bool| async move {
+            let (state, backend) = simulated();
+            let state = state.with_clock(Arc::new(MockClock::new(rebooted_at)));
+            let persisted = state.db.open_tree(OUTPUT_STATE).unwrap();
+            for (output, off_at) in [(PIN, minutes(4)), (PIN + 1, minutes(0))] {
+                let record = PersistedOutput {
+                    msg: GpioOutMessage { output, ..on() },
+                    off_at: Some(rebooted_at + Duration::from_std(off_at).unwrap()),
+                };
+                let bytes = serde_json::to_vec(&record).unwrap();
+                persisted.insert(output.to_be_bytes(), bytes).unwrap();
+            }
+            let resumed = state.restore_outputs(resume).await.unwrap();
+            flush(&state.gpio_tx).await;
+            (state, backend, resumed)
+        };
+
+        let (_, backend, resumed) = reboot(false).await;
+        assert_eq!(resumed, 0);
+        let mut writes = backend.writes.lock().unwrap().clone();
+        writes.sort();
+        assert_eq!(writes, [(PIN, false), (PIN + 1, false)]);
+
+        let (state, backend, resumed) = reboot(true).await;
+        assert_eq!(resumed, 1);
+        let writes = || {
+            let mut writes = backend.writes.lock().unwrap().clone();
+            writes.sort();
+            writes
+        };
+        assert_eq!(writes(), [(PIN, true), (PIN + 1, false)]);
+        sleep(minutes(4) - std::time::Duration::from_secs(1)).await;
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, true), (PIN + 1, false)]);
+        sleep(std::time::Duration::from_secs(2)).await;
+        flush(&state.gpio_tx).await;
+        assert_eq!(writes(), [(PIN, false), (PIN, true), (PIN + 1, false)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_normally_on_timer_turns_its_output_off_for_the_window() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 0, 0).unwrap());
+        let state = AppState::new(Arc::new(db), tx).with_clock(Arc::new(clock));
+        // What's sent to the output, letting runs start as soon as they ask
+        let sent = Arc::new(Mutex::new(vec![]));
+        let manager = sent.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let noted = match msg {
+                    GpioMessage::Slot { resp, .. } => {
+                        let _ = resp.send(None);
+                        continue;
+                    }
+                    GpioMessage::Rest(msg) => ("rest", msg.value),
+                    GpioMessage::OnFor { msg, .. } => ("window", msg.value),
+                    other => panic!("unexpected {:?}", other),
+                };
+                manager.lock().unwrap().push(noted);
+            }
+        });
+        let hour = std::time::Duration::from_secs(60 * 60);
+
+        for (normally_on, expected) in [
+            (false, vec![("window", true), ("rest", false)]),
+            (
+                true,
+                vec![("rest", true), ("window", false), ("rest", true)],
+            ),
+        ] {
+            let mut timer = IntervalTimer::once_daily(None, None, hour, hm(6, 0)).unwrap();
+            timer.set_timezone(Some(chrono_tz::UTC));
+            timer.output = PIN;
+            timer.normally_on = normally_on;
+            state.spawn_interval_timer(&timer).unwrap();
+            // From 05:00 until after the 06:00 to 07:00 window
+            sleep(3 * hour).await;
+            let sent = std::mem::take(&mut *sent.lock().unwrap());
+            assert_eq!(sent, expected, "normally on: {}", normally_on);
+            state.cancel_timer(&timer.get_id());
+            sleep(21 * hour).await;
+        }
+    }
+
+    #[test]
+    fn a_file_given_as_the_database_is_refused_with_a_hint() {
+        let dir = std::env::temp_dir().join(format!("sploosh-db-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let file = dir.join("timers.json");
+        std::fs::write(&file, "{}").unwrap();
+
+        let err = open_db(&file).unwrap_err();
+        assert!(matches!(&err, Error::DbNotDirectory(path) if *path == file));
+        assert!(err.to_string().contains("is not a directory"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "{}");
+        // Nor can the file be a parent of the database
+        let err = open_db(&file.join("db")).unwrap_err();
+        assert!(matches!(&err, Error::DbNotDirectory(path) if *path == file));
+
+        // Missing parents are created
+        let nested = dir.join("a").join("b").join("db");
+        drop(open_db(&nested).unwrap());
+        assert!(nested.is_dir());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn each_scheduled_run_is_counted_on_the_stored_timer() {
+        let (state, _backend) = simulated();
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 0, 0).unwrap());
+        let state = state.with_clock(Arc::new(clock));
+        let mut timer =
+            IntervalTimer::once_daily(None, None, std::time::Duration::from_secs(60), hm(6, 0))
+                .unwrap();
+        timer.set_timezone(Some(chrono_tz::UTC));
+        timer.output = PIN;
+        state.insert_interval_timer(&timer).unwrap();
+        state.spawn_interval_timer(&timer).unwrap();
+
+        sleep(std::time::Duration::from_secs(3 * 24 * 60 * 60)).await;
+        let stored = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(stored.fire_count, 3);
+        let last = stored.last_fired_at.unwrap().with_timezone(&Utc);
+        assert_eq!(last, Utc.with_ymd_and_hms(2024, 6, 3, 6, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn concurrent_runs_and_edits_lose_no_counts() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), mpsc::channel(1).0);
+        let timer = IntervalTimer::new(None, None, crate::IntervalSettings::default());
+        state.insert_interval_timer(&timer).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        state.record_fire(timer.get_id()).unwrap();
+                    }
+                });
+            }
+            // Edits made from a copy read before the runs don't set the count back
+            scope.spawn(|| {
+                for n in 0..50 {
+                    let mut edited = timer.clone();
+                    edited.name = Some(format!("zone {}", n));
+                    state.insert_interval_timer(&edited).unwrap();
+                }
+            });
+        });
+        let stored = state.get_interval_timer(timer.get_id()).unwrap().unwrap();
+        assert_eq!(stored.fire_count, 150);
+        assert_eq!(stored.name.as_deref(), Some("zone 49"));
+        assert_eq!(state.record_fire(Uuid::new_v4()).unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_window_spanning_midnight_ends_the_next_morning() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 23, 0, 0).unwrap());
+        let _task = DailyTimer::new(hm(23, 30), on(), Duration::minutes(60), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_clock(Arc::new(clock))
+            .run();
+        let sent = Arc::new(Mutex::new(vec![]));
+        let manager = sent.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let value = match msg {
+                    GpioMessage::Slot { resp, .. } => {
+                        let _ = resp.send(None);
+                        continue;
+                    }
+                    GpioMessage::OnFor { msg, .. } | GpioMessage::Rest(msg) => msg.value,
+                    other => panic!("unexpected {:?}", other),
+                };
+                let at = clock.now();
+                manager
+                    .lock()
+                    .unwrap()
+                    .push((at.day(), at.hour(), at.minute(), value));
+            }
+        });
+
+        sleep(std::time::Duration::from_secs(25 * 60 * 60)).await;
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [(1, 23, 30, true), (2, 0, 30, false), (2, 23, 30, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn booting_spawns_every_enabled_timer_which_can_run() {
+        let (state, _backend) = simulated();
+        let minute = std::time::Duration::from_secs(60);
+        let daily = IntervalTimer::once_daily(None, None, minute, hm(6, 0)).unwrap();
+        let cycling = IntervalTimer::cycling(None, None, minute, minute).unwrap();
+        let mut disabled = IntervalTimer::once_daily(None, None, minute, hm(7, 0)).unwrap();
+        disabled.enabled = false;
+        // A daily timer stored without a start time is skipped rather than failing the boot
+        let unset = crate::IntervalSettings::new(minute, std::time::Duration::ZERO, None);
+        let unset = IntervalTimer::new(None, None, unset);
+        for timer in [&daily, &cycling, &disabled, &unset] {
+            state.insert_interval_timer(timer).unwrap();
+        }
+
+        let handles = state.spawn_all_timers().unwrap();
+        assert_eq!(handles.len(), 2);
+        let mut expected = vec![daily.get_id(), cycling.get_id()];
+        expected.sort();
+        assert_eq!(state.running_timer_ids(), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_weekly_timer_runs_only_on_its_days() {
+        let (tx, mut rx) = mpsc::channel(8);
+        // Saturday the 1st
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 6, 1, 5, 0, 0).unwrap());
+        let days = vec![Weekday::Mon, Weekday::Thu];
+        let _task = DailyTimer::weekly(hm(6, 0), days, on(), Duration::minutes(1), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_clock(Arc::new(clock))
+            .run();
+        let ran_on = Arc::new(Mutex::new(vec![]));
+        let manager = ran_on.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                match msg {
+                    GpioMessage::Slot { resp, .. } => drop(resp.send(None)),
+                    GpioMessage::OnFor { .. } => manager.lock().unwrap().push(clock.now().day()),
+                    _ => {}
+                }
+            }
+        });
+
+        sleep(std::time::Duration::from_secs(8 * 24 * 60 * 60)).await;
+        assert_eq!(*ran_on.lock().unwrap(), [3, 6]);
+
+        // The days are kept through storage
+        let days = vec![Weekday::Thu, Weekday::Mon];
+        let minute = std::time::Duration::from_secs(60);
+        let timer = IntervalTimer::weekly(None, None, days, minute, hm(6, 0)).unwrap();
+        let stored = IntervalTimer::from_json_slice(timer.to_json_vec().unwrap()).unwrap();
+        let expected = crate::Schedule::Weekly(vec![Weekday::Mon, Weekday::Thu]);
+        assert_eq!(stored.settings().schedule(), &expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_periodic_timer_pulses_for_its_duty_cycle() {
+        let seconds = Duration::seconds;
+        for duty in [-0.1, 1.5, f32::NAN] {
+            let err = Periodic::from_duty(duty, seconds(4)).unwrap_err();
+            assert!(matches!(err, Error::InvalidDuty(_)), "{}", duty);
+        }
+        let err = Periodic::new(seconds(5), 0.5, seconds(4)).unwrap_err();
+        assert!(matches!(err, Error::InvalidDuration));
+        let err = Periodic::from_pulse_width(seconds(5), seconds(4)).unwrap_err();
+        assert!(matches!(err, Error::InvalidDuty(_)));
+        let err = Periodic::from_duty(0.5, Duration::zero()).unwrap_err();
+        assert!(matches!(err, Error::InvalidDuration));
+        let by_width = Periodic::from_pulse_width(seconds(1), seconds(4)).unwrap();
+        assert_eq!(by_width.duty(), 0.25);
+
+        let (state, backend) = simulated();
+        let quarter = Periodic::from_duty(0.25, seconds(4)).unwrap();
+        assert_eq!(quarter.pulse_width(), seconds(1));
+        let task = quarter.run(state.gpio_tx.clone(), PIN);
+        sleep(std::time::Duration::from_millis(8500)).await;
+        task.abort();
+        flush(&state.gpio_tx).await;
+        let values: Vec<_> = backend.writes.lock().unwrap().iter().map(|w| w.1).collect();
+        assert_eq!(values, [true, false, true, false, true]);
+    }
+
+    /// The next message turning a timer's output on for a run, with the run's duration, or
+    /// back off after it, granting any output slot asked for on the way
+    async fn next_run_message(
+        rx: &mut mpsc::Receiver<GpioMessage>,
+    ) -> (GpioOutMessage, Option<Duration>) {
+        loop {
+            match rx.recv().await.unwrap() {
+                GpioMessage::Slot { resp, .. } => drop(resp.send(None)),
+                GpioMessage::OnFor { msg, duration, .. } => break (msg, Some(duration)),
+                GpioMessage::Rest(msg) => break (msg, None),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_daily_window_schedules_its_output_on_then_off() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let start = (Local::now() + Duration::minutes(2)).time();
+        let handle = Daily::new(start, Duration::minutes(1)).schedule(on(), tx);
+        let minute = std::time::Duration::from_secs(60);
+
+        let begun = tokio::time::Instant::now();
+        let (msg, duration) = next_run_message(&mut rx).await;
+        assert!(msg.value && duration == Some(Duration::minutes(1)));
+        let waited = begun.elapsed();
+        assert!(waited > minute && waited <= 2 * minute, "{:?}", waited);
+        let (msg, duration) = next_run_message(&mut rx).await;
+        assert!(!msg.value && duration.is_none());
+        assert_eq!(begun.elapsed() - waited, minute);
+        assert!(!handle.is_finished());
+        handle.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cron_and_sun_timers_read_the_time_from_their_clock() {
+        use std::str::FromStr;
+
+        // 30 s before the cron timer's 06:00 UTC, whatever the system clock says
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 5, 59, 30).unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let cron = Cron::from_str("0 6 * * *").unwrap();
+        let handle = CronTimer::new(cron, on(), Duration::minutes(1), tx)
+            .with_timezone(Some(chrono_tz::UTC))
+            .with_clock(Arc::new(MockClock::new(now)))
+            .run();
+        let begun = tokio::time::Instant::now();
+        let (msg, duration) = next_run_message(&mut rx).await;
+        assert!(msg.value && duration == Some(Duration::minutes(1)));
+        assert_eq!(begun.elapsed().as_secs(), 30);
+        let (msg, _) = next_run_message(&mut rx).await;
+        assert!(!msg.value);
+        assert_eq!(begun.elapsed().as_secs(), 90);
+        handle.abort();
+
+        // Sunrise at the equator on the prime meridian is a little after 06:00 UTC
+        let (tx, mut rx) = mpsc::channel(8);
+        let offset = Duration::zero();
+        let sunrise = sun::next_event(
+            now.with_timezone(&Local),
+            SunEvent::Sunrise,
+            0.0,
+            0.0,
+            offset,
+        );
+        let handle = SunTimer::new(
+            SunEvent::Sunrise,
+            0.0,
+            0.0,
+            offset,
+            on(),
+            Duration::minutes(1),
+            tx,
+        )
+        .with_clock(Arc::new(MockClock::new(now)))
+        .run();
+        let begun = tokio::time::Instant::now();
+        let (msg, _) = next_run_message(&mut rx).await;
+        assert!(msg.value);
+        let waited = (sunrise.unwrap().with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap();
+        assert_eq!(begun.elapsed().as_secs(), waited.as_secs());
+        handle.abort();
+    }
+}
@@ -0,0 +1,390 @@
+//! Output changes and failed writes POSTed as JSON to configured URLs, e.g. to get notified
+//! through home automation when a zone runs, and the skip check asked before each scheduled
+//! run, e.g. to skip watering after rain. Only plain `http://` URLs are supported, which is
+//! enough for a hub on the local network without pulling in an HTTP client and TLS stack.
+use crate::{util::OutputEvent, Error};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::str::FromStr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// How long a webhook may take to accept an event before it is given up on
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long a run waits for the skip check to answer before going ahead
+const SKIP_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A URL to POST events to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for Webhook {
+    type Err = Error;
+
+    /// Parse a URL of the form `http://host[:port][/path]`
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::Anyhow(anyhow::anyhow!("Webhook {}: {}", url, reason));
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid("invalid port"))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        Ok(Webhook {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// The JSON body sent for each event
+#[derive(Debug, Serialize)]
+struct WebhookEvent {
+    /// `on`, `off` or `write_failed`
+    event: &'static str,
+    output: u16,
+    /// The value which was being written when a write failed
+    value: bool,
+    at: DateTime<Local>,
+}
+
+impl From<OutputEvent> for WebhookEvent {
+    fn from(event: OutputEvent) -> Self {
+        WebhookEvent {
+            event: match (event.ok, event.value) {
+                (false, _) => "write_failed",
+                (true, true) => "on",
+                (true, false) => "off",
+            },
+            output: event.output,
+            value: event.value,
+            at: event.at,
+        }
+    }
+}
+
+/// The JSON body sent to the skip check before a scheduled run
+#[derive(Debug, Clone, Serialize)]
+pub struct SkipQuery {
+    pub timer_id: Uuid,
+    pub name: Option<String>,
+    pub output: u16,
+}
+
+/// Undo chunked transfer encoding, giving up on malformed chunks
+fn dechunk(mut body: &str) -> String {
+    let mut out = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size =
+            match usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16) {
+                Ok(size) if size > 0 && size <= rest.len() => size,
+                _ => break,
+            };
+        out.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    out
+}
+
+impl Webhook {
+    /// POST a JSON body, returning the response's status code and body
+    async fn post(&self, body: &[u8]) -> Result<(u16, String), Error> {
+        let io = |e: std::io::Error| Error::Anyhow(e.into());
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(io)?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: sploosh/{}\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            env!("CARGO_PKG_VERSION"),
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.map_err(io)?;
+        stream.write_all(body).await.map_err(io)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.map_err(io)?;
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| Error::Anyhow(anyhow::anyhow!("Invalid response {:?}", head)))?;
+        let chunked = head.lines().any(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("transfer-encoding: chunked")
+        });
+        let body = match chunked {
+            true => dechunk(body),
+            false => body.to_owned(),
+        };
+        Ok((status, body))
+    }
+
+    /// Ask whether a scheduled run should be skipped, e.g. because it has rained. The check
+    /// skips the run by answering 2xx with a body of `skip` or `{"skip": true}`. Anything else,
+    /// including failing or taking longer than `SKIP_CHECK_TIMEOUT`, lets the run go ahead.
+    pub async fn should_skip(self, query: SkipQuery) -> bool {
+        let url = format!("http://{}:{}{}", self.host, self.port, self.path);
+        let body = match serde_json::to_vec(&query) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("{}", e);
+                return false;
+            }
+        };
+        match tokio::time::timeout(SKIP_CHECK_TIMEOUT, self.post(&body)).await {
+            Ok(Ok((status, body))) if (200..300).contains(&status) => {
+                let body = body.trim();
+                body.eq_ignore_ascii_case("skip")
+                    || serde_json::from_str::<serde_json::Value>(body)
+                        .is_ok_and(|answer| answer["skip"] == true)
+            }
+            Ok(Ok((status, _))) => {
+                warn!(
+                    "Skip check {} responded with status {}, running anyway",
+                    url, status
+                );
+                false
+            }
+            Ok(Err(e)) => {
+                warn!("Skip check {} failed, running anyway: {}", url, e);
+                false
+            }
+            Err(_) => {
+                warn!(
+                    "Skip check {} timed out after {:?}, running anyway",
+                    url, SKIP_CHECK_TIMEOUT
+                );
+                false
+            }
+        }
+    }
+
+    /// POST a JSON body, logging rather than returning any failure
+    async fn notify(self, body: Vec<u8>) {
+        let url = format!("http://{}:{}{}", self.host, self.port, self.path);
+        match tokio::time::timeout(WEBHOOK_TIMEOUT, self.post(&body)).await {
+            Ok(Ok((status, _))) if (200..300).contains(&status) => {
+                debug!("Webhook {} accepted the event", url)
+            }
+            Ok(Ok((status, _))) => warn!("Webhook {} responded with status {}", url, status),
+            Ok(Err(e)) => warn!("Failed to send an event to webhook {}: {}", url, e),
+            Err(_) => warn!("Webhook {} timed out after {:?}", url, WEBHOOK_TIMEOUT),
+        }
+    }
+}
+
+/// Spawn a task sending every output event to each webhook. Each event is sent from its own
+/// task, so a slow or unreachable webhook never holds up the others or the GPIO manager.
+pub fn spawn_webhooks(
+    webhooks: Vec<Webhook>,
+    mut events: broadcast::Receiver<OutputEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Webhooks missed {} output events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let body = match serde_json::to_vec(&WebhookEvent::from(event)) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+            for webhook in &webhooks {
+                tokio::spawn(webhook.clone().notify(body.clone()));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::AppState;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    /// A server on a free local port answering every request with `response`, and the
+    /// requests it receives
+    async fn mock_server(response: &'static str) -> (Webhook, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                // Read the head, then as much body as it says there is
+                let complete = |request: &[u8]| {
+                    let text = String::from_utf8_lossy(request);
+                    let (head, body) = text.split_once("\r\n\r\n")?;
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))?
+                        .parse()
+                        .ok()?;
+                    (body.len() >= length).then(|| text.to_string())
+                };
+                let text = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(text) = complete(&request) {
+                        break text;
+                    }
+                };
+                let _ = tx.send(text);
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (url.parse().unwrap(), rx)
+    }
+
+    #[test]
+    fn only_plain_http_urls_are_accepted() {
+        let hook: Webhook = "http://hub.local:8123/api/sploosh".parse().unwrap();
+        assert_eq!((hook.host.as_str(), hook.port), ("hub.local", 8123));
+        assert_eq!(hook.path, "/api/sploosh");
+        let hook: Webhook = "http://hub.local".parse().unwrap();
+        assert_eq!((hook.port, hook.path.as_str()), (80, "/"));
+        for url in [
+            "https://hub.local/",
+            "http://:80/",
+            "http://hub.local:port/",
+        ] {
+            assert!(url.parse::<Webhook>().is_err(), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn each_output_event_is_posted_as_json() {
+        let (hook, mut requests) = mock_server("").await;
+        let (events, _) = broadcast::channel(8);
+        spawn_webhooks(vec![hook], events.subscribe());
+
+        for (value, ok) in [(true, true), (false, false)] {
+            let at = Local::now();
+            let event = OutputEvent {
+                output: 17,
+                value,
+                at,
+                ok,
+            };
+            events.send(event).unwrap();
+            let request = requests.recv().await.unwrap();
+            assert!(
+                request.starts_with("POST /hook HTTP/1.1\r\n"),
+                "{}",
+                request
+            );
+            assert!(request.contains("Content-Type: application/json\r\n"));
+            let body = request.split_once("\r\n\r\n").unwrap().1;
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            let expected = if ok { "on" } else { "write_failed" };
+            assert_eq!(body["event"], expected);
+            assert_eq!(
+                (&body["output"], &body["value"]),
+                (&17.into(), &value.into())
+            );
+            assert_eq!(
+                body["at"]
+                    .as_str()
+                    .unwrap()
+                    .parse::<DateTime<Local>>()
+                    .unwrap(),
+                at
+            );
+        }
+    }
+
+    /// Whether a daily timer due half a second from now turns its output on, asking the skip
+    /// check at `hook` first, along with the question asked and the timer's state and id
+    async fn runs_when_asked(
+        hook: Webhook,
+        requests: &mut mpsc::UnboundedReceiver<String>,
+    ) -> (bool, serde_json::Value, AppState, Uuid) {
+        use crate::util::{GpioMessage, MockClock};
+        use chrono::{NaiveTime, TimeZone, Utc};
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let due = Utc.with_ymd_and_hms(2024, 6, 1, 6, 0, 0).unwrap();
+        let clock = MockClock::new(due - chrono::Duration::milliseconds(500));
+        let state = AppState::new(std::sync::Arc::new(db), tx)
+            .with_clock(std::sync::Arc::new(clock))
+            .with_skip_check(Some(hook));
+        let six = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let minute = std::time::Duration::from_secs(60);
+        let mut timer =
+            crate::IntervalTimer::once_daily(Some("lawn".to_owned()), None, minute, six).unwrap();
+        timer.set_timezone(Some(chrono_tz::UTC));
+        state.insert_interval_timer(&timer).unwrap();
+        state.spawn_interval_timer(&timer).unwrap();
+
+        let request = requests.recv().await.unwrap();
+        let body = serde_json::from_str(request.split_once("\r\n\r\n").unwrap().1).unwrap();
+        let wait = std::time::Duration::from_millis(500);
+        let fired = loop {
+            match tokio::time::timeout(wait, rx.recv()).await {
+                Ok(Some(GpioMessage::Slot { resp, .. })) => drop(resp.send(None)),
+                Ok(Some(GpioMessage::OnFor { .. })) => break true,
+                Ok(other) => panic!("unexpected {:?}", other),
+                Err(_) => break false,
+            }
+        };
+        state.cancel_timer(&timer.get_id());
+        (fired, body, state, timer.get_id())
+    }
+
+    #[tokio::test]
+    async fn the_skip_check_can_skip_a_run() {
+        let (hook, mut requests) = mock_server("skip").await;
+        let (fired, asked, state, id) = runs_when_asked(hook, &mut requests).await;
+        assert!(!fired);
+        assert_eq!(asked["timer_id"], id.to_string());
+        assert_eq!(asked["name"], "lawn");
+        let history = state.timer_history(id, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].outcome, crate::history::FireOutcome::Skipped);
+        assert_eq!(state.get_interval_timer(id).unwrap().unwrap().fire_count, 0);
+
+        for answer in [r#"{"skip": false}"#, "rain expected"] {
+            let (hook, mut requests) = mock_server(answer).await;
+            let (fired, _, _, _) = runs_when_asked(hook, &mut requests).await;
+            assert!(fired, "{}", answer);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Output changes streamed over a websocket, so that a dashboard can show which zones are on
+//! without polling
+use crate::util::{AppState, OutputEvent};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+/// Upgrade to a websocket which receives each successful output write as a JSON text frame
+pub async fn output_events(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    // Subscribe before upgrading so that no events are missed while the handshake completes
+    let events = state.output_events.subscribe();
+    ws.on_upgrade(move |socket| stream_events(socket, events))
+}
+
+/// Forward events to the client until it disconnects
+async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<OutputEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) if !event.ok => continue,
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Websocket client missed {} output events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        error!("{}", e);
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            // Incoming messages are ignored, but the stream ending means the client has gone
+            msg = socket.recv() => {
+                if !matches!(msg, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pins::MockBackend,
+        util::{GpioManager, GpioOutMessage},
+    };
+    use futures_util::StreamExt;
+    use std::sync::Arc;
+
+    /// Serve the websocket on a free local port, returning its URL
+    async fn serve(state: AppState) -> String {
+        let app = axum::Router::new()
+            .route("/ws", axum::routing::get(output_events))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn each_client_receives_every_write() {
+        let (man, tx) = GpioManager::with_backend(Box::new(MockBackend::new()), 8).unwrap();
+        let events = man.output_events();
+        man.run().unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let state = AppState::new(Arc::new(db), tx.clone()).with_output_events(events);
+        let url = serve(state).await;
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        let msg = GpioOutMessage {
+            output: 6,
+            value: true,
+            active_low: false,
+        };
+        tx.send(msg.into()).await.unwrap();
+        for client in [&mut first, &mut second] {
+            let frame = client.next().await.unwrap().unwrap();
+            let event: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(event["output"], 6);
+            assert_eq!(event["value"], true);
+            assert!(event["at"].is_string());
+        }
+    }
+}